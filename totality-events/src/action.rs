@@ -0,0 +1,690 @@
+//! # Action
+//!
+//! Maps physical inputs to named actions, so that apps react to "move" or "jump" instead of to
+//! W or the space bar, and players can rebind them.
+//!
+//! Actions are digital, or 1D or 2D axes put together from keys, the scroll wheel or mouse
+//! movement. Bindings are grouped into contexts such as gameplay, menu or editor, which are pushed
+//! onto and popped off a stack. An input bound in a context shadows it in every context below, so
+//! a menu binding W keeps gameplay from seeing it until the menu is popped.
+//!
+//! Bindings are read from and written to a line-based config, one binding per line under the
+//! context it belongs to:
+//!
+//! ```text
+//! # Comments start with a hash.
+//! [gameplay]
+//! jump = key space
+//! fire = key lctrl
+//! zoom = keys s w
+//! move = keys a d s w
+//! look = mouse 0.01
+//! [menu]
+//! back = key esc
+//! ```
+//!
+//! `key` binds a digital action, `keys` with two keys (negative, positive) or `scroll` a 1D axis,
+//! and `keys` with four keys (left, right, down, up) or `mouse` a 2D axis. Keys are named by what
+//! the layout prints on them, or by scancode as in `scan17`, to stay in place across layouts.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use na::Vector2;
+
+use crate::{
+    cb::{CBFn, Categorized, Propagation},
+    hal::{
+        self, a, b,
+        b::{Key, Side},
+        p,
+    },
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ActionKind {
+    Digital,
+    Axis1D,
+    Axis2D,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ActionValue {
+    Digital(bool),
+    Axis1D(f32),
+    Axis2D(Vector2<f32>),
+}
+impl ActionValue {
+    fn zero(kind: ActionKind) -> ActionValue {
+        match kind {
+            ActionKind::Digital => ActionValue::Digital(false),
+            ActionKind::Axis1D => ActionValue::Axis1D(0.),
+            ActionKind::Axis2D => ActionValue::Axis2D(Vector2::zeros()),
+        }
+    }
+}
+
+/// What triggers an action.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Binding {
+    /// Down while the key is.
+    Key(b::C),
+    /// -1 while `neg` is down, 1 while `pos` is.
+    Keys1D { neg: b::C, pos: b::C },
+    /// A unit square of directions from four keys.
+    Keys2D {
+        left: b::C,
+        right: b::C,
+        down: b::C,
+        up: b::C,
+    },
+    /// Each scroll, scaled.
+    Scroll(f32),
+    /// Each mouse movement, scaled.
+    Mouse(f32),
+}
+impl Binding {
+    pub fn kind(&self) -> ActionKind {
+        match self {
+            Binding::Key(_) => ActionKind::Digital,
+            Binding::Keys1D { .. } | Binding::Scroll(_) => ActionKind::Axis1D,
+            Binding::Keys2D { .. } | Binding::Mouse(_) => ActionKind::Axis2D,
+        }
+    }
+    fn inputs(&self) -> Vec<hal::C> {
+        match *self {
+            Binding::Key(c) => vec![c.into()],
+            Binding::Keys1D { neg, pos } => vec![neg.into(), pos.into()],
+            Binding::Keys2D {
+                left,
+                right,
+                down,
+                up,
+            } => vec![left.into(), right.into(), down.into(), up.into()],
+            Binding::Scroll(_) => vec![a::C::Scroll.into()],
+            Binding::Mouse(_) => vec![p::C::MouseDelta.into()],
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ActionError {
+    /// An action was bound as one kind, then as another.
+    KindMismatch {
+        action: String,
+        bound: ActionKind,
+        binding: ActionKind,
+    },
+    /// A line of a config could not be read.
+    Parse { line: usize, reason: String },
+}
+impl fmt::Display for ActionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ActionError::KindMismatch {
+                action,
+                bound,
+                binding,
+            } => write!(
+                f,
+                "{} is bound as {:?}, and cannot also be bound as {:?}.",
+                action, bound, binding
+            ),
+            ActionError::Parse { line, reason } => write!(f, "Line {}: {}", line, reason),
+        }
+    }
+}
+impl std::error::Error for ActionError {}
+
+/// Key names in configs, besides single characters.
+const KEY_NAMES: &[(&str, Key)] = &[
+    ("esc", Key::Esc),
+    ("lalt", Key::Alt(Side::L)),
+    ("ralt", Key::Alt(Side::R)),
+    ("lshift", Key::Shift(Side::L)),
+    ("rshift", Key::Shift(Side::R)),
+    ("lctrl", Key::Ctrl(Side::L)),
+    ("rctrl", Key::Ctrl(Side::R)),
+    ("lmod", Key::Mod(Side::L)),
+    ("rmod", Key::Mod(Side::R)),
+    ("tab", Key::Tab),
+    ("home", Key::Home),
+    ("end", Key::End),
+    ("pgdn", Key::PgDn),
+    ("pgup", Key::PgUp),
+    ("ins", Key::Ins),
+    ("del", Key::Del),
+    ("enter", Key::Enter),
+    ("backspace", Key::Backspace),
+    ("up", Key::Up),
+    ("left", Key::Left),
+    ("down", Key::Down),
+    ("right", Key::Right),
+    ("numlk", Key::NumLk),
+    ("scrlk", Key::ScrLk),
+    ("caplk", Key::CapLk),
+    ("printscreen", Key::PrintScreen),
+    ("pause", Key::Pause),
+];
+
+/// Reads a key written by `key_name`.
+pub(crate) fn parse_key(name: &str) -> Result<b::C, String> {
+    let mut chars = name.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => return Ok(b::C::A(c)),
+        _ if name == "space" => return Ok(b::C::A(' ')),
+        _ => (),
+    }
+    if let Some(code) = name.strip_prefix("u+") {
+        return u32::from_str_radix(code, 16)
+            .ok()
+            .and_then(char::from_u32)
+            .map(b::C::A)
+            .ok_or_else(|| format!("{} is not a character.", name));
+    }
+    if let Some(n) = name.strip_prefix('f').and_then(|n| n.parse().ok()) {
+        return Ok(Key::F(n).into());
+    }
+    if let Some(code) = name.strip_prefix("scan").and_then(|n| n.parse().ok()) {
+        return Ok(b::C::P(code));
+    }
+    KEY_NAMES
+        .iter()
+        .find(|(key_name, _)| *key_name == name)
+        .map(|(_, key)| (*key).into())
+        .ok_or_else(|| format!("Unknown key {}.", name))
+}
+
+/// Names a key in a single word. Characters that can't be seen, like tabs, are written as their
+/// code point.
+pub(crate) fn key_name(c: &b::C) -> Option<String> {
+    match c {
+        b::C::A(' ') => Some("space".to_string()),
+        b::C::A(c) if c.is_whitespace() || c.is_control() => Some(format!("u+{:04x}", *c as u32)),
+        b::C::A(c) => Some(c.to_string()),
+        b::C::S(Key::F(n)) => Some(format!("f{}", n)),
+        b::C::S(key) => KEY_NAMES
+            .iter()
+            .find(|(_, k)| k == key)
+            .map(|(name, _)| name.to_string()),
+        b::C::P(code) => Some(format!("scan{}", code)),
+        b::C::F(_) | b::C::Ignored => None,
+    }
+}
+
+impl fmt::Display for Binding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = |c: &b::C| key_name(c).unwrap_or_else(|| format!("{:?}", c));
+        match self {
+            Binding::Key(c) => write!(f, "key {}", name(c)),
+            Binding::Keys1D { neg, pos } => write!(f, "keys {} {}", name(neg), name(pos)),
+            Binding::Keys2D {
+                left,
+                right,
+                down,
+                up,
+            } => write!(
+                f,
+                "keys {} {} {} {}",
+                name(left),
+                name(right),
+                name(down),
+                name(up)
+            ),
+            Binding::Scroll(scale) => write!(f, "scroll {}", scale),
+            Binding::Mouse(scale) => write!(f, "mouse {}", scale),
+        }
+    }
+}
+impl std::str::FromStr for Binding {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Binding, String> {
+        let words = s.split_whitespace().collect::<Vec<_>>();
+        let scale = |w: &str| {
+            w.parse::<f32>()
+                .map_err(|_| format!("{} is not a scale.", w))
+        };
+        match words.as_slice() {
+            ["key", k] => Ok(Binding::Key(parse_key(k)?)),
+            ["keys", neg, pos] => Ok(Binding::Keys1D {
+                neg: parse_key(neg)?,
+                pos: parse_key(pos)?,
+            }),
+            ["keys", left, right, down, up] => Ok(Binding::Keys2D {
+                left: parse_key(left)?,
+                right: parse_key(right)?,
+                down: parse_key(down)?,
+                up: parse_key(up)?,
+            }),
+            ["scroll", s] => Ok(Binding::Scroll(scale(s)?)),
+            ["mouse", s] => Ok(Binding::Mouse(scale(s)?)),
+            _ => Err(format!("Cannot make a binding out of {}.", s)),
+        }
+    }
+}
+
+type Subscriber = Box<dyn FnMut(&ActionValue) + Send>;
+
+/// Actions, their bindings and the contexts they are in.
+pub struct ActionMap {
+    kinds: HashMap<String, ActionKind>,
+    /// Contexts in the order they were first bound in, each with actions in binding order.
+    contexts: Vec<(String, Vec<(String, Binding)>)>,
+    /// Active contexts, the last one on top.
+    stack: Vec<String>,
+    held: HashSet<b::C>,
+    values: HashMap<String, ActionValue>,
+    subscribers: Vec<(String, Subscriber)>,
+}
+impl ActionMap {
+    pub fn new() -> ActionMap {
+        ActionMap {
+            kinds: HashMap::new(),
+            contexts: vec![],
+            stack: vec![],
+            held: HashSet::new(),
+            values: HashMap::new(),
+            subscribers: vec![],
+        }
+    }
+    /// Adds a binding to `action` in `context`. Every binding of an action has to be of the same
+    /// kind.
+    pub fn bind(
+        &mut self,
+        context: &str,
+        action: &str,
+        binding: Binding,
+    ) -> Result<(), ActionError> {
+        match self.kinds.get(action) {
+            Some(&bound) if bound != binding.kind() => {
+                return Err(ActionError::KindMismatch {
+                    action: action.to_string(),
+                    bound,
+                    binding: binding.kind(),
+                })
+            }
+            Some(_) => (),
+            None => {
+                self.kinds.insert(action.to_string(), binding.kind());
+            }
+        }
+        let idx = match self.contexts.iter().position(|(name, _)| name == context) {
+            Some(idx) => idx,
+            None => {
+                self.contexts.push((context.to_string(), vec![]));
+                self.contexts.len() - 1
+            }
+        };
+        self.contexts[idx].1.push((action.to_string(), binding));
+        Ok(())
+    }
+    /// Removes every binding of `action` in `context`. The action stays known, with its kind.
+    pub fn unbind(&mut self, context: &str, action: &str) {
+        for (name, bindings) in self.contexts.iter_mut() {
+            if name == context {
+                bindings.retain(|(a, _)| a != action);
+            }
+        }
+    }
+    pub fn bindings(&self, context: &str, action: &str) -> Vec<Binding> {
+        self.contexts
+            .iter()
+            .filter(|(name, _)| name == context)
+            .flat_map(|(_, bindings)| bindings.iter())
+            .filter(|(a, _)| a == action)
+            .map(|(_, binding)| *binding)
+            .collect()
+    }
+    pub fn kind(&self, action: &str) -> Option<ActionKind> {
+        self.kinds.get(action).copied()
+    }
+    /// Makes `context` the topmost one.
+    pub fn push(&mut self, context: &str) {
+        self.stack.push(context.to_string());
+        self.update(None);
+    }
+    /// Deactivates the topmost context.
+    pub fn pop(&mut self) -> Option<String> {
+        let popped = self.stack.pop();
+        self.update(None);
+        popped
+    }
+    /// Active contexts, from the bottom up.
+    pub fn active(&self) -> &[String] {
+        &self.stack
+    }
+    /// The action's current value. Actions bound to the scroll wheel or the mouse only have a
+    /// value in the moment, and are zero otherwise.
+    pub fn value(&self, action: &str) -> Option<ActionValue> {
+        let kind = self.kind(action)?;
+        Some(
+            self.values
+                .get(action)
+                .copied()
+                .unwrap_or_else(|| ActionValue::zero(kind)),
+        )
+    }
+    /// Calls `f` with the action's value whenever it changes, and with every scroll or mouse
+    /// movement it is bound to.
+    pub fn subscribe(&mut self, action: &str, f: impl FnMut(&ActionValue) + Send + 'static) {
+        self.subscribers.push((action.to_string(), Box::new(f)));
+    }
+    /// Every category bound in any context, for registering `callback` with.
+    pub fn categories(&self) -> Vec<hal::C> {
+        let mut cc = vec![];
+        for (_, bindings) in self.contexts.iter() {
+            for c in bindings.iter().flat_map(|(_, binding)| binding.inputs()) {
+                if !cc.contains(&c) {
+                    cc.push(c);
+                }
+            }
+        }
+        cc
+    }
+    /// The topmost active context binding `c`.
+    fn owner(&self, c: &hal::C) -> Option<&str> {
+        self.stack.iter().rev().map(String::as_str).find(|context| {
+            self.contexts.iter().any(|(name, bindings)| {
+                name == context
+                    && bindings
+                        .iter()
+                        .any(|(_, binding)| binding.inputs().contains(c))
+            })
+        })
+    }
+    /// Takes in an input event. Returns whether any active context binds it.
+    pub fn handle(&mut self, v: &hal::V) -> bool {
+        let c = match v.category() {
+            Some(c) => c,
+            None => return false,
+        };
+        if let hal::V::B(b::V(button, state, _)) = v {
+            match state {
+                b::State::DOWN => self.held.insert(*button),
+                b::State::UP => self.held.remove(button),
+            };
+        }
+        let bound = self.owner(&c).is_some();
+        self.update(Some(v));
+        bound
+    }
+    /// Works out every action's value, notifying subscribers of changes. `v` is the momentary
+    /// input that caused the update, if any.
+    fn update(&mut self, v: Option<&hal::V>) {
+        let mut values: HashMap<String, ActionValue> = HashMap::new();
+        let mut momentary = HashSet::new();
+        for context in self.stack.iter() {
+            let bindings = match self.contexts.iter().find(|(name, _)| name == context) {
+                Some((_, bindings)) => bindings,
+                None => continue,
+            };
+            let held = |c: &b::C| {
+                self.held.contains(c) && self.owner(&hal::C::from(*c)) == Some(context.as_str())
+            };
+            let level = |c: &b::C| if held(c) { 1. } else { 0. };
+            for (action, binding) in bindings.iter() {
+                let value = values
+                    .entry(action.clone())
+                    .or_insert_with(|| ActionValue::zero(binding.kind()));
+                match (binding, value, v) {
+                    (Binding::Key(c), ActionValue::Digital(down), _) => *down |= held(c),
+                    (Binding::Keys1D { neg, pos }, ActionValue::Axis1D(x), _) => {
+                        *x += level(pos) - level(neg)
+                    }
+                    (
+                        Binding::Keys2D {
+                            left,
+                            right,
+                            down,
+                            up,
+                        },
+                        ActionValue::Axis2D(xy),
+                        _,
+                    ) => *xy += Vector2::new(level(right) - level(left), level(up) - level(down)),
+                    (
+                        Binding::Scroll(scale),
+                        ActionValue::Axis1D(x),
+                        Some(hal::V::A(a::V::Scroll(d))),
+                    ) if self.owner(&a::C::Scroll.into()) == Some(context.as_str()) => {
+                        *x += *d * *scale;
+                        momentary.insert(action.clone());
+                    }
+                    (
+                        Binding::Mouse(scale),
+                        ActionValue::Axis2D(xy),
+                        Some(hal::V::P(p::V::MouseDelta(p::DeltaState(d)))),
+                    ) if self.owner(&p::C::MouseDelta.into()) == Some(context.as_str()) => {
+                        *xy += *d * *scale;
+                        momentary.insert(action.clone());
+                    }
+                    _ => (),
+                }
+            }
+        }
+        // Actions that are no longer bound in any active context go back to zero.
+        for (action, kind) in self.kinds.iter() {
+            values
+                .entry(action.clone())
+                .or_insert_with(|| ActionValue::zero(*kind));
+        }
+        let changed: HashSet<&String> = values
+            .iter()
+            .filter(|(action, value)| {
+                momentary.contains(*action)
+                    || self.values.get(*action).map_or_else(
+                        || ActionValue::zero(self.kinds[*action]) != **value,
+                        |previous| previous != *value,
+                    )
+            })
+            .map(|(action, _)| action)
+            .collect();
+        // Subscribers hear about changes in the order they subscribed.
+        for (action, f) in self.subscribers.iter_mut() {
+            if changed.contains(action) {
+                f(&values[action]);
+            }
+        }
+        for (action, value) in values.into_iter() {
+            if !momentary.contains(&action) {
+                self.values.insert(action, value);
+            }
+        }
+    }
+    /// Reads bindings from a config, see the module documentation.
+    pub fn from_config(config: &str) -> Result<ActionMap, ActionError> {
+        let mut map = ActionMap::new();
+        let mut context = None;
+        for (i, line) in config.lines().enumerate() {
+            let line = line.trim();
+            let parse_err = |reason: String| ActionError::Parse {
+                line: i + 1,
+                reason,
+            };
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                context = Some(name.trim().to_string());
+                continue;
+            }
+            let context = context
+                .as_deref()
+                .ok_or_else(|| parse_err("Binding outside of a context.".to_string()))?;
+            let (action, binding) = line
+                .split_once('=')
+                .ok_or_else(|| parse_err(format!("Expected action = binding, got {}.", line)))?;
+            let binding = binding.trim().parse::<Binding>().map_err(parse_err)?;
+            map.bind(context, action.trim(), binding)
+                .map_err(|e| parse_err(e.to_string()))?;
+        }
+        Ok(map)
+    }
+    /// Writes bindings out as a config that `from_config` reads back.
+    pub fn to_config(&self) -> String {
+        let mut config = String::new();
+        for (context, bindings) in self.contexts.iter() {
+            config += &format!("[{}]\n", context);
+            for (action, binding) in bindings.iter() {
+                config += &format!("{} = {}\n", action, binding);
+            }
+        }
+        config
+    }
+    pub fn load(path: impl AsRef<Path>) -> std::io::Result<ActionMap> {
+        let config = std::fs::read_to_string(path)?;
+        ActionMap::from_config(&config)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+    }
+    pub fn save(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        std::fs::write(path, self.to_config())
+    }
+    /// A callback feeding the shared map, to register for each of `categories`. Events bound in an
+    /// active context are consumed.
+    pub fn callback(
+        map: &Arc<Mutex<ActionMap>>,
+    ) -> Arc<Mutex<dyn CBFn<hal::State, hal::V, hal::C>>> {
+        let map = map.clone();
+        Arc::new(Mutex::new(
+            move |_: &hal::State, v: &hal::V, _: &Instant, _: &Instant| {
+                let bound = match map.lock() {
+                    Ok(mut map) => map.handle(v),
+                    Err(poisoned) => poisoned.into_inner().handle(v),
+                };
+                if bound {
+                    Propagation::Consumed
+                } else {
+                    Propagation::Continue
+                }
+            },
+        ))
+    }
+}
+impl Default for ActionMap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl fmt::Debug for ActionMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ActionMap")
+            .field("contexts", &self.contexts)
+            .field("stack", &self.stack)
+            .field("values", &self.values)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn configs_round_trip() {
+        let map = ActionMap::from_config(include_str!("../../resources/bindings.cfg")).unwrap();
+        assert_eq!(
+            map.bindings("gameplay", "exit"),
+            vec![Binding::Key(Key::Esc.into())]
+        );
+        assert_eq!(map.kind("move"), Some(ActionKind::Axis2D));
+        // Both backends send tab as the special key, not as a character.
+        assert_eq!(
+            map.bindings("viewer", "toggle_wireframe"),
+            vec![Binding::Key(Key::Tab.into())]
+        );
+        let config = map.to_config();
+        assert_eq!(ActionMap::from_config(&config).unwrap().to_config(), config);
+
+        let config = "[menu]\nback = key esc\nback = keys a d\n";
+        assert!(matches!(
+            ActionMap::from_config(config),
+            Err(ActionError::Parse { line: 3, .. })
+        ));
+        assert!(matches!(
+            ActionMap::from_config("jump = key space"),
+            Err(ActionError::Parse { line: 1, .. })
+        ));
+        assert!(matches!(
+            ActionMap::from_config("[menu]\nback = key escape"),
+            Err(ActionError::Parse { line: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn upper_contexts_shadow_lower_ones() {
+        let mut map = ActionMap::from_config(
+            "[gameplay]\nforward = key w\njump = key space\n[menu]\nup = key w\n",
+        )
+        .unwrap();
+        let seen = Arc::new(Mutex::new(vec![]));
+        for action in ["forward", "up"] {
+            let seen = seen.clone();
+            map.subscribe(action, move |v| seen.lock().unwrap().push((action, *v)));
+        }
+        map.push("gameplay");
+        assert!(map.handle(&b::V::pressed('w')));
+        map.push("menu");
+        // Jumping still works from the menu, since it does not bind space.
+        assert!(map.handle(&b::V::pressed(' ')));
+        assert_eq!(map.value("jump"), Some(ActionValue::Digital(true)));
+        assert_eq!(map.pop().as_deref(), Some("menu"));
+        assert!(map.handle(&b::V::released('w')));
+        assert!(!map.handle(&b::V::pressed('x')));
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec![
+                ("forward", ActionValue::Digital(true)),
+                ("forward", ActionValue::Digital(false)),
+                ("up", ActionValue::Digital(true)),
+                ("forward", ActionValue::Digital(true)),
+                ("up", ActionValue::Digital(false)),
+                ("forward", ActionValue::Digital(false)),
+            ]
+        );
+    }
+
+    #[test]
+    fn axes_combine_keys_and_the_mouse() {
+        let mut map = ActionMap::new();
+        map.bind("gameplay", "move", "keys a d s w".parse().unwrap())
+            .unwrap();
+        map.bind("gameplay", "move", Binding::Mouse(0.5)).unwrap();
+        map.bind("gameplay", "zoom", Binding::Scroll(2.)).unwrap();
+        assert!(matches!(
+            map.bind("gameplay", "move", Binding::Key('x'.into())),
+            Err(ActionError::KindMismatch { .. })
+        ));
+        let zooms = Arc::new(Mutex::new(vec![]));
+        let zooms_cb = zooms.clone();
+        map.subscribe("zoom", move |v| zooms_cb.lock().unwrap().push(*v));
+        map.push("gameplay");
+
+        map.handle(&b::V::pressed('w'));
+        map.handle(&b::V::pressed('d'));
+        assert_eq!(
+            map.value("move"),
+            Some(ActionValue::Axis2D(Vector2::new(1., 1.)))
+        );
+        // Mouse movement only counts in the moment.
+        map.handle(&p::V::MouseDelta(p::DeltaState(Vector2::new(2., 0.))).into());
+        map.handle(&a::V::Scroll(1.).into());
+        map.handle(&a::V::Scroll(1.).into());
+        assert_eq!(
+            map.value("move"),
+            Some(ActionValue::Axis2D(Vector2::new(1., 1.)))
+        );
+        assert_eq!(*zooms.lock().unwrap(), vec![ActionValue::Axis1D(2.); 2]);
+        assert_eq!(map.categories().len(), 6);
+
+        // Popping the context lets go of everything it held.
+        map.pop();
+        assert_eq!(
+            map.value("move"),
+            Some(ActionValue::Axis2D(Vector2::zeros()))
+        );
+    }
+}
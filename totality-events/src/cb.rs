@@ -7,6 +7,11 @@ use std::{
     time::Instant,
 };
 
+use th::{
+    clock::{Clock, SystemClock},
+    profile,
+};
+
 pub trait Categorized<C: Hash + Eq + PartialEq + Copy + Clone> {
     fn category(&self) -> Option<C>;
 }
@@ -14,34 +19,86 @@ pub trait ValueStore<C: Hash + Eq + PartialEq + Copy + Clone, V: Categorized<C>>
     fn get(&self, c: &C) -> V;
 }
 
+/// What a callback did with an event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Propagation {
+    /// Lets callbacks of lower priority see the event too.
+    #[default]
+    Continue,
+    /// Keeps callbacks of lower priority from seeing the event.
+    Consumed,
+}
+
 pub trait CBFn<G: ValueStore<C, V>, V: Categorized<C>, C: Hash + Eq + PartialEq + Copy + Clone>:
-    FnMut(&G, &V, &Instant, &Instant) + Send + 'static
+    FnMut(&G, &V, &Instant, &Instant) -> Propagation + Send + 'static
 {
 }
 impl<
-        T: FnMut(&G, &V, &Instant, &Instant) + Send + 'static,
+        T: FnMut(&G, &V, &Instant, &Instant) -> Propagation + Send + 'static,
         G: ValueStore<C, V>,
         V: Categorized<C>,
         C: Hash + Eq + PartialEq + Copy + Clone,
     > CBFn<G, V, C> for T
 {
 }
+/// Decides whether a value changing from `before` to `after` is worth firing for.
+pub trait ChangeFilter<V>: Send {
+    fn passes(&self, before: &V, after: &V) -> bool;
+}
+impl<V, F: Fn(&V, &V) -> bool + Send> ChangeFilter<V> for F {
+    fn passes(&self, before: &V, after: &V) -> bool {
+        self(before, after)
+    }
+}
+
 pub struct CB<G, V: Categorized<C>, C: Hash + Eq + PartialEq + Copy + Clone> {
     c: C,
+    /// Callbacks of higher priority fire first. Equal priorities fire in registration order.
+    priority: i32,
+    /// Only fire for changes this lets through.
+    filter: Option<Box<dyn ChangeFilter<V>>>,
     cb: Weak<Mutex<dyn CBFn<G, V, C>>>,
 }
 impl<G, V: Categorized<C>, C: Hash + Eq + PartialEq + Copy + Clone> CB<G, V, C> {
-    fn call(&self, s: &G, v: &V, l_t: &Instant, c_t: &Instant) -> Result<(), ()> {
+    fn call(
+        &self,
+        s: &G,
+        before: &V,
+        v: &V,
+        l_t: &Instant,
+        c_t: &Instant,
+    ) -> Result<Propagation, ()> {
+        if let Some(ref filter) = self.filter {
+            if !filter.passes(before, v) {
+                return Result::Ok(Propagation::Continue);
+            }
+        }
         match self.cb.upgrade() {
             Some(cb_m) => match cb_m.lock() {
                 Ok(mut cb_mg) => Result::Ok((cb_mg.deref_mut())(s, v, l_t, c_t)),
-                Err(_) => Result::Ok(()),
+                Err(_) => Result::Ok(Propagation::Continue),
             },
             None => Result::Err(()),
         }
     }
     pub fn new(c: C, cb: Weak<Mutex<dyn CBFn<G, V, C>>>) -> Self {
-        CB { c: c, cb: cb }
+        Self::with_priority(c, 0, cb)
+    }
+    pub fn with_priority(c: C, priority: i32, cb: Weak<Mutex<dyn CBFn<G, V, C>>>) -> Self {
+        CB {
+            c,
+            priority,
+            filter: None,
+            cb,
+        }
+    }
+    pub fn category(&self) -> C {
+        self.c
+    }
+    /// Only fires when the value changes in a way `filter` lets through, e.g. `hal::change::Ch`.
+    pub fn filtered(mut self, filter: impl ChangeFilter<V> + 'static) -> Self {
+        self.filter = Some(Box::new(filter));
+        self
     }
 }
 pub enum RegRequest<G, V: Categorized<C>, C: Hash + PartialEq + Eq + Copy + Clone> {
@@ -60,41 +117,55 @@ pub struct Manager<G: ValueStore<C, V>, V: Categorized<C>, C: Hash + Eq + Partia
     // TODO potentially change Vec into a linked list for O(1) removal
     buckets: HashMap<C, Vec<Arc<Mutex<CB<G, V, C>>>>>,
     last_inst: Instant,
+    /// The values `fire_and_clean_all` last fired with, to compare against for filters.
+    seen: HashMap<C, V>,
+    clock: Arc<dyn Clock>,
 }
 impl<G: ValueStore<C, V>, V: Categorized<C>, C: Hash + Eq + PartialEq + Copy + Clone>
     Manager<G, V, C>
 {
     pub fn new() -> Self {
+        Self::with_clock(SystemClock)
+    }
+    /// Creates a manager that timestamps callbacks with `clock`.
+    pub fn with_clock(clock: impl Clock + 'static) -> Self {
         Manager {
             occupied: vec![],
             buckets: HashMap::new(),
-            last_inst: Instant::now(),
+            last_inst: clock.now(),
+            seen: HashMap::new(),
+            clock: Arc::new(clock),
         }
     }
     fn fire_event(
         s: &G,
+        before: &V,
         v: &V,
         cb_m: &Arc<Mutex<CB<G, V, C>>>,
         last_inst: &Instant,
         curr_inst: &Instant,
-    ) -> Result<(), ()> {
+    ) -> Result<Propagation, ()> {
         match cb_m.lock() {
-            Ok(cb_mg) => (*cb_mg).call(s, v, last_inst, curr_inst),
-            Err(_) => Result::Ok(()),
+            Ok(cb_mg) => (*cb_mg).call(s, before, v, last_inst, curr_inst),
+            Err(_) => Result::Ok(Propagation::Continue),
         }
     }
+    /// Fires the callbacks of a category from highest priority down, until one consumes the event.
+    /// Returns the callbacks that were found to be dropped.
     fn fire_category_events(
         &self,
         s: &G,
         c: &C,
+        before: &V,
         v: &V,
         curr_inst: &Instant,
     ) -> (C, Vec<Arc<Mutex<CB<G, V, C>>>>) {
         let mut to_remove = vec![];
         if let Some(bucket) = self.buckets.get(&c) {
             for cb in bucket.iter() {
-                match Manager::fire_event(s, v, cb, &self.last_inst, curr_inst) {
-                    Ok(_) => (),
+                match Manager::fire_event(s, before, v, cb, &self.last_inst, curr_inst) {
+                    Ok(Propagation::Continue) => (),
+                    Ok(Propagation::Consumed) => break,
                     Err(_) => to_remove.push(cb.clone()),
                 }
             }
@@ -105,16 +176,20 @@ impl<G: ValueStore<C, V>, V: Categorized<C>, C: Hash + Eq + PartialEq + Copy + C
         &mut self,
         s: &G,
         curr_inst: &Instant,
-    ) -> Vec<(C, Vec<Arc<Mutex<CB<G, V, C>>>>)> {
+    ) -> Vec<(C, Vec<Arc<Mutex<CB<G, V, C>>>>)>
+    where
+        V: Clone,
+    {
         let mut removal_stuff = Vec::with_capacity(self.occupied.len());
+        let mut seen = HashMap::with_capacity(self.occupied.len());
         for category in self.occupied.iter() {
-            removal_stuff.push(self.fire_category_events(
-                s,
-                category,
-                &s.get(&category),
-                curr_inst,
-            ));
+            let v = s.get(&category);
+            // The first time around, there is nothing to have changed from.
+            let before = self.seen.get(category).unwrap_or(&v);
+            removal_stuff.push(self.fire_category_events(s, category, before, &v, curr_inst));
+            seen.insert(*category, v);
         }
+        self.seen = seen;
         removal_stuff
     }
     fn remove_category(cc: &mut Vec<C>, c: &C) {
@@ -138,6 +213,12 @@ impl<G: ValueStore<C, V>, V: Categorized<C>, C: Hash + Eq + PartialEq + Copy + C
             }
         }
     }
+    fn priority(cb_m: &Arc<Mutex<CB<G, V, C>>>) -> i32 {
+        match cb_m.lock() {
+            Ok(cb_mg) => cb_mg.priority,
+            Err(poisoned) => poisoned.into_inner().priority,
+        }
+    }
     pub fn register(&mut self, cb: CB<G, V, C>) -> Weak<Mutex<CB<G, V, C>>> {
         if !self.buckets.contains_key(&cb.c) {
             self.occupied.push(cb.c.clone())
@@ -146,8 +227,13 @@ impl<G: ValueStore<C, V>, V: Categorized<C>, C: Hash + Eq + PartialEq + Copy + C
             .buckets
             .entry(cb.c.clone())
             .or_insert(Vec::with_capacity(1));
-        v.push(Arc::new(Mutex::new(cb)));
-        Arc::downgrade(&v.last().unwrap())
+        // Keep the bucket ordered by descending priority, after callbacks of the same priority.
+        let idx = v
+            .iter()
+            .position(|x| Self::priority(x) < cb.priority)
+            .unwrap_or(v.len());
+        v.insert(idx, Arc::new(Mutex::new(cb)));
+        Arc::downgrade(&v[idx])
     }
     fn unregister(&mut self, cb_m: Arc<Mutex<CB<G, V, C>>>) {
         let c = match cb_m.lock() {
@@ -157,13 +243,26 @@ impl<G: ValueStore<C, V>, V: Categorized<C>, C: Hash + Eq + PartialEq + Copy + C
         .clone();
         self.remove_matching(vec![(c, vec![cb_m.clone()])]);
     }
+    /// Fires callbacks for each of `vv` in turn. `s` is expected to still hold the values from
+    /// before `vv`, for filters to compare against.
     pub fn fire_and_clean_listing(&mut self, s: &G, vv: &mut Vec<V>) {
-        let curr_inst = Instant::now();
+        let _span = profile::scope("fire callbacks");
+        let curr_inst = self.clock.now();
         let mut deallocs = Vec::new();
         let mut dealloc_idx = HashMap::new();
+        // Values of categories that change more than once in `vv`.
+        let mut latest: HashMap<C, &V> = HashMap::new();
         for v in vv.iter() {
             if let Some(c) = v.category() {
-                let (c, mut rr) = self.fire_category_events(s, &c, &v, &curr_inst);
+                let stored;
+                let before = match latest.insert(c, v) {
+                    Some(before) => before,
+                    None => {
+                        stored = s.get(&c);
+                        &stored
+                    }
+                };
+                let (c, mut rr) = self.fire_category_events(s, &c, before, &v, &curr_inst);
                 if !rr.is_empty() {
                     let idx = dealloc_idx
                         .entry(c.clone())
@@ -179,8 +278,12 @@ impl<G: ValueStore<C, V>, V: Categorized<C>, C: Hash + Eq + PartialEq + Copy + C
         self.remove_matching(deallocs);
         self.last_inst = curr_inst;
     }
-    pub fn fire_and_clean_all(&mut self, s: &G) {
-        let curr_inst = Instant::now();
+    pub fn fire_and_clean_all(&mut self, s: &G)
+    where
+        V: Clone,
+    {
+        let _span = profile::scope("fire all callbacks");
+        let curr_inst = self.clock.now();
         let rems = self.fire_all_events(s, &curr_inst);
         self.remove_matching(rems);
         self.last_inst = curr_inst;
@@ -202,3 +305,151 @@ impl<G: ValueStore<C, V>, V: Categorized<C>, C: Hash + Eq + PartialEq + Copy + C
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use th::clock::ManualClock;
+
+    use super::*;
+
+    #[derive(Clone, Copy)]
+    struct Press(u8);
+    impl Categorized<u8> for Press {
+        fn category(&self) -> Option<u8> {
+            Some(self.0)
+        }
+    }
+    struct Keys;
+    impl ValueStore<u8, Press> for Keys {
+        fn get(&self, c: &u8) -> Press {
+            Press(*c)
+        }
+    }
+
+    #[test]
+    fn callbacks_are_timestamped_by_the_clock() {
+        let clock = ManualClock::new();
+        let start = clock.now();
+        let mut man = Manager::with_clock(clock.clone());
+        let seen = Arc::new(Mutex::new(vec![]));
+        let seen_cb = seen.clone();
+        let cb: Arc<Mutex<dyn CBFn<Keys, Press, u8>>> = Arc::new(Mutex::new(
+            move |_: &Keys, _: &Press, l: &Instant, c: &Instant| {
+                seen_cb.lock().unwrap().push((*l, *c));
+                Propagation::Continue
+            },
+        ));
+        man.register(CB::new(1, Arc::downgrade(&cb)));
+
+        clock.advance(Duration::from_millis(16));
+        man.fire_and_clean_all(&Keys);
+        clock.advance(Duration::from_millis(5));
+        man.fire_and_clean_listing(&Keys, &mut vec![Press(1), Press(2)]);
+
+        let ms = Duration::from_millis;
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec![(start, start + ms(16)), (start + ms(16), start + ms(21)),]
+        );
+    }
+
+    #[test]
+    fn higher_priorities_fire_first_and_can_consume() {
+        let mut man = Manager::new();
+        let fired = Arc::new(Mutex::new(vec![]));
+        let make = |name: &'static str, consume: bool| {
+            let fired = fired.clone();
+            let cb: Arc<Mutex<dyn CBFn<Keys, Press, u8>>> = Arc::new(Mutex::new(
+                move |_: &Keys, p: &Press, _: &Instant, _: &Instant| {
+                    fired.lock().unwrap().push((name, p.0));
+                    // The text box only wants key 1.
+                    if consume && p.0 == 1 {
+                        Propagation::Consumed
+                    } else {
+                        Propagation::Continue
+                    }
+                },
+            ));
+            cb
+        };
+        let camera = make("camera", false);
+        let text_box = make("text box", true);
+        let overlay = make("overlay", false);
+        man.register(CB::new(1, Arc::downgrade(&camera)));
+        man.register(CB::new(2, Arc::downgrade(&camera)));
+        man.register(CB::with_priority(1, 10, Arc::downgrade(&text_box)));
+        man.register(CB::with_priority(2, 10, Arc::downgrade(&text_box)));
+        man.register(CB::with_priority(1, 10, Arc::downgrade(&overlay)));
+
+        man.fire_and_clean_listing(&Keys, &mut vec![Press(1), Press(2)]);
+        assert_eq!(
+            *fired.lock().unwrap(),
+            vec![("text box", 1), ("text box", 2), ("camera", 2)]
+        );
+
+        // Once the text box is gone, the rest see the event again.
+        drop(text_box);
+        fired.lock().unwrap().clear();
+        man.fire_and_clean_listing(&Keys, &mut vec![Press(1)]);
+        man.fire_and_clean_listing(&Keys, &mut vec![Press(1)]);
+        assert_eq!(
+            *fired.lock().unwrap(),
+            vec![("overlay", 1), ("camera", 1), ("overlay", 1), ("camera", 1)]
+        );
+        assert_eq!(man.buckets[&1].len(), 2);
+    }
+
+    #[test]
+    fn filters_see_changes_from_the_state() {
+        use crate::hal::{b, change::Ch, State, V};
+
+        let mut state = State::default();
+        let fired = Arc::new(Mutex::new(vec![]));
+        let make = |name: &'static str| {
+            let fired = fired.clone();
+            let cb: Arc<Mutex<dyn CBFn<State, V, crate::hal::C>>> = Arc::new(Mutex::new(
+                move |_: &State, _: &V, _: &Instant, _: &Instant| {
+                    fired.lock().unwrap().push(name);
+                    Propagation::Continue
+                },
+            ));
+            cb
+        };
+        let (press, release, change, always) = (
+            make("press"),
+            make("release"),
+            make("change"),
+            make("always"),
+        );
+        let w = crate::hal::C::from(b::C::A('w'));
+        let mut man = Manager::new();
+        man.register(CB::new(w, Arc::downgrade(&press)).filtered(Ch::press()));
+        man.register(CB::new(w, Arc::downgrade(&release)).filtered(Ch::release()));
+        man.register(CB::new(w, Arc::downgrade(&change)).filtered(Ch::<V>::any()));
+        man.register(CB::new(w, Arc::downgrade(&always)));
+
+        let key = |s: b::State| V::from(b::V(b::C::A('w'), s, b::Mods::NONE));
+        let mut fire = |vv: Vec<V>| {
+            fired.lock().unwrap().clear();
+            let mut vv = vv;
+            man.fire_and_clean_listing(&state, &mut vv);
+            for v in vv.iter() {
+                state.update(v);
+            }
+            fired.lock().unwrap().clone()
+        };
+        assert_eq!(
+            fire(vec![key(b::State::DOWN)]),
+            vec!["press", "change", "always"]
+        );
+        // Held down, the key repeats without changing.
+        assert_eq!(fire(vec![key(b::State::DOWN)]), vec!["always"]);
+        // Within a single batch, each event is compared with the one before.
+        assert_eq!(
+            fire(vec![key(b::State::UP), key(b::State::DOWN)]),
+            vec!["release", "change", "always", "press", "change", "always"]
+        );
+    }
+}
@@ -0,0 +1,451 @@
+//! # Combo
+//!
+//! Recognizes gestures made of several button events: chords like Ctrl+S, timed sequences, double
+//! taps and long presses.
+//!
+//! A `Recognizer` is fed button events in order along with when they happened, and returns the
+//! gestures they complete. A shared recognizer can also feed a callback for `cb::Manager`,
+//! registered for every one of its `categories`, and still be ticked from the app's loop so that
+//! long presses fire on time.
+//!
+//! ```ignore
+//! let mut rec = Recognizer::new();
+//! rec.add(Gesture::chord([Button::Ctrl, 's'.into()]), Action::Save);
+//! rec.add(Gesture::double_tap('w', Duration::from_millis(250)), Action::Sprint);
+//! let rec = Arc::new(Mutex::new(rec));
+//! let cb = Recognizer::callback(&rec, move |_, action| actions_tx.send(action).unwrap());
+//! for c in rec.lock().unwrap().categories() {
+//!     sm.reg_imm_cb(CB::new(c, Arc::downgrade(&cb)));
+//! }
+//! loop {
+//!     for action in rec.lock().unwrap().tick(Instant::now()) {
+//!         // ...
+//!     }
+//! }
+//! ```
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    cb::{CBFn, Propagation},
+    hal::{
+        self,
+        b::{self, Key, Side},
+    },
+};
+
+/// A button taking part in a gesture. Modifiers can be either the left or the right one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Button {
+    Is(b::C),
+    Ctrl,
+    Shift,
+    Alt,
+    Mod,
+}
+impl Button {
+    pub fn matches(&self, c: &b::C) -> bool {
+        match (self, c) {
+            (Button::Is(is), c) => is == c,
+            (Button::Ctrl, b::C::S(Key::Ctrl(_))) => true,
+            (Button::Shift, b::C::S(Key::Shift(_))) => true,
+            (Button::Alt, b::C::S(Key::Alt(_))) => true,
+            (Button::Mod, b::C::S(Key::Mod(_))) => true,
+            _ => false,
+        }
+    }
+    /// Every button this matches.
+    fn buttons(&self) -> Vec<b::C> {
+        let sides = |key: fn(Side) -> Key| vec![key(Side::L).into(), key(Side::R).into()];
+        match self {
+            Button::Is(c) => vec![*c],
+            Button::Ctrl => sides(Key::Ctrl),
+            Button::Shift => sides(Key::Shift),
+            Button::Alt => sides(Key::Alt),
+            Button::Mod => sides(Key::Mod),
+        }
+    }
+}
+impl From<b::C> for Button {
+    fn from(c: b::C) -> Button {
+        Button::Is(c)
+    }
+}
+impl From<char> for Button {
+    fn from(c: char) -> Button {
+        Button::Is(c.into())
+    }
+}
+impl From<Key> for Button {
+    fn from(k: Key) -> Button {
+        Button::Is(k.into())
+    }
+}
+
+fn is_modifier(c: &b::C) -> bool {
+    matches!(
+        c,
+        b::C::S(Key::Ctrl(_) | Key::Shift(_) | Key::Alt(_) | Key::Mod(_))
+    )
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Gesture {
+    /// All of the buttons held down at once, recognized when the last of them goes down. Other
+    /// modifiers being held as well keep it from being recognized, so that Ctrl+Shift+S is not
+    /// also Ctrl+S.
+    Chord(Vec<Button>),
+    /// The buttons pressed in order, each within `gap` of the one before.
+    Sequence { buttons: Vec<Button>, gap: Duration },
+    /// The button held down for `hold`. Recognized once per press.
+    LongPress { button: Button, hold: Duration },
+}
+impl Gesture {
+    pub fn chord<B: Into<Button>>(buttons: impl IntoIterator<Item = B>) -> Gesture {
+        Gesture::Chord(buttons.into_iter().map(Into::into).collect())
+    }
+    pub fn sequence<B: Into<Button>>(
+        buttons: impl IntoIterator<Item = B>,
+        gap: Duration,
+    ) -> Gesture {
+        Gesture::Sequence {
+            buttons: buttons.into_iter().map(Into::into).collect(),
+            gap,
+        }
+    }
+    /// Two presses of the button, the second within `within` of the first.
+    pub fn double_tap(button: impl Into<Button>, within: Duration) -> Gesture {
+        let button = button.into();
+        Gesture::Sequence {
+            buttons: vec![button, button],
+            gap: within,
+        }
+    }
+    pub fn long_press(button: impl Into<Button>, hold: Duration) -> Gesture {
+        Gesture::LongPress {
+            button: button.into(),
+            hold,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Entry<T> {
+    gesture: Gesture,
+    id: T,
+    /// For sequences, the first press that can start the next one, so that presses are not
+    /// shared by overlapping recognitions.
+    next_press: u64,
+    /// For long presses, when the press that was last recognized started.
+    recognized_press: Option<Instant>,
+}
+
+#[derive(Debug)]
+pub struct Recognizer<T> {
+    entries: Vec<Entry<T>>,
+    /// Buttons being held down, and since when.
+    held: HashMap<b::C, Instant>,
+    /// The most recent presses, numbered.
+    presses: VecDeque<(u64, b::C, Instant)>,
+    count: u64,
+}
+impl<T: Clone> Recognizer<T> {
+    pub fn new() -> Self {
+        Recognizer {
+            entries: vec![],
+            held: HashMap::new(),
+            presses: VecDeque::new(),
+            count: 0,
+        }
+    }
+    /// Recognizes `gesture` as `id` from now on.
+    pub fn add(&mut self, gesture: Gesture, id: T) {
+        self.entries.push(Entry {
+            gesture,
+            id,
+            next_press: 0,
+            recognized_press: None,
+        });
+    }
+    /// Every button category the gestures are made of.
+    pub fn categories(&self) -> Vec<hal::C> {
+        let mut cc: Vec<hal::C> = vec![];
+        for entry in self.entries.iter() {
+            let buttons = match &entry.gesture {
+                Gesture::Chord(buttons) | Gesture::Sequence { buttons, .. } => buttons.clone(),
+                Gesture::LongPress { button, .. } => vec![*button],
+            };
+            for c in buttons.iter().flat_map(Button::buttons) {
+                if !cc.contains(&c.into()) {
+                    cc.push(c.into());
+                }
+            }
+        }
+        cc
+    }
+    /// Takes in an event that happened at `at`, and returns the gestures it completes, in the order
+    /// they were added. Events are expected in the order they happened.
+    ///
+    /// Anything but a button only moves time along.
+    pub fn feed(&mut self, v: &hal::V, at: Instant) -> Vec<T> {
+        let mut recognized = self.tick(at);
+        if let hal::V::B(b::V(c, state, _)) = v {
+            match (state, self.held.contains_key(c)) {
+                (b::State::DOWN, false) => {
+                    self.held.insert(*c, at);
+                    self.press(*c, at, &mut recognized);
+                }
+                (b::State::UP, true) => {
+                    self.held.remove(c);
+                }
+                // Repeats, or releases of buttons that went down before we were listening.
+                _ => (),
+            }
+        }
+        recognized
+    }
+    /// Recognizes long presses that have been held long enough by `now`. `feed` does this too, so
+    /// this is only needed to recognize them without waiting for the next event.
+    pub fn tick(&mut self, now: Instant) -> Vec<T> {
+        let mut recognized = vec![];
+        for entry in self.entries.iter_mut() {
+            if let Gesture::LongPress { button, hold } = &entry.gesture {
+                let press = self
+                    .held
+                    .iter()
+                    .filter(|(c, since)| button.matches(c) && now - **since >= *hold)
+                    .map(|(_, since)| *since)
+                    .min();
+                if let Some(since) = press {
+                    if entry.recognized_press != Some(since) {
+                        entry.recognized_press = Some(since);
+                        recognized.push(entry.id.clone());
+                    }
+                }
+            }
+        }
+        recognized
+    }
+    fn press(&mut self, c: b::C, at: Instant, recognized: &mut Vec<T>) {
+        self.count += 1;
+        let longest = self
+            .entries
+            .iter()
+            .map(|e| match &e.gesture {
+                Gesture::Sequence { buttons, .. } => buttons.len(),
+                _ => 0,
+            })
+            .max()
+            .unwrap_or(0);
+        self.presses.push_back((self.count, c, at));
+        while self.presses.len() > longest {
+            self.presses.pop_front();
+        }
+        for entry in self.entries.iter_mut() {
+            let is_recognized = match &entry.gesture {
+                Gesture::Chord(buttons) => {
+                    buttons.iter().any(|b| b.matches(&c))
+                        && buttons
+                            .iter()
+                            .all(|b| self.held.keys().any(|held| b.matches(held)))
+                        && self.held.keys().all(|held| {
+                            !is_modifier(held) || buttons.iter().any(|b| b.matches(held))
+                        })
+                }
+                Gesture::Sequence { buttons, gap } => {
+                    let n = buttons.len();
+                    n > 0
+                        && self.presses.len() >= n
+                        && self.presses[self.presses.len() - n].0 >= entry.next_press
+                        && buttons
+                            .iter()
+                            .zip(self.presses.iter().skip(self.presses.len() - n))
+                            .all(|(b, (_, c, _))| b.matches(c))
+                        && self
+                            .presses
+                            .iter()
+                            .skip(self.presses.len() - n)
+                            .zip(self.presses.iter().skip(self.presses.len() - n + 1))
+                            .all(|((_, _, before), (_, _, after))| *after - *before <= *gap)
+                }
+                Gesture::LongPress { .. } => false,
+            };
+            if is_recognized {
+                entry.next_press = self.count + 1;
+                recognized.push(entry.id.clone());
+            }
+        }
+    }
+}
+impl<T: Clone + Send + 'static> Recognizer<T> {
+    /// A callback feeding the shared recognizer, which calls `f` for every gesture it recognizes.
+    /// It should be registered for each of `categories`.
+    ///
+    /// The callback only runs when an event comes in, so long presses are not recognized until the
+    /// next one unless the recognizer is also ticked. Events are fed with the time their batch was
+    /// fired, so gaps in sequences and double taps are only as precise as the polling.
+    pub fn callback(
+        rec: &Arc<Mutex<Recognizer<T>>>,
+        mut f: impl FnMut(&hal::State, T) + Send + 'static,
+    ) -> Arc<Mutex<dyn CBFn<hal::State, hal::V, hal::C>>> {
+        let rec = rec.clone();
+        Arc::new(Mutex::new(
+            move |s: &hal::State, v: &hal::V, _: &Instant, c_t: &Instant| {
+                let recognized = match rec.lock() {
+                    Ok(mut rec) => rec.feed(v, *c_t),
+                    Err(poisoned) => poisoned.into_inner().feed(v, *c_t),
+                };
+                for id in recognized {
+                    f(s, id);
+                }
+                Propagation::Continue
+            },
+        ))
+    }
+}
+impl<T: Clone> Default for Recognizer<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const MS: Duration = Duration::from_millis(1);
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    enum Action {
+        Save,
+        SaveAs,
+        Sprint,
+        Konami,
+        Charge,
+    }
+
+    /// Feeds events at the given milliseconds, collecting what was recognized along with when.
+    fn run(rec: &mut Recognizer<Action>, events: &[(u64, hal::V)]) -> Vec<(u64, Action)> {
+        let start = Instant::now();
+        let mut recognized = vec![];
+        for (ms, v) in events {
+            for action in rec.feed(v, start + MS * *ms as u32) {
+                recognized.push((*ms, action));
+            }
+        }
+        recognized
+    }
+
+    #[test]
+    fn chords_match_either_side_but_not_extra_modifiers() {
+        let mut rec = Recognizer::new();
+        rec.add(Gesture::chord([Button::Ctrl, 's'.into()]), Action::Save);
+        rec.add(
+            Gesture::chord([Button::Ctrl, Button::Shift, 's'.into()]),
+            Action::SaveAs,
+        );
+        let (ctrl_l, ctrl_r, shift) = (Key::Ctrl(Side::L), Key::Ctrl(Side::R), Key::Shift(Side::L));
+        let recognized = run(
+            &mut rec,
+            &[
+                (0, b::V::pressed('s')),
+                (10, b::V::pressed(ctrl_l)),
+                (20, b::V::released('s')),
+                (30, b::V::pressed('s')),
+                (40, b::V::pressed('s')),
+                (50, b::V::released('s')),
+                (60, b::V::released(ctrl_l)),
+                (70, b::V::pressed(ctrl_r)),
+                (80, b::V::pressed(shift)),
+                (90, b::V::pressed('s')),
+            ],
+        );
+        // Either order counts, but repeats do not.
+        assert_eq!(
+            recognized,
+            vec![(10, Action::Save), (30, Action::Save), (90, Action::SaveAs)]
+        );
+    }
+
+    #[test]
+    fn sequences_and_double_taps_time_out() {
+        let mut rec = Recognizer::new();
+        rec.add(Gesture::double_tap('w', MS * 200), Action::Sprint);
+        let (u, d) = (Key::Up, Key::Down);
+        rec.add(Gesture::sequence([u, u, d, d], MS * 500), Action::Konami);
+        let tap = |at: u64, c: b::C| [(at, b::V::pressed(c)), (at + 50, b::V::released(c))];
+        let events = [
+            tap(0, 'w'.into()),
+            tap(300, 'w'.into()),
+            tap(400, 'w'.into()),
+            tap(500, 'w'.into()),
+            tap(1000, u.into()),
+            tap(1100, u.into()),
+            tap(1200, u.into()),
+            tap(1300, d.into()),
+            tap(2000, d.into()),
+            tap(2100, u.into()),
+            tap(2200, u.into()),
+            tap(2300, d.into()),
+            tap(2400, d.into()),
+        ]
+        .concat();
+        assert_eq!(
+            run(&mut rec, &events),
+            vec![
+                // The fourth tap does not make another double tap with the third.
+                (400, Action::Sprint),
+                // The first attempt's second down came too late.
+                (2400, Action::Konami),
+            ]
+        );
+    }
+
+    #[test]
+    fn long_presses_fire_once_per_press() {
+        let mut rec = Recognizer::new();
+        rec.add(Gesture::long_press(' ', MS * 500), Action::Charge);
+        let start = Instant::now();
+        assert!(rec.feed(&b::V::pressed(' '), start).is_empty());
+        assert!(rec.tick(start + MS * 499).is_empty());
+        assert_eq!(rec.tick(start + MS * 500), vec![Action::Charge]);
+        assert!(rec.feed(&b::V::pressed(' '), start + MS * 600).is_empty());
+        assert!(rec.feed(&b::V::released(' '), start + MS * 700).is_empty());
+        // A short press is not long.
+        assert!(rec.feed(&b::V::pressed(' '), start + MS * 800).is_empty());
+        assert!(rec.feed(&b::V::released(' '), start + MS * 900).is_empty());
+        assert!(rec.tick(start + MS * 2000).is_empty());
+        assert_eq!(rec.categories(), vec![hal::C::from(b::C::A(' '))]);
+    }
+
+    #[test]
+    fn callbacks_feed_the_recognizer() {
+        let mut rec = Recognizer::new();
+        rec.add(Gesture::chord([Button::Ctrl, 's'.into()]), Action::Save);
+        rec.add(Gesture::long_press('s', MS * 500), Action::Charge);
+        assert_eq!(rec.categories().len(), 3);
+        let rec = Arc::new(Mutex::new(rec));
+        let recognized = Arc::new(Mutex::new(vec![]));
+        let recognized_cb = recognized.clone();
+        let cb = Recognizer::callback(&rec, move |_, action| {
+            recognized_cb.lock().unwrap().push(action)
+        });
+        let mut man = crate::cb::Manager::new();
+        for c in [b::C::S(Key::Ctrl(Side::R)), b::C::A('s')] {
+            man.register(crate::cb::CB::new(c.into(), Arc::downgrade(&cb)));
+        }
+        let state = hal::State::default();
+        man.fire_and_clean_listing(
+            &state,
+            &mut vec![b::V::pressed(Key::Ctrl(Side::R)), b::V::pressed('s')],
+        );
+        assert_eq!(*recognized.lock().unwrap(), vec![Action::Save]);
+        // The recognizer is still around to be ticked in between events.
+        let later = Instant::now() + MS * 1000;
+        assert_eq!(rec.lock().unwrap().tick(later), vec![Action::Charge]);
+    }
+}
@@ -22,7 +22,7 @@ impl From<V> for C {
     }
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub enum V {
     Scroll(f32),
 }
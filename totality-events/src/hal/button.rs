@@ -3,6 +3,7 @@ pub enum C {
     A(char), // Alpha-numeric + punctuation
     S(Key),  // special, like ESC, ALT, SHIFT, etc.
     F(Flag), // flag, like window close
+    P(u32),  // physical, the scancode of a key wherever the layout puts it
     Ignored,
 }
 impl C {
@@ -106,18 +107,51 @@ impl From<State> for bool {
     }
 }
 
+/// The modifiers held down, on either side.
+#[derive(Debug, Hash, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Mods {
+    pub shift: bool,
+    pub ctrl: bool,
+    pub alt: bool,
+    /// `Key::Mod`, i.e. the Windows, Command or Super key.
+    pub logo: bool,
+}
+impl Mods {
+    pub const NONE: Mods = Mods {
+        shift: false,
+        ctrl: false,
+        alt: false,
+        logo: false,
+    };
+}
+
+/// A button going up or down, with the modifiers held at the time.
 #[derive(Debug, Hash, Copy, Clone, PartialEq, Eq)]
-pub struct V(pub C, pub State);
+pub struct V(pub C, pub State, pub Mods);
 impl V {
     pub fn value(&self) -> State {
         self.1.clone()
     }
+    pub fn mods(&self) -> Mods {
+        self.2
+    }
     pub fn default_value_of(c: &C) -> Self {
-        Self(c.clone(), State::UP)
+        Self(c.clone(), State::UP, Mods::NONE)
+    }
+}
+#[cfg(test)]
+impl V {
+    /// `c` going down with no modifiers, as a whole event.
+    pub fn pressed(c: impl Into<C>) -> super::V {
+        V(c.into(), State::DOWN, Mods::NONE).into()
+    }
+    /// `c` going up with no modifiers, as a whole event.
+    pub fn released(c: impl Into<C>) -> super::V {
+        V(c.into(), State::UP, Mods::NONE).into()
     }
 }
 impl From<(C, State)> for V {
     fn from((a, b): (C, State)) -> V {
-        V(a, b)
+        V(a, b, Mods::NONE)
     }
 }
@@ -1,3 +1,6 @@
+use super::{b, V};
+use crate::cb::ChangeFilter;
+
 #[derive(Debug, Copy, Clone)]
 pub enum ChV<T> {
     Any,
@@ -76,3 +79,31 @@ impl<T> Ch<T> {
         self.before.satisfied_by(&other.before) && self.after.satisfied_by(&other.after)
     }
 }
+impl Ch<b::State> {
+    /// A button going down.
+    pub fn press() -> Ch<b::State> {
+        Ch::new(b::State::UP, b::State::DOWN)
+    }
+    /// A button coming back up.
+    pub fn release() -> Ch<b::State> {
+        Ch::new(b::State::DOWN, b::State::UP)
+    }
+}
+
+/// Matches the state of buttons that changed. Anything but a button does not pass.
+impl ChangeFilter<V> for Ch<b::State> {
+    fn passes(&self, before: &V, after: &V) -> bool {
+        match (before, after) {
+            (V::B(before), V::B(after)) => {
+                before.1 != after.1 && self.satisfied_by(&Ch::new(before.1, after.1))
+            }
+            _ => false,
+        }
+    }
+}
+/// Matches whole values that changed, so `Ch::any()` fires on any change at all.
+impl ChangeFilter<V> for Ch<V> {
+    fn passes(&self, before: &V, after: &V) -> bool {
+        before != after && self.satisfied_by(&Ch::new(before.clone(), after.clone()))
+    }
+}
@@ -2,10 +2,12 @@ pub mod axis;
 pub mod button;
 pub mod change;
 pub mod pos;
+pub mod text;
 
 pub use self::axis as a;
 pub use self::button as b;
 pub use self::pos as p;
+pub use self::text as t;
 
 use crate::cb::{Categorized, ValueStore};
 use std::collections::HashMap;
@@ -15,6 +17,7 @@ pub enum C {
     A(a::C),
     P(p::C),
     B(b::C),
+    T(t::C),
     Ignored,
     Unknown,
 }
@@ -24,6 +27,7 @@ impl C {
             C::A(a_c) => V::A(a_c.default_value()),
             C::P(p_c) => V::P(p_c.default_value()),
             C::B(b_c) => V::B(b_c.default_value()),
+            C::T(t_c) => V::T(t_c.default_value()),
             C::Ignored => V::Ignored,
             _ => unimplemented!("Category {:?} does not have a default value yet.", self),
         }
@@ -35,6 +39,7 @@ impl From<V> for C {
             V::A(v) => C::from(a::C::from(v)),
             V::P(v) => C::from(p::C::from(v)),
             V::B(v) => C::from(b::C::from(v)),
+            V::T(v) => C::from(t::C::from(v)),
             _ => unimplemented!("Crap. Can't convert from {:?} to C yet.", v),
         }
     }
@@ -45,6 +50,7 @@ impl From<&V> for C {
             V::A(v) => C::from(a::C::from(v)),
             V::P(v) => C::from(p::C::from(v)),
             V::B(v) => C::from(b::C::from(v)),
+            V::T(v) => C::from(t::C::from(v)),
             _ => unimplemented!("Crap. Can't convert from {:?} to C yet.", v),
         }
     }
@@ -64,12 +70,18 @@ impl From<b::C> for C {
         C::B(c)
     }
 }
+impl From<t::C> for C {
+    fn from(c: t::C) -> C {
+        C::T(c)
+    }
+}
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum V {
     A(a::V),
     P(p::V),
     B(b::V),
+    T(t::V),
     Ignored,
     Unknown,
 }
@@ -85,6 +97,7 @@ impl Categorized<C> for V {
 #[derive(Default)]
 pub struct State {
     m: HashMap<C, V>,
+    mods: b::Mods,
 }
 impl From<a::V> for V {
     fn from(v: a::V) -> V {
@@ -101,6 +114,11 @@ impl From<b::V> for V {
         V::B(v)
     }
 }
+impl From<t::V> for V {
+    fn from(v: t::V) -> V {
+        V::T(v)
+    }
+}
 impl State {
     pub fn update<'a>(&mut self, v: &'a V) -> (C, &'a V) {
         match v {
@@ -110,10 +128,38 @@ impl State {
                 // TODO use previously found v to update e
                 let c = C::from(v);
                 self.m.insert(c, v.clone());
+                if let V::B(b::V(button, _, mods)) = v {
+                    self.mods = self.mods_after(button, *mods);
+                }
                 (c, v)
             }
         }
     }
+    /// The modifiers held as of the last button event.
+    pub fn mods(&self) -> b::Mods {
+        self.mods
+    }
+    fn is_down(&self, key: b::Key) -> bool {
+        matches!(
+            self.m.get(&C::B(key.into())),
+            Some(V::B(b::V(_, b::State::DOWN, _)))
+        )
+    }
+    /// Backends may or may not count a modifier in its own event, and say nothing of which side is
+    /// held, so a modifier's own keys have the last word on it.
+    fn mods_after(&self, button: &b::C, mut mods: b::Mods) -> b::Mods {
+        let held = |key: fn(b::Side) -> b::Key| {
+            self.is_down(key(b::Side::L)) || self.is_down(key(b::Side::R))
+        };
+        match button {
+            b::C::S(b::Key::Shift(_)) => mods.shift = held(b::Key::Shift),
+            b::C::S(b::Key::Ctrl(_)) => mods.ctrl = held(b::Key::Ctrl),
+            b::C::S(b::Key::Alt(_)) => mods.alt = held(b::Key::Alt),
+            b::C::S(b::Key::Mod(_)) => mods.logo = held(b::Key::Mod),
+            _ => (),
+        }
+        mods
+    }
 }
 impl ValueStore<C, V> for State {
     fn get(&self, c: &C) -> V {
@@ -123,3 +169,48 @@ impl ValueStore<C, V> for State {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn state_tracks_modifiers() {
+        let mut state = State::default();
+        let shift = b::Mods {
+            shift: true,
+            ..b::Mods::NONE
+        };
+        let ctrl_shift = b::Mods {
+            ctrl: true,
+            ..shift
+        };
+        let lshift = b::Key::Shift(b::Side::L).into();
+        let lctrl = b::Key::Ctrl(b::Side::L).into();
+        // Backends may or may not count a modifier in its own event.
+        state.update(&b::V(lshift, b::State::DOWN, b::Mods::NONE).into());
+        assert_eq!(state.mods(), shift);
+        state.update(&b::V(lctrl, b::State::DOWN, ctrl_shift).into());
+        assert_eq!(state.mods(), ctrl_shift);
+        state.update(&b::V(b::C::P(30), b::State::DOWN, ctrl_shift).into());
+        state.update(&b::V(lshift, b::State::UP, ctrl_shift).into());
+        assert_eq!(
+            state.mods(),
+            b::Mods {
+                ctrl: true,
+                ..b::Mods::NONE
+            }
+        );
+        // Anything but a button leaves them be.
+        state.update(&a::V::Scroll(1.).into());
+        assert!(state.mods().ctrl);
+
+        // Letting go of one side keeps the modifier while the other is held.
+        let rctrl = b::Key::Ctrl(b::Side::R).into();
+        state.update(&b::V(rctrl, b::State::DOWN, ctrl_shift).into());
+        state.update(&b::V(lctrl, b::State::UP, ctrl_shift).into());
+        assert!(state.mods().ctrl);
+        state.update(&b::V(rctrl, b::State::UP, ctrl_shift).into());
+        assert!(!state.mods().ctrl);
+    }
+}
@@ -0,0 +1,50 @@
+//! Text as it is typed, after the layout, modifiers and input method have had their say. Use
+//! this for writing text, and buttons for everything else.
+
+#[derive(Debug, Hash, Copy, Clone, PartialEq, Eq)]
+pub enum C {
+    Commit,
+    Preedit,
+}
+impl C {
+    pub fn default_value(&self) -> V {
+        V::default_value_of(self)
+    }
+}
+impl From<&V> for C {
+    fn from(v: &V) -> C {
+        match v {
+            V::Commit(_) => C::Commit,
+            V::Preedit { .. } => C::Preedit,
+        }
+    }
+}
+impl From<V> for C {
+    fn from(v: V) -> C {
+        C::from(&v)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum V {
+    /// Text to insert at the cursor, either typed or finished composing.
+    Commit(String),
+    /// Text an input method is still composing, to show at the cursor until the next preedit or
+    /// commit. Empty once composing stops.
+    Preedit {
+        text: String,
+        /// The byte range of `text` the input method highlights, if any.
+        cursor: Option<(usize, usize)>,
+    },
+}
+impl V {
+    pub fn default_value_of(c: &C) -> Self {
+        match c {
+            C::Commit => V::Commit(String::new()),
+            C::Preedit => V::Preedit {
+                text: String::new(),
+                cursor: None,
+            },
+        }
+    }
+}
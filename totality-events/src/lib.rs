@@ -1,2 +1,5 @@
+pub mod action;
 pub mod cb;
+pub mod combo;
 pub mod hal;
+pub mod record;
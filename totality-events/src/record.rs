@@ -0,0 +1,555 @@
+//! # Record
+//!
+//! Captures the input of a session and plays it back, to reproduce bugs.
+//!
+//! A `Recorder` writes every batch of events that were polled together, along with when they were
+//! polled. It writes as it goes, so a session that crashes is still recorded up to the crash. A
+//! `Player` hands the batches of a `Recording` back one per poll, at their original pace or faster.
+//! Since the batches are the same, so are the `hal::State` transitions and the callbacks fired for
+//! them.
+//!
+//! Recordings are line-based text. Each batch starts with when it was polled, in microseconds since
+//! the first batch:
+//!
+//! ```text
+//! # Input recording, read by events::record::Recording.
+//! @ 0
+//! button w down
+//! cursor_pos 210 96.5
+//! @ 16702
+//! button w up
+//! button scan31 down ctrl+alt
+//! commit "W"
+//! preedit "にほ" 3 6
+//! button close down
+//! ```
+//!
+//! Ignored events are left out, since they never reach the state or any callback.
+
+use std::{
+    fmt,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use na::Vector2;
+
+use crate::{
+    action::{key_name, parse_key},
+    hal::{self, a, b, p, t},
+};
+
+const FLAGS: [(&str, b::Flag); 4] = [
+    ("close", b::Flag::Close),
+    ("cursor_entered", b::Flag::CursorEntered),
+    ("refresh", b::Flag::Refresh),
+    ("focus", b::Flag::Focus),
+];
+
+fn button_name(c: &b::C) -> String {
+    match c {
+        b::C::F(flag) => FLAGS
+            .iter()
+            .find(|(_, f)| f == flag)
+            .map(|(name, _)| name.to_string())
+            .expect("Every flag is named."),
+        b::C::Ignored => "ignored".to_string(),
+        c => key_name(c).unwrap_or_else(|| panic!("{:?} has no name.", c)),
+    }
+}
+
+fn parse_button(name: &str) -> Result<b::C, String> {
+    match FLAGS.iter().find(|(n, _)| *n == name) {
+        Some((_, flag)) => Ok((*flag).into()),
+        None if name == "ignored" => Ok(b::C::Ignored),
+        None => parse_key(name),
+    }
+}
+
+/// Quotes text so that it stays on one line, with its spaces intact.
+fn quote(text: &str) -> String {
+    format!("{:?}", text)
+}
+
+/// Reads text written by `quote`, along with whatever follows it.
+fn unquote(s: &str) -> Result<(String, &str), String> {
+    let mut chars = s.char_indices();
+    if !matches!(chars.next(), Some((_, '"'))) {
+        return Err(format!("{} is not quoted.", s));
+    }
+    let mut text = String::new();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Ok((text, &s[i + 1..])),
+            '\\' => text.push(match chars.next().map(|(_, c)| c) {
+                Some('n') => '\n',
+                Some('r') => '\r',
+                Some('t') => '\t',
+                Some('0') => '\0',
+                Some(c @ ('"' | '\'' | '\\')) => c,
+                Some('u') => {
+                    let code = chars
+                        .by_ref()
+                        .map(|(_, c)| c)
+                        .skip_while(|c| *c == '{')
+                        .take_while(|c| *c != '}')
+                        .collect::<String>();
+                    u32::from_str_radix(&code, 16)
+                        .ok()
+                        .and_then(char::from_u32)
+                        .ok_or_else(|| format!("{} is not a character.", code))?
+                }
+                c => return Err(format!("Unknown escape {:?}.", c)),
+            }),
+            c => text.push(c),
+        }
+    }
+    Err(format!("{} is missing its closing quote.", s))
+}
+
+/// Names the modifiers held, as in `ctrl+shift`.
+fn mods_name(mods: &b::Mods) -> String {
+    [
+        ("shift", mods.shift),
+        ("ctrl", mods.ctrl),
+        ("alt", mods.alt),
+        ("logo", mods.logo),
+    ]
+    .iter()
+    .filter(|(_, held)| *held)
+    .map(|(name, _)| *name)
+    .collect::<Vec<_>>()
+    .join("+")
+}
+
+fn parse_mods(s: &str) -> Result<b::Mods, String> {
+    let mut mods = b::Mods::NONE;
+    for name in s.split('+') {
+        let held = match name {
+            "shift" => &mut mods.shift,
+            "ctrl" => &mut mods.ctrl,
+            "alt" => &mut mods.alt,
+            "logo" => &mut mods.logo,
+            _ => return Err(format!("Unknown modifier {}.", name)),
+        };
+        *held = true;
+    }
+    Ok(mods)
+}
+
+/// Writes an event on a single line, or nothing if it is ignored.
+fn event_line(v: &hal::V) -> Option<String> {
+    let xy = |name: &str, v: &Vector2<f32>| format!("{} {} {}", name, v.x, v.y);
+    Some(match v {
+        hal::V::B(b::V(c, state, mods)) => {
+            let state = if bool::from(*state) { "down" } else { "up" };
+            match *mods {
+                b::Mods::NONE => format!("button {} {}", button_name(c), state),
+                mods => format!("button {} {} {}", button_name(c), state, mods_name(&mods)),
+            }
+        }
+        hal::V::A(a::V::Scroll(d)) => format!("scroll {}", d),
+        hal::V::P(p::V::MousePos(p::PosState(v))) => xy("mouse_pos", v),
+        hal::V::P(p::V::MouseDelta(p::DeltaState(v))) => xy("mouse_delta", v),
+        hal::V::P(p::V::ScreenPos(p::PosState(v))) => xy("screen_pos", v),
+        hal::V::P(p::V::ScreenSz(p::SzState(v))) => xy("screen_sz", v),
+        hal::V::P(p::V::CursorPos(p::PosState(v))) => xy("cursor_pos", v),
+        hal::V::T(t::V::Commit(text)) => format!("commit {}", quote(text)),
+        hal::V::T(t::V::Preedit { text, cursor }) => match cursor {
+            Some((start, end)) => format!("preedit {} {} {}", quote(text), start, end),
+            None => format!("preedit {}", quote(text)),
+        },
+        hal::V::Unknown => "unknown".to_string(),
+        hal::V::Ignored => return None,
+    })
+}
+
+fn parse_event(line: &str) -> Result<hal::V, String> {
+    if let Some(rest) = line.strip_prefix("commit ") {
+        return match unquote(rest.trim_start())? {
+            (text, "") => Ok(t::V::Commit(text).into()),
+            (_, rest) => Err(format!("Cannot read {} after a commit.", rest)),
+        };
+    }
+    if let Some(rest) = line.strip_prefix("preedit ") {
+        let (text, rest) = unquote(rest.trim_start())?;
+        let index = |w: &str| {
+            w.parse::<usize>()
+                .map_err(|_| format!("{} is not an index.", w))
+        };
+        let cursor = match rest.split_whitespace().collect::<Vec<_>>().as_slice() {
+            [] => None,
+            [start, end] => Some((index(start)?, index(end)?)),
+            _ => return Err(format!("Cannot read a cursor from {}.", rest)),
+        };
+        return Ok(t::V::Preedit { text, cursor }.into());
+    }
+    let words = line.split_whitespace().collect::<Vec<_>>();
+    let num = |w: &str| {
+        w.parse::<f32>()
+            .map_err(|_| format!("{} is not a number.", w))
+    };
+    let xy = |x: &str, y: &str| Ok::<_, String>(Vector2::new(num(x)?, num(y)?));
+    Ok(match words.as_slice() {
+        ["button", c, state, mods @ ..] if mods.len() <= 1 => {
+            let state = match *state {
+                "down" => b::State::DOWN,
+                "up" => b::State::UP,
+                _ => return Err(format!("{} is neither down nor up.", state)),
+            };
+            let mods = mods.first().map_or(Ok(b::Mods::NONE), |m| parse_mods(m))?;
+            b::V(parse_button(c)?, state, mods).into()
+        }
+        ["scroll", d] => a::V::Scroll(num(d)?).into(),
+        ["mouse_pos", x, y] => p::V::MousePos(p::PosState(xy(x, y)?)).into(),
+        ["mouse_delta", x, y] => p::V::MouseDelta(p::DeltaState(xy(x, y)?)).into(),
+        ["screen_pos", x, y] => p::V::ScreenPos(p::PosState(xy(x, y)?)).into(),
+        ["screen_sz", x, y] => p::V::ScreenSz(p::SzState(xy(x, y)?)).into(),
+        ["cursor_pos", x, y] => p::V::CursorPos(p::PosState(xy(x, y)?)).into(),
+        ["unknown"] => hal::V::Unknown,
+        _ => return Err(format!("Cannot read an event from {}.", line)),
+    })
+}
+
+/// A line of a recording that could not be read.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordingError {
+    pub line: usize,
+    pub reason: String,
+}
+impl fmt::Display for RecordingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Line {}: {}", self.line, self.reason)
+    }
+}
+impl std::error::Error for RecordingError {}
+
+/// Events that were polled together.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Batch {
+    /// Since the first batch.
+    pub at: Duration,
+    pub events: Vec<hal::V>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Recording {
+    batches: Vec<Batch>,
+}
+impl Recording {
+    pub fn batches(&self) -> &[Batch] {
+        &self.batches
+    }
+    /// When the last batch was polled.
+    pub fn duration(&self) -> Duration {
+        self.batches.last().map_or(Duration::ZERO, |b| b.at)
+    }
+    /// Reads a recording, see the module documentation.
+    pub fn parse(text: &str) -> Result<Recording, RecordingError> {
+        let mut batches: Vec<Batch> = vec![];
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            let parse_err = |reason: String| RecordingError {
+                line: i + 1,
+                reason,
+            };
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some(at) = line.strip_prefix('@') {
+                let at = at
+                    .trim()
+                    .parse()
+                    .map_err(|_| parse_err(format!("{} is not a time.", at.trim())))?;
+                batches.push(Batch {
+                    at: Duration::from_micros(at),
+                    events: vec![],
+                });
+            } else {
+                let v = parse_event(line).map_err(parse_err)?;
+                match batches.last_mut() {
+                    Some(batch) => batch.events.push(v),
+                    None => return Err(parse_err("Events must follow a time.".to_string())),
+                }
+            }
+        }
+        Ok(Recording { batches })
+    }
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Recording> {
+        let text = std::fs::read_to_string(path)?;
+        Recording::parse(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+/// Writes batches of events as they are polled.
+#[derive(Debug)]
+pub struct Recorder<W: Write> {
+    w: W,
+    /// When the first batch was polled.
+    start: Option<Instant>,
+}
+impl Recorder<BufWriter<File>> {
+    /// Records to a new file at `path`.
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Recorder::new(BufWriter::new(File::create(path)?)))
+    }
+}
+impl<W: Write> Recorder<W> {
+    pub fn new(w: W) -> Self {
+        Recorder { w, start: None }
+    }
+    /// Records the events polled together at `now`. Batches with nothing but ignored events are
+    /// left out.
+    pub fn record(&mut self, now: Instant, batch: &[hal::V]) -> io::Result<()> {
+        let lines = batch.iter().filter_map(event_line).collect::<Vec<_>>();
+        if lines.is_empty() {
+            return Ok(());
+        }
+        let start = match self.start {
+            Some(start) => start,
+            None => {
+                writeln!(
+                    self.w,
+                    "# Input recording, read by events::record::Recording."
+                )?;
+                *self.start.insert(now)
+            }
+        };
+        writeln!(
+            self.w,
+            "@ {}",
+            now.saturating_duration_since(start).as_micros()
+        )?;
+        for line in lines {
+            writeln!(self.w, "{}", line)?;
+        }
+        self.w.flush()
+    }
+    pub fn into_inner(self) -> W {
+        self.w
+    }
+}
+
+/// How fast a recording is played back.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Timing {
+    /// As it was recorded.
+    Original,
+    /// Faster by a factor, e.g. 2 for twice as fast. Must be positive.
+    Speed(f32),
+    /// A batch every poll, without waiting.
+    Unpaced,
+}
+
+/// Plays back a recording one batch at a time.
+#[derive(Debug)]
+pub struct Player {
+    recording: Recording,
+    timing: Timing,
+    next: usize,
+    /// When the first batch was played.
+    start: Option<Instant>,
+}
+impl Player {
+    pub fn new(recording: Recording, timing: Timing) -> Self {
+        Player {
+            recording,
+            timing,
+            next: 0,
+            start: None,
+        }
+    }
+    /// The next batch, once it is due at `now`. Playback starts with the first poll.
+    ///
+    /// Batches come one per poll even when several are due, so callbacks see them just as they
+    /// were recorded.
+    pub fn poll(&mut self, now: Instant) -> Option<&[hal::V]> {
+        let batch = self.recording.batches.get(self.next)?;
+        let start = *self.start.get_or_insert(now);
+        let due = match self.timing {
+            Timing::Original => batch.at,
+            // Through nanoseconds, since going through seconds would round.
+            Timing::Speed(speed) => {
+                Duration::from_nanos((batch.at.as_nanos() as f64 / speed as f64) as u64)
+            }
+            Timing::Unpaced => Duration::ZERO,
+        };
+        if now.saturating_duration_since(start) < due {
+            return None;
+        }
+        self.next += 1;
+        Some(&batch.events)
+    }
+    /// Whether every batch was played.
+    pub fn is_done(&self) -> bool {
+        self.next == self.recording.batches.len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use th::clock::{Clock, ManualClock};
+
+    use super::*;
+    use crate::cb::{self, CBFn, Propagation, CB};
+
+    fn session() -> Vec<Vec<hal::V>> {
+        let alt = b::Mods {
+            alt: true,
+            ..b::Mods::NONE
+        };
+        vec![
+            vec![
+                b::V('w'.into(), b::State::DOWN, b::Mods::NONE).into(),
+                p::V::CursorPos(p::PosState(Vector2::new(210., 96.5))).into(),
+                hal::V::Ignored,
+            ],
+            vec![hal::V::Ignored],
+            vec![
+                b::V(
+                    b::Key::Alt(b::Side::L).into(),
+                    b::State::DOWN,
+                    b::Mods::NONE,
+                )
+                .into(),
+                b::V('\t'.into(), b::State::DOWN, alt).into(),
+                b::V(b::C::P(15), b::State::DOWN, alt).into(),
+                a::V::Scroll(-0.1).into(),
+            ],
+            vec![
+                b::V('w'.into(), b::State::UP, b::Mods::NONE).into(),
+                b::V('w'.into(), b::State::DOWN, b::Mods::NONE).into(),
+                t::V::Commit(" \"W\"\\\n".to_string()).into(),
+                t::V::Preedit {
+                    text: "にほ\u{301}".to_string(),
+                    cursor: Some((3, 6)),
+                }
+                .into(),
+                t::V::Preedit {
+                    text: String::new(),
+                    cursor: None,
+                }
+                .into(),
+                b::V(b::Flag::Close.into(), b::State::DOWN, b::Mods::NONE).into(),
+            ],
+        ]
+    }
+
+    /// Runs batches through a callback manager the way `io::Manager` does, noting every callback.
+    fn fire(batches: impl Iterator<Item = (Duration, Vec<hal::V>)>) -> Vec<(hal::V, Duration)> {
+        let clock = ManualClock::new();
+        let start = clock.now();
+        let mut man = cb::Manager::with_clock(clock.clone());
+        let mut state = hal::State::default();
+        let fired = Arc::new(Mutex::new(vec![]));
+        let fired_cb = fired.clone();
+        let f: Arc<Mutex<dyn CBFn<hal::State, hal::V, hal::C>>> = Arc::new(Mutex::new(
+            move |_: &hal::State, v: &hal::V, _: &Instant, c: &Instant| {
+                fired_cb.lock().unwrap().push((v.clone(), *c - start));
+                Propagation::Continue
+            },
+        ));
+        for c in ['w', '\t'] {
+            man.register(
+                CB::new(b::C::from(c).into(), Arc::downgrade(&f))
+                    .filtered(|before: &hal::V, after: &hal::V| before != after),
+            );
+        }
+        man.register(CB::new(a::C::Scroll.into(), Arc::downgrade(&f)));
+        for (at, mut vv) in batches {
+            clock.advance(at - (clock.now() - start));
+            man.fire_and_clean_listing(&state, &mut vv);
+            for v in vv.drain(..) {
+                state.update(&v);
+            }
+        }
+        let fired = fired.lock().unwrap().clone();
+        fired
+    }
+
+    #[test]
+    fn recordings_round_trip() {
+        let start = Instant::now();
+        let mut rec = Recorder::new(vec![]);
+        for (i, batch) in session().iter().enumerate() {
+            let at = start + Duration::from_millis(16 * i as u64 + 3);
+            rec.record(at, batch).unwrap();
+        }
+        let text = String::from_utf8(rec.into_inner()).unwrap();
+        assert!(text.contains("\n@ 32000\nbutton lalt down\nbutton u+0009 down alt\nbutton scan15 down alt\nscroll -0.1\n"));
+
+        let recording = Recording::parse(&text).unwrap();
+        let ats = recording.batches().iter().map(|b| b.at).collect::<Vec<_>>();
+        let ms = Duration::from_millis;
+        assert_eq!(ats, vec![ms(0), ms(32), ms(48)]);
+        assert_eq!(recording.batches()[0].events, session()[0][..2]);
+        assert_eq!(recording.batches()[1].events, session()[2]);
+        assert_eq!(recording.batches()[2].events, session()[3]);
+        assert!(text.contains("\ncommit \" \\\"W\\\"\\\\\\n\"\n"));
+        assert_eq!(recording.duration(), ms(48));
+
+        assert_eq!(
+            Recording::parse("# Nothing yet.\nbutton w down"),
+            Err(RecordingError {
+                line: 2,
+                reason: "Events must follow a time.".to_string()
+            })
+        );
+        assert!(Recording::parse("@ 0\nbutton w sideways").is_err());
+        assert!(Recording::parse("@ 0\nbutton w down hyper").is_err());
+    }
+
+    #[test]
+    fn replays_fire_the_same_callbacks() {
+        let ms = Duration::from_millis;
+        let batches = session()
+            .into_iter()
+            .enumerate()
+            .map(|(i, vv)| (ms(16 * i as u64), vv))
+            .collect::<Vec<_>>();
+        let mut rec = Recorder::new(vec![]);
+        let start = Instant::now();
+        for (at, vv) in batches.iter() {
+            rec.record(start + *at, vv).unwrap();
+        }
+        let recording = Recording::parse(&String::from_utf8(rec.into_inner()).unwrap()).unwrap();
+        let recorded = recording.batches().iter().map(|b| b.at).collect::<Vec<_>>();
+
+        let mut player = Player::new(recording, Timing::Unpaced);
+        let mut replayed = vec![];
+        while let Some(vv) = player.poll(start) {
+            replayed.push(vv.to_vec());
+        }
+        assert!(player.is_done());
+        let original = fire(batches.into_iter());
+        assert_eq!(original.len(), 5);
+        assert_eq!(fire(recorded.into_iter().zip(replayed)), original);
+    }
+
+    #[test]
+    fn batches_are_played_when_due() {
+        let recording =
+            Recording::parse("@ 0\nscroll 1\n@ 100000\nscroll 2\n@ 150000\nscroll 3").unwrap();
+        let start = Instant::now();
+        let ms = |n| start + Duration::from_millis(n);
+
+        let mut player = Player::new(recording.clone(), Timing::Original);
+        assert_eq!(player.poll(start), Some(&[a::V::Scroll(1.).into()][..]));
+        assert_eq!(player.poll(ms(99)), None);
+        assert_eq!(player.poll(ms(200)), Some(&[a::V::Scroll(2.).into()][..]));
+        // Batches that are due together still come one at a time.
+        assert_eq!(player.poll(ms(200)), Some(&[a::V::Scroll(3.).into()][..]));
+        assert_eq!(player.poll(ms(300)), None);
+        assert!(player.is_done());
+
+        let mut player = Player::new(recording, Timing::Speed(2.));
+        assert!(player.poll(start).is_some());
+        assert_eq!(player.poll(ms(49)), None);
+        assert!(player.poll(ms(50)).is_some());
+        assert!(player.poll(ms(75)).is_some());
+    }
+}
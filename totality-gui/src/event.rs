@@ -10,4 +10,6 @@ pub enum E {
     Dropped(String),
     Scroll,
     Key(e::b::Key),
+    /// Text typed while focused, or being composed.
+    Text(e::t::V),
 }
@@ -2,11 +2,15 @@
 mod source;
 
 use internal_events::cb;
+pub use internal_events::cb::Propagation;
 pub use internal_events::hal as e;
+pub use internal_events::record::Timing;
 
 // std dependencies
 use std::{
+    io,
     option::Option,
+    path::Path,
     result::Result,
     sync::{
         mpsc::{channel, Receiver, RecvError, SendError, Sender},
@@ -14,10 +18,12 @@ use std::{
     },
 };
 // internal dependencies
+use self::source::record::{Recorded, Replayed};
 use self::source::WindowSpecs;
 use self::source::IO;
 // workspace internal dependencies
-use th::killable_thread::KillableThread;
+use internal_events::record::{Player, Recorder, Recording};
+use th::{control::Control, killable_thread::KillableThread};
 // external dependencies
 use e::*;
 use winit::Window;
@@ -35,6 +41,7 @@ pub enum RegErr<T> {
 }
 
 pub type CB = cb::CB<State, V, C>;
+type RegResult = Result<cb::RegResponse<State, V, C>, RegErr<cb::RegRequest<State, V, C>>>;
 
 pub struct Manager {
     registrar: Twinned<
@@ -43,23 +50,29 @@ pub struct Manager {
             Receiver<cb::RegResponse<State, V, C>>,
         )>,
     >, // triggered periodically
-    pollers: Option<Twinned<KillableThread<(), ()>>>, // thread handle of polling thread
+    pollers: Option<Twinned<KillableThread<Control<()>, ()>>>, // thread handle of polling thread
     pub win: Arc<self::source::back::Window>,         // output is just this part
 }
 impl Manager {
     #[inline(always)]
-    fn start_event_thread(
+    fn start_event_thread<I, F>(
+        make_io: F,
         s_m: Arc<Mutex<e::State>>,
         // window creation needs to happen here
         win_tx: Sender<Window>,
         req_rx: Receiver<cb::RegRequest<State, V, C>>,
         res_tx: Sender<cb::RegResponse<State, V, C>>,
-    ) -> KillableThread<(), ()> {
+    ) -> KillableThread<Control<()>, ()>
+    where
+        I: IO<Window = Window>,
+        // The window has to be made on the thread that polls it.
+        F: FnOnce() -> I + Send + 'static,
+    {
         th::create_kt!(
             "Immediate Event Loop",
             {
                 let mut man = cb::Manager::new();
-                let mut io = self::source::back::IO::new();
+                let mut io = make_io();
                 io.init();
                 if let Err(_) = win_tx.send(io.create_window(WindowSpecs::new("Tracer"))) {
                     panic!("Could not send created window back to main thread.");
@@ -107,7 +120,7 @@ impl Manager {
         s_m: Arc<Mutex<State>>,
         req_rx: Receiver<cb::RegRequest<State, V, C>>,
         res_tx: Sender<cb::RegResponse<State, V, C>>,
-    ) -> KillableThread<(), ()> {
+    ) -> KillableThread<Control<()>, ()> {
         th::create_rated_kt!(
             60,
             "Periodic Event Loop",
@@ -143,6 +156,24 @@ impl Manager {
         .expect("Could not start event thread.... Welp I'm out.")
     }
     pub fn new() -> Manager {
+        Self::with_io(self::source::back::IO::new)
+    }
+    /// Records all input to a file at `path`, to be played back with `replaying`.
+    pub fn recording(path: impl AsRef<Path>) -> io::Result<Manager> {
+        let rec = Recorder::create(path)?;
+        Ok(Self::with_io(move || Recorded::new(self::source::back::IO::new(), rec)))
+    }
+    /// Plays back input recorded to `path` instead of polling it. Input comes through as usual
+    /// once the recording is over.
+    pub fn replaying(path: impl AsRef<Path>, timing: Timing) -> io::Result<Manager> {
+        let player = Player::new(Recording::load(path)?, timing);
+        Ok(Self::with_io(move || Replayed::new(self::source::back::IO::new(), player)))
+    }
+    fn with_io<I, F>(make_io: F) -> Manager
+    where
+        I: IO<Window = Window>,
+        F: FnOnce() -> I + Send + 'static,
+    {
         let (win_tx, win_rx) = channel();
         let (imm_tx, imm_rx) = channel();
         let (w_imm_tx, w_imm_rx) = channel();
@@ -155,44 +186,54 @@ impl Manager {
                 imm: Mutex::new((imm_tx, w_imm_rx)),
             },
             pollers: Option::Some(Twinned {
-                imm: Self::start_event_thread(curr.clone(), win_tx, imm_rx, w_imm_tx),
+                imm: Self::start_event_thread(make_io, curr.clone(), win_tx, imm_rx, w_imm_tx),
                 per: Self::start_periodic_thread(curr, per_rx, w_per_tx),
             }),
             win: Arc::new(win_rx.recv().unwrap()),
         }
     }
-    pub fn reg_imm<F: cb::CBFn<State, V, C>>(
+    pub fn reg_imm<F: cb::CBFn<State, V, C>>(&self, c: C, f: Arc<Mutex<F>>) -> RegResult {
+        self.reg_imm_with_priority(c, 0, f)
+    }
+    /// Registers a callback that fires before those of lower priority, and can consume events
+    /// to keep them from firing.
+    pub fn reg_imm_with_priority<F: cb::CBFn<State, V, C>>(
         &self,
         c: C,
+        priority: i32,
         f: Arc<Mutex<F>>,
-    ) -> Result<cb::RegResponse<State, V, C>, RegErr<cb::RegRequest<State, V, C>>> {
-        let cb = Arc::downgrade(&f);
-        Self::send_to_manager(
-            &self.registrar.imm,
-            cb::RegRequest::Register(c, vec![cb::CB::new(c, cb)]),
-        )
+    ) -> RegResult {
+        let f = Arc::downgrade(&f);
+        let cb: Weak<Mutex<dyn cb::CBFn<State, V, C>>> = f;
+        self.reg_imm_cb(cb::CB::with_priority(c, priority, cb))
+    }
+    /// Registers a callback put together by hand, e.g. with a filter from `e::change::Ch`. The
+    /// callback lives as long as the `Arc` it was made from.
+    pub fn reg_imm_cb(&self, cb: CB) -> RegResult {
+        let c = cb.category();
+        Self::send_to_manager(&self.registrar.imm, cb::RegRequest::Register(c, vec![cb]))
     }
-    pub fn reg_per<F: cb::CBFn<State, V, C>>(
+    pub fn reg_per<F: cb::CBFn<State, V, C>>(&self, c: C, f: Arc<Mutex<F>>) -> RegResult {
+        self.reg_per_with_priority(c, 0, f)
+    }
+    pub fn reg_per_with_priority<F: cb::CBFn<State, V, C>>(
         &self,
         c: C,
+        priority: i32,
         f: Arc<Mutex<F>>,
-    ) -> Result<cb::RegResponse<State, V, C>, RegErr<cb::RegRequest<State, V, C>>> {
-        let cb = Arc::downgrade(&f);
-        Self::send_to_manager(
-            &self.registrar.per,
-            cb::RegRequest::Register(c, vec![cb::CB::new(c, cb)]),
-        )
+    ) -> RegResult {
+        let f = Arc::downgrade(&f);
+        let cb: Weak<Mutex<dyn cb::CBFn<State, V, C>>> = f;
+        self.reg_per_cb(cb::CB::with_priority(c, priority, cb))
     }
-    pub fn unreg_imm(
-        &self,
-        cb: Weak<Mutex<cb::CB<State, V, C>>>,
-    ) -> Result<cb::RegResponse<State, V, C>, RegErr<cb::RegRequest<State, V, C>>> {
+    pub fn reg_per_cb(&self, cb: CB) -> RegResult {
+        let c = cb.category();
+        Self::send_to_manager(&self.registrar.per, cb::RegRequest::Register(c, vec![cb]))
+    }
+    pub fn unreg_imm(&self, cb: Weak<Mutex<cb::CB<State, V, C>>>) -> RegResult {
         Self::send_to_manager(&self.registrar.imm, cb::RegRequest::Unregister(vec![cb]))
     }
-    pub fn unreg_per(
-        &self,
-        cb: Weak<Mutex<cb::CB<State, V, C>>>,
-    ) -> Result<cb::RegResponse<State, V, C>, RegErr<cb::RegRequest<State, V, C>>> {
+    pub fn unreg_per(&self, cb: Weak<Mutex<cb::CB<State, V, C>>>) -> RegResult {
         Self::send_to_manager(&self.registrar.per, cb::RegRequest::Unregister(vec![cb]))
     }
     fn send_to_manager(
@@ -201,7 +242,7 @@ impl Manager {
             Receiver<cb::RegResponse<State, V, C>>,
         )>,
         req: cb::RegRequest<State, V, C>,
-    ) -> Result<cb::RegResponse<State, V, C>, RegErr<cb::RegRequest<State, V, C>>> {
+    ) -> RegResult {
         match trx.lock() {
             Ok(guard) => {
                 let (ref tx, ref rx) = *guard;
@@ -224,6 +265,10 @@ impl Drop for Manager {
     }
 }
 
+/// Wraps a block in a callback, ready to be registered.
+///
+/// The callback lets the event through to callbacks of lower priority, unless the block returns
+/// `Propagation::Consumed` early.
 #[macro_export]
 macro_rules! cb_arc {
     ( $name:literal, $v:ident, $s:ident, $l_t:ident, $c_t:ident, {$($head:tt)*} ) => {
@@ -234,6 +279,7 @@ macro_rules! cb_arc {
                     trace!("{} handler fired with {:?}", $name, $v);
                     $($head)*;
                     trace!("{} handler completed.", $name);
+                    $crate::Propagation::Continue
                 }
             ));
             arc
@@ -247,6 +293,7 @@ macro_rules! cb_arc {
                     trace!("{} handler fired with {:?}", $name, $v);
                     $($head)*;
                     trace!("{} handler completed.", $name);
+                    $crate::Propagation::Continue
                 }
             ));
             arc
@@ -260,6 +307,7 @@ macro_rules! cb_arc {
                     trace!("{} handler fired with {:?}", $name, v);
                     $($head)*;
                     trace!("{} handler completed.", $name);
+                    $crate::Propagation::Continue
                 }
             ));
             arc
@@ -273,6 +321,7 @@ macro_rules! cb_arc {
                     trace!("{} handler fired with {:?}", $name, v);
                     $($head)*;
                     trace!("{} handler completed.", $name);
+                    $crate::Propagation::Continue
                 }
             ));
             arc
@@ -1,3 +1,4 @@
+pub mod record;
 pub mod winit_convert;
 pub use self::winit_convert as back;
 
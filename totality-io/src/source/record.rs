@@ -0,0 +1,162 @@
+use std::{cell::RefCell, fs::File, io::BufWriter, time::Instant};
+
+use super::{WindowSpecs, IO};
+use internal_events::{
+    hal as e,
+    record::{Player, Recorder},
+};
+
+use log::{error, info};
+
+/// Records everything polled from another `IO`.
+pub struct Recorded<I: IO> {
+    io: I,
+    /// Dropped once writing fails.
+    rec: RefCell<Option<Recorder<BufWriter<File>>>>,
+}
+impl<I: IO> Recorded<I> {
+    pub fn new(io: I, rec: Recorder<BufWriter<File>>) -> Self {
+        Self {
+            io,
+            rec: RefCell::new(Some(rec)),
+        }
+    }
+}
+impl<I: IO> IO for Recorded<I> {
+    type Window = I::Window;
+    type Event = I::Event;
+    fn init(&mut self) {
+        self.io.init()
+    }
+    fn next_events(&self, buf: &mut Vec<e::V>) {
+        let polled = buf.len();
+        self.io.next_events(buf);
+        let mut rec = self.rec.borrow_mut();
+        if let Some(Err(err)) = rec
+            .as_mut()
+            .map(|rec| rec.record(Instant::now(), &buf[polled..]))
+        {
+            error!(
+                "Could not record input, so the recording ends here: {}",
+                err
+            );
+            *rec = None;
+        }
+    }
+    fn create_window(&self, specs: WindowSpecs) -> Self::Window {
+        self.io.create_window(specs)
+    }
+    fn to_v(e: Self::Event) -> e::V {
+        I::to_v(e)
+    }
+}
+
+/// Plays a recording back in place of what another `IO` polls.
+///
+/// The other `IO` still makes the window. Its input is thrown away until the recording is over,
+/// then comes through as usual. Events about the window itself, like it closing or being resized,
+/// come through all along.
+pub struct Replayed<I: IO> {
+    io: I,
+    player: RefCell<Player>,
+}
+impl<I: IO> Replayed<I> {
+    pub fn new(io: I, player: Player) -> Self {
+        Self {
+            io,
+            player: RefCell::new(player),
+        }
+    }
+}
+impl<I: IO> IO for Replayed<I> {
+    type Window = I::Window;
+    type Event = I::Event;
+    fn init(&mut self) {
+        self.io.init()
+    }
+    fn next_events(&self, buf: &mut Vec<e::V>) {
+        let mut player = self.player.borrow_mut();
+        if player.is_done() {
+            return self.io.next_events(buf);
+        }
+        // Still polled, to keep the window responsive.
+        let mut live = Vec::new();
+        self.io.next_events(&mut live);
+        buf.extend(live.into_iter().filter(is_window_event));
+        if let Some(vv) = player.poll(Instant::now()) {
+            buf.extend_from_slice(vv);
+            if player.is_done() {
+                info!("Replay finished, passing input through again.");
+            }
+        }
+    }
+    fn create_window(&self, specs: WindowSpecs) -> Self::Window {
+        self.io.create_window(specs)
+    }
+    fn to_v(e: Self::Event) -> e::V {
+        I::to_v(e)
+    }
+}
+
+/// Whether `v` is about the window rather than input, so that a replay doesn't stand in for it.
+fn is_window_event(v: &e::V) -> bool {
+    matches!(
+        v,
+        e::V::B(e::b::V(e::b::C::F(_), ..))
+            | e::V::P(e::p::V::ScreenPos(_))
+            | e::V::P(e::p::V::ScreenSz(_))
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use internal_events::{
+        hal::{b, p},
+        record::{Recording, Timing},
+    };
+
+    use super::*;
+
+    /// Polls the same events every time.
+    struct Live(Vec<e::V>);
+    impl IO for Live {
+        type Window = ();
+        type Event = e::V;
+        fn init(&mut self) {}
+        fn next_events(&self, buf: &mut Vec<e::V>) {
+            buf.extend_from_slice(&self.0);
+        }
+        fn create_window(&self, _: WindowSpecs) {}
+        fn to_v(e: e::V) -> e::V {
+            e
+        }
+    }
+
+    #[test]
+    fn window_events_pass_through_replays() {
+        let close: e::V = b::V(b::Flag::Close.into(), b::State::DOWN, b::Mods::NONE).into();
+        let resize = e::V::P(p::V::default_value_of(&p::C::ScreenSz));
+        let live = Live(vec![
+            b::V(b::C::A('s'), b::State::DOWN, b::Mods::NONE).into(),
+            close.clone(),
+            resize.clone(),
+        ]);
+        // The second batch is due long after the test is over, so the replay is never done.
+        let recording =
+            Recording::parse("@ 0\nbutton w down\n@ 3600000000\nbutton w up\n").unwrap();
+        let io = Replayed::new(live, Player::new(recording, Timing::Original));
+        let mut buf = vec![];
+        io.next_events(&mut buf);
+        assert_eq!(
+            buf,
+            vec![
+                close.clone(),
+                resize.clone(),
+                b::V(b::C::A('w'), b::State::DOWN, b::Mods::NONE).into(),
+            ]
+        );
+        buf.clear();
+        io.next_events(&mut buf);
+        assert_eq!(buf, vec![close, resize]);
+    }
+}
@@ -5,7 +5,7 @@ use internal_events::hal as e;
 
 use log::trace;
 use winit::{
-    dpi::*, DeviceEvent, Event, EventsLoop, KeyboardInput, VirtualKeyCode,
+    dpi::*, DeviceEvent, Event, EventsLoop, KeyboardInput, ModifiersState, VirtualKeyCode,
     WindowBuilder, WindowEvent,
 };
 
@@ -28,7 +28,18 @@ impl super::IO for IO {
     }
     fn next_events(&self, buf: &mut Vec<e::V>) {
         if let Some(ref e_loop) = self.e_loop {
-            e_loop.borrow_mut().poll_events(|e| buf.push(Self::to_v(e)))
+            e_loop.borrow_mut().poll_events(|e| {
+                // Keys come through as what the layout makes of them, then as where they are.
+                let physical = match e {
+                    Event::WindowEvent {
+                        event: WindowEvent::KeyboardInput { input: k, .. },
+                        ..
+                    } => Some(physical_key(k)),
+                    _ => None,
+                };
+                buf.push(Self::to_v(e));
+                buf.extend(physical);
+            })
         }
     }
     fn create_window(&self, specs: WindowSpecs) -> Self::Window {
@@ -48,7 +59,12 @@ impl super::IO for IO {
             Event::WindowEvent {
                 event: WindowEvent::CloseRequested,
                 ..
-            } => e::b::V(e::b::Flag::Close.into(), e::b::State::DOWN).into(),
+            } => e::b::V(
+                e::b::Flag::Close.into(),
+                e::b::State::DOWN,
+                e::b::Mods::NONE,
+            )
+            .into(),
             Event::WindowEvent {
                 event: WindowEvent::Resized(LogicalSize { width, height }),
                 ..
@@ -57,15 +73,30 @@ impl super::IO for IO {
             Event::WindowEvent {
                 event: WindowEvent::Refresh,
                 ..
-            } => e::b::V(e::b::Flag::Refresh.into(), e::b::State::DOWN).into(),
+            } => e::b::V(
+                e::b::Flag::Refresh.into(),
+                e::b::State::DOWN,
+                e::b::Mods::NONE,
+            )
+            .into(),
             Event::WindowEvent {
                 event: WindowEvent::CursorEntered { .. },
                 ..
-            } => e::b::V(e::b::Flag::CursorEntered.into(), e::b::State::DOWN).into(),
+            } => e::b::V(
+                e::b::Flag::CursorEntered.into(),
+                e::b::State::DOWN,
+                e::b::Mods::NONE,
+            )
+            .into(),
             Event::WindowEvent {
                 event: WindowEvent::CursorLeft { .. },
                 ..
-            } => e::b::V(e::b::Flag::CursorEntered.into(), e::b::State::DOWN).into(),
+            } => e::b::V(
+                e::b::Flag::CursorEntered.into(),
+                e::b::State::DOWN,
+                e::b::Mods::NONE,
+            )
+            .into(),
             Event::WindowEvent {
                 event: WindowEvent::CursorMoved { position: p, .. },
                 ..
@@ -73,7 +104,12 @@ impl super::IO for IO {
             Event::WindowEvent {
                 event: WindowEvent::Focused(_),
                 ..
-            } => e::b::V(e::b::Flag::Focus.into(), e::b::State::DOWN).into(),
+            } => e::b::V(
+                e::b::Flag::Focus.into(),
+                e::b::State::DOWN,
+                e::b::Mods::NONE,
+            )
+            .into(),
             Event::DeviceEvent {
                 // Ignored since it's actually a duplicate of the below.
                 event: DeviceEvent::Key(_),
@@ -96,9 +132,17 @@ impl super::IO for IO {
                 ..
             } => e::V::Ignored,
             Event::WindowEvent {
-                event: WindowEvent::ReceivedCharacter(_),
+                event: WindowEvent::ReceivedCharacter(c),
                 ..
-            } => e::V::Ignored,
+            } => {
+                // Backspace, enter and the like come through as keys already. This version of
+                // winit reports characters once composed, so there are no preedits to send.
+                if c.is_control() {
+                    e::V::Ignored
+                } else {
+                    e::t::V::Commit(c.to_string()).into()
+                }
+            }
             Event::Awakened => e::V::Ignored,
             _ => unimplemented!("Cannot cast {:?} to C.", e),
         };
@@ -108,18 +152,33 @@ impl super::IO for IO {
 }
 
 fn parse_keyboard(k: KeyboardInput) -> e::V {
-    if let Some(vk) = k.virtual_keycode {
-        match map_vk(vk) {
-            e::b::C::Ignored => e::V::Ignored,
-            c @ _ => e::b::V(
-                c,
-                e::b::State::from(k.state == winit::ElementState::Pressed),
-            )
-            .into(),
-        }
-    } else {
-        // TODO e::b::V(map_sc(k.scancode), e::b::State::from(k.state == winit::ElementState::Pressed)).into()
-        e::V::Ignored
+    // Keys without a virtual keycode still come through `physical_key`.
+    match k.virtual_keycode.map(map_vk) {
+        None | Some(e::b::C::Ignored) => e::V::Ignored,
+        Some(c) => e::b::V(
+            c,
+            e::b::State::from(k.state == winit::ElementState::Pressed),
+            map_mods(k.modifiers),
+        )
+        .into(),
+    }
+}
+
+fn physical_key(k: KeyboardInput) -> e::V {
+    e::b::V(
+        e::b::C::P(k.scancode),
+        e::b::State::from(k.state == winit::ElementState::Pressed),
+        map_mods(k.modifiers),
+    )
+    .into()
+}
+
+fn map_mods(m: ModifiersState) -> e::b::Mods {
+    e::b::Mods {
+        shift: m.shift,
+        ctrl: m.ctrl,
+        alt: m.alt,
+        logo: m.logo,
     }
 }
 
@@ -235,7 +294,7 @@ fn map_vk(vk: VirtualKeyCode) -> e::b::C {
         // VirtualKeyCode::Ax,
         VirtualKeyCode::Backslash => e::b::C::A('\\'),
         // VirtualKeyCode::Calculator,
-        // VirtualKeyCode::Capital,
+        VirtualKeyCode::Capital => e::b::Key::CapLk.into(),
         VirtualKeyCode::Colon => e::b::C::A(':'),
         VirtualKeyCode::Comma => e::b::C::A(','),
         // VirtualKeyCode::Convert,
@@ -247,9 +306,9 @@ fn map_vk(vk: VirtualKeyCode) -> e::b::C {
         // VirtualKeyCode::Kanji,
         VirtualKeyCode::LAlt => e::b::Key::Alt(e::b::Side::L).into(),
         VirtualKeyCode::LBracket => e::b::C::A('['),
-        VirtualKeyCode::LControl => e::b::Key::Alt(e::b::Side::L).into(),
-        VirtualKeyCode::LShift => e::b::Key::Alt(e::b::Side::L).into(),
-        VirtualKeyCode::LWin => e::b::Key::Alt(e::b::Side::L).into(),
+        VirtualKeyCode::LControl => e::b::Key::Ctrl(e::b::Side::L).into(),
+        VirtualKeyCode::LShift => e::b::Key::Shift(e::b::Side::L).into(),
+        VirtualKeyCode::LWin => e::b::Key::Mod(e::b::Side::L).into(),
         // VirtualKeyCode::Mail,
         // VirtualKeyCode::MediaSelect,
         // VirtualKeyCode::MediaStop,
@@ -262,7 +321,7 @@ fn map_vk(vk: VirtualKeyCode) -> e::b::C {
         // VirtualKeyCode::NextTrack,
         // VirtualKeyCode::NoConvert,
         VirtualKeyCode::NumpadComma => e::b::C::A(','),
-        VirtualKeyCode::NumpadEnter => e::b::Key::Alt(e::b::Side::L).into(),
+        VirtualKeyCode::NumpadEnter => e::b::Key::Enter.into(),
         VirtualKeyCode::NumpadEquals => e::b::C::A('='),
         // VirtualKeyCode::OEM102,
         VirtualKeyCode::Period => e::b::C::A('.'),
@@ -280,7 +339,7 @@ fn map_vk(vk: VirtualKeyCode) -> e::b::C {
         // VirtualKeyCode::Stop,
         VirtualKeyCode::Subtract => e::b::C::A('-'),
         // VirtualKeyCode::Sysrq,
-        VirtualKeyCode::Tab => e::b::C::A('\t'),
+        VirtualKeyCode::Tab => e::b::Key::Tab.into(),
         // VirtualKeyCode::Underline,
         // VirtualKeyCode::Unlabeled,
         // VirtualKeyCode::VolumeDown,
@@ -303,3 +362,202 @@ fn map_vk(vk: VirtualKeyCode) -> e::b::C {
 fn as_vec(p: LogicalPosition) -> na::Vector2<f32> {
     na::Vector2::new(p.x as f32, p.y as f32)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use internal_events::hal::b::{Key, Side, C};
+
+    /// Every key the backend handles, and what it comes through as.
+    const KEYS: &[(VirtualKeyCode, C)] = &[
+        (VirtualKeyCode::Key1, C::A('1')),
+        (VirtualKeyCode::Key2, C::A('2')),
+        (VirtualKeyCode::Key3, C::A('3')),
+        (VirtualKeyCode::Key4, C::A('4')),
+        (VirtualKeyCode::Key5, C::A('5')),
+        (VirtualKeyCode::Key6, C::A('6')),
+        (VirtualKeyCode::Key7, C::A('7')),
+        (VirtualKeyCode::Key8, C::A('8')),
+        (VirtualKeyCode::Key9, C::A('9')),
+        (VirtualKeyCode::Key0, C::A('0')),
+        (VirtualKeyCode::A, C::A('a')),
+        (VirtualKeyCode::B, C::A('b')),
+        (VirtualKeyCode::C, C::A('c')),
+        (VirtualKeyCode::D, C::A('d')),
+        (VirtualKeyCode::E, C::A('e')),
+        (VirtualKeyCode::F, C::A('f')),
+        (VirtualKeyCode::G, C::A('g')),
+        (VirtualKeyCode::H, C::A('h')),
+        (VirtualKeyCode::I, C::A('i')),
+        (VirtualKeyCode::J, C::A('j')),
+        (VirtualKeyCode::K, C::A('k')),
+        (VirtualKeyCode::L, C::A('l')),
+        (VirtualKeyCode::M, C::A('m')),
+        (VirtualKeyCode::N, C::A('n')),
+        (VirtualKeyCode::O, C::A('o')),
+        (VirtualKeyCode::P, C::A('p')),
+        (VirtualKeyCode::Q, C::A('q')),
+        (VirtualKeyCode::R, C::A('r')),
+        (VirtualKeyCode::S, C::A('s')),
+        (VirtualKeyCode::T, C::A('t')),
+        (VirtualKeyCode::U, C::A('u')),
+        (VirtualKeyCode::V, C::A('v')),
+        (VirtualKeyCode::W, C::A('w')),
+        (VirtualKeyCode::X, C::A('x')),
+        (VirtualKeyCode::Y, C::A('y')),
+        (VirtualKeyCode::Z, C::A('z')),
+        (VirtualKeyCode::Escape, C::S(Key::Esc)),
+        (VirtualKeyCode::F1, C::S(Key::F(1))),
+        (VirtualKeyCode::F2, C::S(Key::F(2))),
+        (VirtualKeyCode::F3, C::S(Key::F(3))),
+        (VirtualKeyCode::F4, C::S(Key::F(4))),
+        (VirtualKeyCode::F5, C::S(Key::F(5))),
+        (VirtualKeyCode::F6, C::S(Key::F(6))),
+        (VirtualKeyCode::F7, C::S(Key::F(7))),
+        (VirtualKeyCode::F8, C::S(Key::F(8))),
+        (VirtualKeyCode::F9, C::S(Key::F(9))),
+        (VirtualKeyCode::F10, C::S(Key::F(10))),
+        (VirtualKeyCode::F11, C::S(Key::F(11))),
+        (VirtualKeyCode::F12, C::S(Key::F(12))),
+        (VirtualKeyCode::F13, C::S(Key::F(13))),
+        (VirtualKeyCode::F14, C::S(Key::F(14))),
+        (VirtualKeyCode::F15, C::S(Key::F(15))),
+        (VirtualKeyCode::F16, C::S(Key::F(16))),
+        (VirtualKeyCode::F17, C::S(Key::F(17))),
+        (VirtualKeyCode::F18, C::S(Key::F(18))),
+        (VirtualKeyCode::F19, C::S(Key::F(19))),
+        (VirtualKeyCode::F20, C::S(Key::F(20))),
+        (VirtualKeyCode::F21, C::S(Key::F(21))),
+        (VirtualKeyCode::F22, C::S(Key::F(22))),
+        (VirtualKeyCode::F23, C::S(Key::F(23))),
+        (VirtualKeyCode::F24, C::S(Key::F(24))),
+        (VirtualKeyCode::Snapshot, C::S(Key::PrintScreen)),
+        (VirtualKeyCode::Scroll, C::S(Key::ScrLk)),
+        (VirtualKeyCode::Pause, C::S(Key::Pause)),
+        (VirtualKeyCode::Insert, C::S(Key::Ins)),
+        (VirtualKeyCode::Home, C::S(Key::Home)),
+        (VirtualKeyCode::Delete, C::S(Key::Del)),
+        (VirtualKeyCode::End, C::S(Key::End)),
+        (VirtualKeyCode::PageDown, C::S(Key::PgDn)),
+        (VirtualKeyCode::PageUp, C::S(Key::PgUp)),
+        (VirtualKeyCode::Left, C::S(Key::Left)),
+        (VirtualKeyCode::Up, C::S(Key::Up)),
+        (VirtualKeyCode::Right, C::S(Key::Right)),
+        (VirtualKeyCode::Down, C::S(Key::Down)),
+        (VirtualKeyCode::Back, C::S(Key::Backspace)),
+        (VirtualKeyCode::Return, C::S(Key::Enter)),
+        (VirtualKeyCode::Space, C::A(' ')),
+        (VirtualKeyCode::Caret, C::A('^')),
+        (VirtualKeyCode::Numlock, C::S(Key::NumLk)),
+        (VirtualKeyCode::Numpad0, C::A('0')),
+        (VirtualKeyCode::Numpad1, C::A('1')),
+        (VirtualKeyCode::Numpad2, C::A('2')),
+        (VirtualKeyCode::Numpad3, C::A('3')),
+        (VirtualKeyCode::Numpad4, C::A('4')),
+        (VirtualKeyCode::Numpad5, C::A('5')),
+        (VirtualKeyCode::Numpad6, C::A('6')),
+        (VirtualKeyCode::Numpad7, C::A('7')),
+        (VirtualKeyCode::Numpad8, C::A('8')),
+        (VirtualKeyCode::Numpad9, C::A('9')),
+        (VirtualKeyCode::Add, C::A('+')),
+        (VirtualKeyCode::Apostrophe, C::A('\'')),
+        (VirtualKeyCode::At, C::A('@')),
+        (VirtualKeyCode::Backslash, C::A('\\')),
+        (VirtualKeyCode::Capital, C::S(Key::CapLk)),
+        (VirtualKeyCode::Colon, C::A(':')),
+        (VirtualKeyCode::Comma, C::A(',')),
+        (VirtualKeyCode::Decimal, C::A('.')),
+        (VirtualKeyCode::Divide, C::A('/')),
+        (VirtualKeyCode::Equals, C::A('=')),
+        (VirtualKeyCode::LAlt, C::S(Key::Alt(Side::L))),
+        (VirtualKeyCode::LBracket, C::A('[')),
+        (VirtualKeyCode::LControl, C::S(Key::Ctrl(Side::L))),
+        (VirtualKeyCode::LShift, C::S(Key::Shift(Side::L))),
+        (VirtualKeyCode::LWin, C::S(Key::Mod(Side::L))),
+        (VirtualKeyCode::Minus, C::A('-')),
+        (VirtualKeyCode::Multiply, C::A('*')),
+        (VirtualKeyCode::NumpadComma, C::A(',')),
+        (VirtualKeyCode::NumpadEnter, C::S(Key::Enter)),
+        (VirtualKeyCode::NumpadEquals, C::A('=')),
+        (VirtualKeyCode::Period, C::A('.')),
+        (VirtualKeyCode::RAlt, C::S(Key::Alt(Side::R))),
+        (VirtualKeyCode::RBracket, C::A(']')),
+        (VirtualKeyCode::RControl, C::S(Key::Ctrl(Side::R))),
+        (VirtualKeyCode::RShift, C::S(Key::Shift(Side::R))),
+        (VirtualKeyCode::RWin, C::S(Key::Mod(Side::R))),
+        (VirtualKeyCode::Semicolon, C::A(';')),
+        (VirtualKeyCode::Slash, C::A('/')),
+        (VirtualKeyCode::Subtract, C::A('-')),
+        (VirtualKeyCode::Tab, C::S(Key::Tab)),
+    ];
+
+    fn input(vk: Option<VirtualKeyCode>, shift: bool) -> KeyboardInput {
+        KeyboardInput {
+            scancode: 42,
+            state: winit::ElementState::Pressed,
+            virtual_keycode: vk,
+            modifiers: ModifiersState {
+                shift,
+                ..ModifiersState::default()
+            },
+        }
+    }
+
+    #[test]
+    fn keys_map_as_listed() {
+        for (vk, c) in KEYS.iter() {
+            assert_eq!(map_vk(*vk), *c, "{:?} maps to the wrong key.", vk);
+        }
+        for vk in [
+            VirtualKeyCode::Compose,
+            VirtualKeyCode::Mute,
+            VirtualKeyCode::Kana,
+            VirtualKeyCode::Copy,
+        ] {
+            assert_eq!(map_vk(vk), C::Ignored, "{:?} should be ignored.", vk);
+        }
+    }
+
+    fn to_v(event: WindowEvent) -> e::V {
+        <IO as crate::source::IO>::to_v(Event::WindowEvent {
+            window_id: unsafe { winit::WindowId::dummy() },
+            event,
+        })
+    }
+
+    #[test]
+    fn characters_become_text() {
+        assert_eq!(
+            to_v(WindowEvent::ReceivedCharacter('é')),
+            e::V::T(e::t::V::Commit("é".to_string()))
+        );
+        // Backspace, enter and delete.
+        for c in ['\u{8}', '\r', '\u{7f}'] {
+            assert_eq!(to_v(WindowEvent::ReceivedCharacter(c)), e::V::Ignored);
+        }
+    }
+
+    #[test]
+    fn keys_carry_modifiers_and_scancodes() {
+        let shift = e::b::Mods {
+            shift: true,
+            ..e::b::Mods::NONE
+        };
+        let k = input(Some(VirtualKeyCode::A), true);
+        assert_eq!(
+            parse_keyboard(k),
+            e::V::B(e::b::V(C::A('a'), e::b::State::DOWN, shift))
+        );
+        assert_eq!(
+            physical_key(k),
+            e::V::B(e::b::V(C::P(42), e::b::State::DOWN, shift))
+        );
+        // Keys the platform can't name still come through where they are.
+        let k = input(None, false);
+        assert_eq!(parse_keyboard(k), e::V::Ignored);
+        assert_eq!(
+            physical_key(k),
+            e::V::B(e::b::V(C::P(42), e::b::State::DOWN, e::b::Mods::NONE))
+        );
+    }
+}
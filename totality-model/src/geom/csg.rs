@@ -0,0 +1,657 @@
+//! Constructive solid geometry on closed triangle meshes.
+//!
+//! Booleans are done with BSP trees, as in csg.js: each mesh is turned into a tree of its own
+//! polygons, and polygons of one mesh are clipped against the tree of the other. Vertex normals and
+//! UVs are interpolated wherever a polygon gets split. The clipped polygons then get their
+//! positions welded and their T-junctions resolved before being triangulated, so that the output
+//! is manifold if both inputs were.
+//!
+//! The output uses the texture of the left hand side mesh.
+
+use std::collections::HashMap;
+
+use na::{Vector2, Vector3};
+
+use super::{tri::TriMeshGeom, FMat, MeshAlloc, VMat};
+
+const EPS: f64 = 1e-5;
+
+#[derive(Debug, Copy, Clone)]
+struct CsgVertex {
+    pos: Vector3<f64>,
+    norm: Vector3<f64>,
+    uv: Vector2<f64>,
+}
+impl CsgVertex {
+    fn lerp(&self, other: &CsgVertex, t: f64) -> CsgVertex {
+        CsgVertex {
+            pos: self.pos.lerp(&other.pos, t),
+            norm: self.norm.lerp(&other.norm, t),
+            uv: self.uv.lerp(&other.uv, t),
+        }
+    }
+    fn flip(&mut self) {
+        self.norm = -self.norm;
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Plane {
+    n: Vector3<f64>,
+    w: f64,
+}
+impl Plane {
+    fn through(a: &Vector3<f64>, b: &Vector3<f64>, c: &Vector3<f64>) -> Option<Plane> {
+        let n = (b - a).cross(&(c - a)).try_normalize(EPS * EPS)?;
+        Some(Plane { n, w: n.dot(a) })
+    }
+    fn flip(&mut self) {
+        self.n = -self.n;
+        self.w = -self.w;
+    }
+    fn dist(&self, p: &Vector3<f64>) -> f64 {
+        self.n.dot(p) - self.w
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Polygon {
+    vv: Vec<CsgVertex>,
+    plane: Plane,
+}
+impl Polygon {
+    fn flip(&mut self) {
+        self.vv.reverse();
+        self.vv.iter_mut().for_each(CsgVertex::flip);
+        self.plane.flip();
+    }
+}
+
+enum Split {
+    CoplanarFront(Polygon),
+    CoplanarBack(Polygon),
+    Front(Polygon),
+    Back(Polygon),
+    Spanning(Polygon, Polygon),
+}
+impl Plane {
+    fn split(&self, poly: Polygon) -> Split {
+        const COPLANAR: u8 = 0;
+        const FRONT: u8 = 1;
+        const BACK: u8 = 2;
+        const SPANNING: u8 = 3;
+        let types = poly
+            .vv
+            .iter()
+            .map(|v| match self.dist(&v.pos) {
+                t if t < -EPS => BACK,
+                t if t > EPS => FRONT,
+                _ => COPLANAR,
+            })
+            .collect::<Vec<_>>();
+        match types.iter().fold(COPLANAR, |acc, t| acc | t) {
+            COPLANAR if self.n.dot(&poly.plane.n) > 0. => Split::CoplanarFront(poly),
+            COPLANAR => Split::CoplanarBack(poly),
+            FRONT => Split::Front(poly),
+            BACK => Split::Back(poly),
+            _ => {
+                let (mut f, mut b) = (vec![], vec![]);
+                for i in 0..poly.vv.len() {
+                    let j = (i + 1) % poly.vv.len();
+                    let (ti, tj) = (types[i], types[j]);
+                    let (vi, vj) = (&poly.vv[i], &poly.vv[j]);
+                    if ti != BACK {
+                        f.push(*vi);
+                    }
+                    if ti != FRONT {
+                        b.push(*vi);
+                    }
+                    if (ti | tj) == SPANNING {
+                        let t = (self.w - self.n.dot(&vi.pos)) / self.n.dot(&(vj.pos - vi.pos));
+                        let v = vi.lerp(vj, t);
+                        f.push(v);
+                        b.push(v);
+                    }
+                }
+                Split::Spanning(
+                    Polygon {
+                        vv: f,
+                        plane: poly.plane,
+                    },
+                    Polygon {
+                        vv: b,
+                        plane: poly.plane,
+                    },
+                )
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Node {
+    plane: Option<Plane>,
+    front: Option<Box<Node>>,
+    back: Option<Box<Node>>,
+    polygons: Vec<Polygon>,
+}
+impl Node {
+    fn new(polygons: Vec<Polygon>) -> Node {
+        let mut n = Node::default();
+        n.build(polygons);
+        n
+    }
+    fn invert(&mut self) {
+        self.polygons.iter_mut().for_each(Polygon::flip);
+        if let Some(ref mut p) = self.plane {
+            p.flip();
+        }
+        if let Some(ref mut f) = self.front {
+            f.invert();
+        }
+        if let Some(ref mut b) = self.back {
+            b.invert();
+        }
+        std::mem::swap(&mut self.front, &mut self.back);
+    }
+    /// Removes every part of `polygons` inside of this tree.
+    fn clip_polygons(&self, polygons: Vec<Polygon>) -> Vec<Polygon> {
+        let plane = match self.plane {
+            Some(p) => p,
+            None => return polygons,
+        };
+        let (mut f, mut b) = (vec![], vec![]);
+        for poly in polygons {
+            match plane.split(poly) {
+                Split::CoplanarFront(p) | Split::Front(p) => f.push(p),
+                Split::CoplanarBack(p) | Split::Back(p) => b.push(p),
+                Split::Spanning(pf, pb) => {
+                    f.push(pf);
+                    b.push(pb);
+                }
+            }
+        }
+        let mut f = match self.front {
+            Some(ref n) => n.clip_polygons(f),
+            None => f,
+        };
+        if let Some(ref n) = self.back {
+            f.append(&mut n.clip_polygons(b));
+        }
+        f
+    }
+    /// Removes every part of this tree's polygons inside of `other`.
+    fn clip_to(&mut self, other: &Node) {
+        self.polygons = other.clip_polygons(std::mem::take(&mut self.polygons));
+        if let Some(ref mut f) = self.front {
+            f.clip_to(other);
+        }
+        if let Some(ref mut b) = self.back {
+            b.clip_to(other);
+        }
+    }
+    fn all_polygons(&self) -> Vec<Polygon> {
+        let mut all = self.polygons.clone();
+        if let Some(ref f) = self.front {
+            all.append(&mut f.all_polygons());
+        }
+        if let Some(ref b) = self.back {
+            all.append(&mut b.all_polygons());
+        }
+        all
+    }
+    fn build(&mut self, polygons: Vec<Polygon>) {
+        let plane = match polygons.first() {
+            Some(p) => *self.plane.get_or_insert(p.plane),
+            None => return,
+        };
+        let (mut f, mut b) = (vec![], vec![]);
+        for poly in polygons {
+            match plane.split(poly) {
+                Split::CoplanarFront(p) | Split::CoplanarBack(p) => self.polygons.push(p),
+                Split::Front(p) => f.push(p),
+                Split::Back(p) => b.push(p),
+                Split::Spanning(pf, pb) => {
+                    f.push(pf);
+                    b.push(pb);
+                }
+            }
+        }
+        if !f.is_empty() {
+            self.front.get_or_insert_with(Default::default).build(f);
+        }
+        if !b.is_empty() {
+            self.back.get_or_insert_with(Default::default).build(b);
+        }
+    }
+}
+
+fn polygons_of(mesh: &TriMeshGeom) -> Vec<Polygon> {
+    let vv = mesh
+        .vec_vv
+        .iter()
+        .map(|v| {
+            let (pos, norm, uv) = (v.pos, v.norm, v.uv);
+            CsgVertex {
+                pos: Vector3::from(pos).cast(),
+                norm: Vector3::from(norm).cast(),
+                uv: Vector2::from(uv).cast(),
+            }
+        })
+        .collect::<Vec<_>>();
+    mesh.ff
+        .column_iter()
+        .filter_map(|f| {
+            let tri = vec![vv[f[0] as usize], vv[f[1] as usize], vv[f[2] as usize]];
+            let plane = Plane::through(&tri[0].pos, &tri[1].pos, &tri[2].pos)?;
+            Some(Polygon { vv: tri, plane })
+        })
+        .collect()
+}
+
+/// Space that is inside of either mesh.
+pub fn union(alloc: &mut MeshAlloc, lhs: &TriMeshGeom, rhs: &TriMeshGeom) -> TriMeshGeom {
+    let mut a = Node::new(polygons_of(lhs));
+    let mut b = Node::new(polygons_of(rhs));
+    a.clip_to(&b);
+    b.clip_to(&a);
+    b.invert();
+    b.clip_to(&a);
+    b.invert();
+    a.build(b.all_polygons());
+    to_geom(alloc, a.all_polygons(), lhs.tex_file.clone())
+}
+
+/// Space that is inside of both meshes.
+pub fn intersection(alloc: &mut MeshAlloc, lhs: &TriMeshGeom, rhs: &TriMeshGeom) -> TriMeshGeom {
+    let mut a = Node::new(polygons_of(lhs));
+    let mut b = Node::new(polygons_of(rhs));
+    a.invert();
+    b.clip_to(&a);
+    b.invert();
+    a.clip_to(&b);
+    b.clip_to(&a);
+    a.build(b.all_polygons());
+    a.invert();
+    to_geom(alloc, a.all_polygons(), lhs.tex_file.clone())
+}
+
+/// Space that is inside of `lhs` but not `rhs`.
+pub fn difference(alloc: &mut MeshAlloc, lhs: &TriMeshGeom, rhs: &TriMeshGeom) -> TriMeshGeom {
+    let mut a = Node::new(polygons_of(lhs));
+    let mut b = Node::new(polygons_of(rhs));
+    a.invert();
+    a.clip_to(&b);
+    b.clip_to(&a);
+    b.invert();
+    b.clip_to(&a);
+    b.invert();
+    a.build(b.all_polygons());
+    a.invert();
+    to_geom(alloc, a.all_polygons(), lhs.tex_file.clone())
+}
+
+/// Snaps positions that are within `EPS` of each other to the same point. Points are kept in a grid,
+/// so that those near a position or an edge are found without going through all of them.
+struct Welder {
+    size: f64,
+    cells: HashMap<[i64; 3], Vec<usize>>,
+    points: Vec<Vector3<f64>>,
+}
+impl Welder {
+    /// A welder with grid cells `size` across, which is best about as long as an edge.
+    fn new(size: f64) -> Self {
+        Welder {
+            size: size.max(EPS * 4.),
+            cells: HashMap::new(),
+            points: vec![],
+        }
+    }
+    fn key(&self, p: &Vector3<f64>) -> [i64; 3] {
+        [0, 1, 2].map(|i| (p[i] / self.size).floor() as i64)
+    }
+    fn weld(&mut self, p: &Vector3<f64>) -> usize {
+        let k = self.key(p);
+        for dx in -1..=1 {
+            for dy in -1..=1 {
+                for dz in -1..=1 {
+                    if let Some(ii) = self.cells.get(&[k[0] + dx, k[1] + dy, k[2] + dz]) {
+                        if let Some(&i) = ii.iter().find(|&&i| (self.points[i] - p).norm() <= EPS) {
+                            return i;
+                        }
+                    }
+                }
+            }
+        }
+        self.points.push(*p);
+        self.cells.entry(k).or_default().push(self.points.len() - 1);
+        self.points.len() - 1
+    }
+    /// Points in the cells within `EPS` of the box from `lo` to `hi`.
+    fn near(&self, lo: &Vector3<f64>, hi: &Vector3<f64>) -> impl Iterator<Item = usize> + '_ {
+        let (lo, hi) = (
+            self.key(&lo.add_scalar(-EPS)),
+            self.key(&hi.add_scalar(EPS)),
+        );
+        (lo[0]..=hi[0])
+            .flat_map(move |x| {
+                (lo[1]..=hi[1]).flat_map(move |y| (lo[2]..=hi[2]).map(move |z| [x, y, z]))
+            })
+            .filter_map(move |k| self.cells.get(&k))
+            .flatten()
+            .copied()
+    }
+}
+
+fn to_geom(alloc: &mut MeshAlloc, polygons: Vec<Polygon>, texture: Option<String>) -> TriMeshGeom {
+    // Weld, dropping any edges that collapsed in the process. Cells about as long as the average
+    // edge keep both welding and looking along an edge to a handful of cells.
+    let (total, count) = polygons
+        .iter()
+        .flat_map(|poly| poly.vv.iter().zip(poly.vv.iter().cycle().skip(1)))
+        .fold((0., 0), |(total, count), (a, b)| {
+            (total + (b.pos - a.pos).norm(), count + 1)
+        });
+    let mut welder = Welder::new(if count > 0 { total / count as f64 } else { 0. });
+    let polygons = polygons
+        .into_iter()
+        .filter_map(|mut poly| {
+            let mut ids: Vec<usize> = vec![];
+            let mut vv: Vec<CsgVertex> = vec![];
+            for mut v in poly.vv {
+                let id = welder.weld(&v.pos);
+                v.pos = welder.points[id];
+                if ids.last() != Some(&id) {
+                    ids.push(id);
+                    vv.push(v);
+                }
+            }
+            while ids.len() > 1 && ids.first() == ids.last() {
+                ids.pop();
+                vv.pop();
+            }
+            poly.vv = vv;
+            (ids.len() >= 3).then_some((poly, ids))
+        })
+        .collect::<Vec<_>>();
+
+    // Resolve T-junctions: any welded point lying in the middle of an edge gets inserted into it.
+    let welder = &welder;
+    let polygons = polygons
+        .into_iter()
+        .map(|(poly, ids)| {
+            let mut vv = Vec::with_capacity(poly.vv.len());
+            let mut inserted = false;
+            for i in 0..poly.vv.len() {
+                let j = (i + 1) % poly.vv.len();
+                let (a, b) = (&poly.vv[i], &poly.vv[j]);
+                vv.push(*a);
+                let d = b.pos - a.pos;
+                let len2 = d.norm_squared();
+                let mut on_edge = welder
+                    .near(&a.pos.inf(&b.pos), &a.pos.sup(&b.pos))
+                    .filter(|&k| k != ids[i] && k != ids[j])
+                    .filter_map(|k| {
+                        let p = welder.points[k];
+                        let t = (p - a.pos).dot(&d) / len2;
+                        (t > 0. && t < 1. && (a.pos + d * t - p).norm() <= EPS).then_some((t, p))
+                    })
+                    .collect::<Vec<_>>();
+                on_edge.sort_by(|x, y| x.0.total_cmp(&y.0));
+                for (t, p) in on_edge {
+                    let mut v = a.lerp(b, t);
+                    v.pos = p;
+                    vv.push(v);
+                    inserted = true;
+                }
+            }
+            (
+                Polygon {
+                    vv,
+                    plane: poly.plane,
+                },
+                inserted,
+            )
+        })
+        .collect::<Vec<_>>();
+
+    // Triangulate. Polygons are convex, but ones with inserted points have collinear runs, so fan
+    // out from their centroid instead of a corner to avoid zero area triangles.
+    let mut vertex_ids: HashMap<[u64; 8], u32> = HashMap::new();
+    let mut out_vv: Vec<CsgVertex> = vec![];
+    let mut out_ff: Vec<([u32; 3], Vector3<f64>)> = vec![];
+    let mut id_of = |v: &CsgVertex, n: &Vector3<f64>| {
+        let norm = v.norm.try_normalize(EPS).unwrap_or(*n);
+        let key = [
+            v.pos[0].to_bits(),
+            v.pos[1].to_bits(),
+            v.pos[2].to_bits(),
+            (norm[0] as f32).to_bits() as u64,
+            (norm[1] as f32).to_bits() as u64,
+            (norm[2] as f32).to_bits() as u64,
+            (v.uv[0] as f32).to_bits() as u64,
+            (v.uv[1] as f32).to_bits() as u64,
+        ];
+        *vertex_ids.entry(key).or_insert_with(|| {
+            out_vv.push(CsgVertex { norm, ..*v });
+            (out_vv.len() - 1) as u32
+        })
+    };
+    for (poly, inserted) in polygons {
+        let n = poly.plane.n;
+        if inserted {
+            let count = poly.vv.len() as f64;
+            let centroid = poly.vv.iter().skip(1).fold(poly.vv[0], |acc, v| CsgVertex {
+                pos: acc.pos + v.pos,
+                norm: acc.norm + v.norm,
+                uv: acc.uv + v.uv,
+            });
+            let centroid = CsgVertex {
+                pos: centroid.pos / count,
+                norm: centroid.norm / count,
+                uv: centroid.uv / count,
+            };
+            let c = id_of(&centroid, &n);
+            for i in 0..poly.vv.len() {
+                let j = (i + 1) % poly.vv.len();
+                out_ff.push(([c, id_of(&poly.vv[i], &n), id_of(&poly.vv[j], &n)], n));
+            }
+        } else {
+            let first = id_of(&poly.vv[0], &n);
+            for i in 1..poly.vv.len() - 1 {
+                out_ff.push((
+                    [first, id_of(&poly.vv[i], &n), id_of(&poly.vv[i + 1], &n)],
+                    n,
+                ));
+            }
+        }
+    }
+
+    TriMeshGeom::new(
+        alloc,
+        VMat::from_iterator(
+            out_vv.len(),
+            out_vv
+                .iter()
+                .flat_map(|v| v.pos.iter().map(|x| *x as f32).collect::<Vec<_>>()),
+        ),
+        FMat::from_iterator(
+            out_ff.len(),
+            out_ff.iter().flat_map(|(f, _)| f.iter().copied()),
+        ),
+        out_vv.iter().map(|v| v.norm.cast::<f32>().into()).collect(),
+        out_ff.iter().map(|(_, n)| n.cast::<f32>().into()).collect(),
+        out_vv.iter().map(|v| v.uv.cast::<f32>().into()).collect(),
+        texture,
+    )
+}
+
+impl TriMeshGeom {
+    pub fn union(&self, alloc: &mut MeshAlloc, other: &TriMeshGeom) -> TriMeshGeom {
+        union(alloc, self, other)
+    }
+    pub fn intersection(&self, alloc: &mut MeshAlloc, other: &TriMeshGeom) -> TriMeshGeom {
+        intersection(alloc, self, other)
+    }
+    pub fn difference(&self, alloc: &mut MeshAlloc, other: &TriMeshGeom) -> TriMeshGeom {
+        difference(alloc, self, other)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn cube_at(alloc: &mut MeshAlloc, x: f32, y: f32, z: f32) -> TriMeshGeom {
+        let mut cube = crate::unit_cube(alloc, None);
+        for mut c in cube.vv.column_iter_mut() {
+            c += Vector3::new(x, y, z);
+        }
+        for v in cube.vec_vv.iter_mut() {
+            let pos = v.pos;
+            v.pos = [pos[0] + x, pos[1] + y, pos[2] + z];
+        }
+        cube
+    }
+
+    /// Every edge is shared by exactly two faces, which traverse it in opposite directions.
+    fn assert_manifold(m: &TriMeshGeom) {
+        let key = |i: u32| {
+            let p = m.vv.column(i as usize);
+            [0, 1, 2].map(|k| (p[k] * 1e4).round() as i64)
+        };
+        let mut directed: HashMap<([i64; 3], [i64; 3]), usize> = HashMap::new();
+        for f in m.ff.column_iter() {
+            for (a, b) in [(f[0], f[1]), (f[1], f[2]), (f[2], f[0])] {
+                *directed.entry((key(a), key(b))).or_default() += 1;
+            }
+        }
+        assert!(!directed.is_empty());
+        for ((a, b), count) in directed.iter() {
+            assert_eq!(*count, 1, "Edge {:?} -> {:?} used {} times.", a, b, count);
+            assert_eq!(
+                directed.get(&(*b, *a)),
+                Some(&1),
+                "Edge {:?} -> {:?} has no twin.",
+                a,
+                b
+            );
+        }
+    }
+
+    fn assert_volume(m: &TriMeshGeom, expected: f32) {
+        assert!(
+            (m.volume() - expected).abs() < 1e-4,
+            "Expected volume {}, got {}.",
+            expected,
+            m.volume()
+        );
+    }
+
+    #[test]
+    fn union_of_overlapping_cubes() {
+        let mut alloc = MeshAlloc::new();
+        let (a, b) = (
+            cube_at(&mut alloc, 0., 0., 0.),
+            cube_at(&mut alloc, 0.5, 0.5, 0.5),
+        );
+        let u = a.union(&mut alloc, &b);
+        assert_volume(&u, 2. - 0.125);
+        assert_manifold(&u);
+    }
+
+    #[test]
+    fn union_of_cubes_sharing_a_face() {
+        let mut alloc = MeshAlloc::new();
+        let (a, b) = (
+            cube_at(&mut alloc, 0., 0., 0.),
+            cube_at(&mut alloc, 1., 0., 0.),
+        );
+        let u = a.union(&mut alloc, &b);
+        assert_volume(&u, 2.);
+        assert_manifold(&u);
+    }
+
+    #[test]
+    fn union_of_cubes_sharing_part_of_a_face() {
+        let mut alloc = MeshAlloc::new();
+        let (a, b) = (
+            cube_at(&mut alloc, 0., 0., 0.),
+            cube_at(&mut alloc, 1., 0.5, 0.25),
+        );
+        let u = a.union(&mut alloc, &b);
+        assert_volume(&u, 2.);
+        assert_manifold(&u);
+    }
+
+    #[test]
+    fn difference_with_flush_faces() {
+        let mut alloc = MeshAlloc::new();
+        // The cutter shares the x and z side planes and the top plane with the cube.
+        let (a, b) = (
+            cube_at(&mut alloc, 0., 0., 0.),
+            cube_at(&mut alloc, 0., 0.5, 0.),
+        );
+        let d = a.difference(&mut alloc, &b);
+        assert_volume(&d, 0.5);
+        assert_manifold(&d);
+    }
+
+    #[test]
+    fn difference_carving_a_notch() {
+        let mut alloc = MeshAlloc::new();
+        let (a, b) = (
+            cube_at(&mut alloc, 0., 0., 0.),
+            cube_at(&mut alloc, 0.5, 0.5, 0.),
+        );
+        let d = a.difference(&mut alloc, &b);
+        assert_volume(&d, 0.75);
+        assert_manifold(&d);
+    }
+
+    #[test]
+    fn intersection_of_coincident_cubes() {
+        let mut alloc = MeshAlloc::new();
+        let (a, b) = (
+            cube_at(&mut alloc, 0., 0., 0.),
+            cube_at(&mut alloc, 0., 0., 0.),
+        );
+        let i = a.intersection(&mut alloc, &b);
+        assert_volume(&i, 1.);
+        assert_manifold(&i);
+    }
+
+    #[test]
+    fn intersection_of_offset_cubes() {
+        let mut alloc = MeshAlloc::new();
+        let (a, b) = (
+            cube_at(&mut alloc, 0., 0., 0.),
+            cube_at(&mut alloc, 0.5, 0., 0.),
+        );
+        let i = a.intersection(&mut alloc, &b);
+        assert_volume(&i, 0.5);
+        assert_manifold(&i);
+    }
+
+    #[test]
+    fn untouched_faces_keep_their_uvs() {
+        let mut alloc = MeshAlloc::new();
+        let (a, b) = (
+            cube_at(&mut alloc, 0., 0., 0.),
+            cube_at(&mut alloc, 5., 0., 0.),
+        );
+        let u = a.union(&mut alloc, &b);
+        assert_volume(&u, 2.);
+        for v in u.vec_vv.iter() {
+            let (pos, uv) = (v.pos, v.uv);
+            if let Some(orig) = a.vec_vv.iter().find(|o| {
+                let opos = o.pos;
+                (Vector3::from(opos) - Vector3::from(pos)).norm() < 1e-6
+            }) {
+                let ouv = orig.uv;
+                assert_eq!(ouv, uv);
+            }
+        }
+    }
+}
@@ -0,0 +1,380 @@
+//! Approximate convex decomposition, in the style of V-HACD.
+//!
+//! The mesh is voxelized, and the voxel set is recursively cut with axis aligned planes. Each cut
+//! is chosen to minimize the concavity (hull volume not covered by voxels) of the two halves, and
+//! the most concave piece is always cut first. Hulls are built from voxel corners, so they are
+//! conservative up to the voxel size.
+
+use std::collections::HashMap;
+
+use na::Vector3;
+
+use super::{
+    hull::{quickhull, Hull},
+    tri::TriMeshGeom,
+    MeshAlloc,
+};
+
+#[derive(Debug, Copy, Clone)]
+pub struct DecompositionParams {
+    /// Voxels along the longest side of the mesh's bounding box.
+    pub resolution: u32,
+    /// Upper bound on the number of hulls produced.
+    pub max_hulls: usize,
+    /// Pieces whose concavity is below this fraction of the mesh's volume are not cut further.
+    pub max_concavity: f32,
+    /// Only every `plane_step`th voxel boundary is considered as a cutting plane.
+    pub plane_step: u32,
+}
+impl Default for DecompositionParams {
+    fn default() -> Self {
+        Self {
+            resolution: 32,
+            max_hulls: 16,
+            max_concavity: 0.01,
+            plane_step: 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ConvexPart {
+    pub hull: TriMeshGeom,
+    /// Volume of the voxels making up this part.
+    pub volume: f32,
+    /// Volume of the hull not covered by this part's voxels.
+    pub concavity: f32,
+}
+
+#[derive(Debug, Clone)]
+pub struct ConvexDecomposition {
+    pub parts: Vec<ConvexPart>,
+    /// Volume of the voxelized mesh.
+    pub volume: f32,
+    /// Sum of the concavity of every part.
+    pub concavity: f32,
+}
+
+struct Grid {
+    origin: Vector3<f32>,
+    h: f32,
+}
+impl Grid {
+    fn corner(&self, v: [u32; 3]) -> Vector3<f32> {
+        self.origin + Vector3::new(v[0] as f32, v[1] as f32, v[2] as f32) * self.h
+    }
+    fn cell_volume(&self) -> f32 {
+        self.h * self.h * self.h
+    }
+}
+
+struct Piece {
+    voxels: Vec<[u32; 3]>,
+    hull: Hull,
+    concavity: f32,
+}
+
+/// Splits `mesh` into at most `params.max_hulls` convex hulls.
+///
+/// The mesh must be closed for the inside/outside classification to make sense.
+pub fn decompose(
+    alloc: &mut MeshAlloc,
+    mesh: &TriMeshGeom,
+    params: &DecompositionParams,
+) -> ConvexDecomposition {
+    let (grid, voxels) = voxelize(mesh, params.resolution.max(1));
+    let volume = voxels.len() as f32 * grid.cell_volume();
+    let mut pieces = match piece_from(&grid, voxels) {
+        Some(p) => vec![p],
+        None => vec![],
+    };
+    let mut done = vec![];
+    while pieces.len() + done.len() < params.max_hulls.max(1) {
+        let worst = match (0..pieces.len())
+            .max_by(|&a, &b| pieces[a].concavity.total_cmp(&pieces[b].concavity))
+        {
+            Some(i) => i,
+            None => break,
+        };
+        if pieces[worst].concavity <= params.max_concavity * volume {
+            break;
+        }
+        let piece = pieces.swap_remove(worst);
+        match best_split(&grid, &piece.voxels, params.plane_step.max(1)) {
+            Some((axis, at)) => {
+                let (l, r): (Vec<_>, Vec<_>) = piece.voxels.into_iter().partition(|v| v[axis] < at);
+                pieces.extend(piece_from(&grid, l));
+                pieces.extend(piece_from(&grid, r));
+            }
+            None => done.push(piece),
+        }
+    }
+    let parts = pieces
+        .into_iter()
+        .chain(done)
+        .map(|p| ConvexPart {
+            volume: p.voxels.len() as f32 * grid.cell_volume(),
+            concavity: p.concavity,
+            hull: p.hull.into_geom(alloc, mesh.tex_file.clone()),
+        })
+        .collect::<Vec<_>>();
+    ConvexDecomposition {
+        concavity: parts.iter().map(|p| p.concavity).sum(),
+        parts,
+        volume,
+    }
+}
+
+fn piece_from(grid: &Grid, voxels: Vec<[u32; 3]>) -> Option<Piece> {
+    if voxels.is_empty() {
+        return None;
+    }
+    let hull = quickhull(&corners(grid, &voxels))?;
+    let concavity = (hull.volume() - voxels.len() as f32 * grid.cell_volume()).max(0.);
+    Some(Piece {
+        voxels,
+        hull,
+        concavity,
+    })
+}
+
+/// Corners of the voxels that can possibly be on the hull: the first and last voxel of every row
+/// along each axis.
+fn corners(grid: &Grid, voxels: &[[u32; 3]]) -> Vec<Vector3<f32>> {
+    let mut extremes: HashMap<(usize, u32, u32), (u32, u32)> = HashMap::new();
+    for v in voxels.iter() {
+        for axis in 0..3 {
+            let (u, w) = ((axis + 1) % 3, (axis + 2) % 3);
+            let e = extremes
+                .entry((axis, v[u], v[w]))
+                .or_insert((v[axis], v[axis]));
+            e.0 = e.0.min(v[axis]);
+            e.1 = e.1.max(v[axis]);
+        }
+    }
+    let mut cc = extremes
+        .into_iter()
+        .flat_map(|((axis, a, b), (lo, hi))| {
+            let (u, w) = ((axis + 1) % 3, (axis + 2) % 3);
+            let mut out = Vec::with_capacity(8);
+            for (t, da, db) in [
+                (lo, 0, 0),
+                (lo, 0, 1),
+                (lo, 1, 0),
+                (lo, 1, 1),
+                (hi + 1, 0, 0),
+                (hi + 1, 0, 1),
+                (hi + 1, 1, 0),
+                (hi + 1, 1, 1),
+            ] {
+                let mut c = [0; 3];
+                c[axis] = t;
+                c[u] = a + da;
+                c[w] = b + db;
+                out.push(c);
+            }
+            out
+        })
+        .collect::<Vec<_>>();
+    cc.sort_unstable();
+    cc.dedup();
+    cc.into_iter().map(|c| grid.corner(c)).collect()
+}
+
+/// Finds the axis aligned cut minimizing the summed concavity of both halves.
+///
+/// Hulls of the halves are grown one slab at a time, reusing the previous hull's vertices, so each
+/// axis costs a linear number of small hull computations.
+fn best_split(grid: &Grid, voxels: &[[u32; 3]], step: u32) -> Option<(usize, u32)> {
+    let cell = grid.cell_volume();
+    let mut best: Option<(f32, usize, u32)> = None;
+    for axis in 0..3 {
+        let lo = voxels.iter().map(|v| v[axis]).min()?;
+        let hi = voxels.iter().map(|v| v[axis]).max()?;
+        if lo == hi {
+            continue;
+        }
+        let slabs = (lo..=hi)
+            .map(|s| {
+                voxels
+                    .iter()
+                    .filter(|v| v[axis] == s)
+                    .copied()
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        let grow = |order: &mut dyn Iterator<Item = usize>| {
+            let mut acc: Vec<Vector3<f32>> = vec![];
+            let mut count = 0;
+            let mut out = vec![0.; slabs.len()];
+            for i in order {
+                acc.extend(corners(grid, &slabs[i]));
+                count += slabs[i].len();
+                if let Some(h) = quickhull(&acc) {
+                    out[i] = (h.volume() - count as f32 * cell).max(0.);
+                    acc = h.vv;
+                }
+            }
+            out
+        };
+        // prefix[i] covers slabs ..=i, suffix[i] covers slabs i..
+        let prefix = grow(&mut (0..slabs.len()));
+        let suffix = grow(&mut (0..slabs.len()).rev());
+        for i in (1..slabs.len()).filter(|i| (*i as u32).is_multiple_of(step)) {
+            if slabs[..i].iter().all(|s| s.is_empty()) || slabs[i..].iter().all(|s| s.is_empty()) {
+                continue;
+            }
+            let cost = prefix[i - 1] + suffix[i];
+            if best.is_none_or(|(c, _, _)| cost < c) {
+                best = Some((cost, axis, lo + i as u32));
+            }
+        }
+    }
+    best.map(|(_, axis, at)| (axis, at))
+}
+
+/// Marks every voxel whose center is inside the mesh, by casting rays along x and counting
+/// crossings.
+fn voxelize(mesh: &TriMeshGeom, resolution: u32) -> (Grid, Vec<[u32; 3]>) {
+    let pp = mesh
+        .vv
+        .column_iter()
+        .map(|c| c.into_owned())
+        .collect::<Vec<Vector3<f32>>>();
+    let (mut lo, mut hi) = (Vector3::repeat(f32::MAX), Vector3::repeat(f32::MIN));
+    for p in pp.iter() {
+        lo = lo.inf(p);
+        hi = hi.sup(p);
+    }
+    if pp.is_empty() {
+        return (
+            Grid {
+                origin: Vector3::zeros(),
+                h: 1.,
+            },
+            vec![],
+        );
+    }
+    let h = ((hi - lo).max() / resolution as f32).max(f32::EPSILON);
+    let dims = (hi - lo).map(|e| ((e / h).ceil() as u32).max(1));
+    let grid = Grid { origin: lo, h };
+    let tris = mesh
+        .ff
+        .column_iter()
+        .map(|c| [pp[c[0] as usize], pp[c[1] as usize], pp[c[2] as usize]])
+        .collect::<Vec<_>>();
+    let mut voxels = vec![];
+    for z in 0..dims[2] {
+        for y in 0..dims[1] {
+            // Nudge the ray off of the cell center so it doesn't run exactly along axis aligned
+            // edges, which would double count crossings.
+            let (ry, rz) = (
+                lo[1] + (y as f32 + 0.5 + 1.3e-4) * h,
+                lo[2] + (z as f32 + 0.5 + 0.7e-4) * h,
+            );
+            let mut xs = tris
+                .iter()
+                .filter_map(|t| ray_x_crossing(t, ry, rz))
+                .collect::<Vec<_>>();
+            xs.sort_by(|a, b| a.total_cmp(b));
+            for x in 0..dims[0] {
+                let cx = lo[0] + (x as f32 + 0.5) * h;
+                if xs.iter().filter(|&&c| c < cx).count() % 2 == 1 {
+                    voxels.push([x, y, z]);
+                }
+            }
+        }
+    }
+    (grid, voxels)
+}
+
+/// Where the line `(_, y, z)` crosses the triangle, if it does.
+fn ray_x_crossing(t: &[Vector3<f32>; 3], y: f32, z: f32) -> Option<f32> {
+    let [a, b, c] = t;
+    let d = (b[1] - a[1]) * (c[2] - a[2]) - (c[1] - a[1]) * (b[2] - a[2]);
+    if d.abs() <= f32::EPSILON * f32::EPSILON {
+        return None;
+    }
+    let u = ((y - a[1]) * (c[2] - a[2]) - (c[1] - a[1]) * (z - a[2])) / d;
+    let v = ((b[1] - a[1]) * (z - a[2]) - (y - a[1]) * (b[2] - a[2])) / d;
+    if u < 0. || v < 0. || u + v > 1. {
+        return None;
+    }
+    Some(a[0] + u * (b[0] - a[0]) + v * (c[0] - a[0]))
+}
+
+impl TriMeshGeom {
+    /// Shorthand for `decompose`.
+    pub fn convex_decomposition(
+        &self,
+        alloc: &mut MeshAlloc,
+        params: &DecompositionParams,
+    ) -> ConvexDecomposition {
+        decompose(alloc, self, params)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::geom::{FMat, VMat};
+
+    fn two_cubes(alloc: &mut MeshAlloc) -> TriMeshGeom {
+        let cube = crate::unit_cube(alloc, None);
+        let mut pos = cube.vv.clone().insert_columns(8, 8, 0.);
+        for c in 0..8 {
+            let mut p = pos.column(c).into_owned();
+            p[0] += 3.;
+            pos.set_column(c + 8, &p);
+        }
+        let mut ff = cube.ff.clone().insert_columns(12, 12, 0);
+        for c in 0..12 {
+            ff.set_column(c + 12, &cube.ff.column(c).map(|i| i + 8));
+        }
+        TriMeshGeom::new(
+            alloc,
+            VMat::from(pos),
+            FMat::from(ff),
+            vec![[0.; 3]; 16],
+            vec![[0.; 3]; 24],
+            vec![[0.; 2]; 16],
+            None,
+        )
+    }
+
+    #[test]
+    fn cube_stays_whole() {
+        let mut alloc = MeshAlloc::new();
+        let cube = crate::unit_cube(&mut alloc, None);
+        let params = DecompositionParams {
+            resolution: 8,
+            ..Default::default()
+        };
+        let d = cube.convex_decomposition(&mut alloc, &params);
+        assert_eq!(d.parts.len(), 1);
+        assert!((d.volume - 1.).abs() < 1e-3, "Volume was {}.", d.volume);
+        assert!(d.concavity < 1e-3);
+    }
+
+    #[test]
+    fn separated_cubes_split_apart() {
+        let mut alloc = MeshAlloc::new();
+        let mesh = two_cubes(&mut alloc);
+        assert!(mesh.concavity() > 1.);
+        let params = DecompositionParams {
+            resolution: 16,
+            max_hulls: 4,
+            ..Default::default()
+        };
+        let d = decompose(&mut alloc, &mesh, &params);
+        assert_eq!(d.parts.len(), 2);
+        assert!(d.concavity < 0.05, "Concavity was {}.", d.concavity);
+        for p in d.parts.iter() {
+            assert!(
+                (p.hull.volume() - 1.).abs() < 0.05,
+                "Hull volume was {}.",
+                p.hull.volume()
+            );
+        }
+    }
+}
@@ -0,0 +1,339 @@
+//! Convex hulls through 3D quickhull.
+
+use std::collections::{HashMap, HashSet};
+
+use na::Vector3;
+
+use super::{tri::TriMeshGeom, FMat, MeshAlloc, VMat};
+
+/// A closed, convex triangle mesh with counter-clockwise (outward facing) winding.
+#[derive(Debug, Clone)]
+pub struct Hull {
+    pub vv: Vec<Vector3<f32>>,
+    pub ff: Vec<[u32; 3]>,
+}
+impl Hull {
+    pub fn volume(&self) -> f32 {
+        signed_volume(self.vv.iter().copied(), self.ff.iter().copied())
+    }
+    pub fn into_geom(self, alloc: &mut MeshAlloc, texture: Option<String>) -> TriMeshGeom {
+        let mut vertex_norms = vec![Vector3::<f32>::zeros(); self.vv.len()];
+        let mut face_norms = Vec::with_capacity(self.ff.len());
+        for f in self.ff.iter() {
+            let [a, b, c] = f.map(|i| self.vv[i as usize]);
+            let n = (b - a).cross(&(c - a));
+            for i in f.iter() {
+                vertex_norms[*i as usize] += n;
+            }
+            face_norms.push(normalized_or_zero(n).into());
+        }
+        TriMeshGeom::new(
+            alloc,
+            VMat::from_columns(&self.vv),
+            FMat::from_iterator(self.ff.len(), self.ff.iter().flatten().copied()),
+            vertex_norms
+                .into_iter()
+                .map(|n| normalized_or_zero(n).into())
+                .collect(),
+            face_norms,
+            vec![[0., 0.]; self.vv.len()],
+            texture,
+        )
+    }
+}
+
+/// Signed volume enclosed by a closed triangle mesh. Positive when faces wind outwards.
+pub(crate) fn signed_volume(
+    vv: impl Iterator<Item = Vector3<f32>>,
+    ff: impl Iterator<Item = [u32; 3]>,
+) -> f32 {
+    let vv = vv.collect::<Vec<_>>();
+    let total: f64 = ff
+        .map(|[a, b, c]| {
+            let (a, b, c) = (
+                vv[a as usize].cast::<f64>(),
+                vv[b as usize].cast::<f64>(),
+                vv[c as usize].cast::<f64>(),
+            );
+            a.dot(&b.cross(&c))
+        })
+        .sum();
+    (total / 6.) as f32
+}
+
+fn normalized_or_zero(v: Vector3<f32>) -> Vector3<f32> {
+    v.try_normalize(f32::EPSILON).unwrap_or_else(Vector3::zeros)
+}
+
+struct HullFace {
+    vv: [usize; 3],
+    n: Vector3<f64>,
+    d: f64,
+    outside: Vec<usize>,
+    alive: bool,
+}
+impl HullFace {
+    fn new(pp: &[Vector3<f64>], vv: [usize; 3]) -> Self {
+        let [a, b, c] = vv.map(|i| pp[i]);
+        let n = (b - a)
+            .cross(&(c - a))
+            .try_normalize(0.)
+            .unwrap_or_else(Vector3::zeros);
+        Self {
+            vv,
+            n,
+            d: n.dot(&a),
+            outside: vec![],
+            alive: true,
+        }
+    }
+    fn dist(&self, p: &Vector3<f64>) -> f64 {
+        self.n.dot(p) - self.d
+    }
+    fn edges(&self) -> [(usize, usize); 3] {
+        let [a, b, c] = self.vv;
+        [(a, b), (b, c), (c, a)]
+    }
+}
+
+/// Computes the convex hull of a point cloud.
+///
+/// Returns `None` if fewer than four points are given or all of them are (nearly) coplanar.
+pub fn quickhull(points: &[Vector3<f32>]) -> Option<Hull> {
+    if points.len() < 4 {
+        return None;
+    }
+    let pp = points.iter().map(|p| p.cast::<f64>()).collect::<Vec<_>>();
+    let (mut lo, mut hi) = (pp[0], pp[0]);
+    for p in pp.iter() {
+        lo = lo.inf(p);
+        hi = hi.sup(p);
+    }
+    let eps = (hi - lo).norm().max(f64::MIN_POSITIVE) * 1e-9;
+
+    // Initial simplex: the extremes of the widest axis, then the farthest point from their line,
+    // then the farthest point from that plane.
+    let axis = (hi - lo).imax();
+    let i0 = (0..pp.len()).min_by(|&a, &b| pp[a][axis].total_cmp(&pp[b][axis]))?;
+    let i1 = (0..pp.len()).max_by(|&a, &b| pp[a][axis].total_cmp(&pp[b][axis]))?;
+    if (pp[i1] - pp[i0]).norm() <= eps {
+        return None;
+    }
+    let dir = (pp[i1] - pp[i0]).normalize();
+    let line_dist = |i: usize| {
+        let rel = pp[i] - pp[i0];
+        (rel - dir * rel.dot(&dir)).norm()
+    };
+    let i2 = (0..pp.len()).max_by(|&a, &b| line_dist(a).total_cmp(&line_dist(b)))?;
+    if line_dist(i2) <= eps {
+        return None;
+    }
+    let base = HullFace::new(&pp, [i0, i1, i2]);
+    let i3 = (0..pp.len())
+        .max_by(|&a, &b| base.dist(&pp[a]).abs().total_cmp(&base.dist(&pp[b]).abs()))?;
+    if base.dist(&pp[i3]).abs() <= eps {
+        return None;
+    }
+
+    let mut faces: Vec<HullFace> = Vec::new();
+    let mut edges: HashMap<(usize, usize), usize> = HashMap::new();
+    let push_face =
+        |faces: &mut Vec<HullFace>, edges: &mut HashMap<(usize, usize), usize>, f: HullFace| {
+            for e in f.edges().iter() {
+                edges.insert(*e, faces.len());
+            }
+            faces.push(f);
+            faces.len() - 1
+        };
+    for (tri, opp) in [
+        ([i0, i1, i2], i3),
+        ([i0, i1, i3], i2),
+        ([i0, i2, i3], i1),
+        ([i1, i2, i3], i0),
+    ] {
+        let mut f = HullFace::new(&pp, tri);
+        if f.dist(&pp[opp]) > 0. {
+            f = HullFace::new(&pp, [tri[0], tri[2], tri[1]]);
+        }
+        push_face(&mut faces, &mut edges, f);
+    }
+    let simplex = [i0, i1, i2, i3];
+    for i in (0..pp.len()).filter(|i| !simplex.contains(i)) {
+        assign_outside(&pp, &mut faces, 0..4, i, eps);
+    }
+
+    while let Some(fi) = faces.iter().position(|f| f.alive && !f.outside.is_empty()) {
+        let eye = *faces[fi]
+            .outside
+            .iter()
+            .max_by(|&&a, &&b| faces[fi].dist(&pp[a]).total_cmp(&faces[fi].dist(&pp[b])))
+            .expect("Outside set was checked to be nonempty.");
+
+        // Flood out from the face to find everything the eye point can see.
+        let mut visible = vec![fi];
+        let mut seen = HashSet::new();
+        seen.insert(fi);
+        let mut cursor = 0;
+        while cursor < visible.len() {
+            let curr = visible[cursor];
+            cursor += 1;
+            for (a, b) in faces[curr].edges() {
+                let neighbor = edges[&(b, a)];
+                if faces[neighbor].dist(&pp[eye]) > eps && seen.insert(neighbor) {
+                    visible.push(neighbor);
+                }
+            }
+        }
+        let horizon = visible
+            .iter()
+            .flat_map(|&f| faces[f].edges())
+            .filter(|&(a, b)| !seen.contains(&edges[&(b, a)]))
+            .collect::<Vec<_>>();
+
+        let mut orphans = vec![];
+        for &f in visible.iter() {
+            faces[f].alive = false;
+            orphans.append(&mut faces[f].outside);
+            for e in faces[f].edges() {
+                edges.remove(&e);
+            }
+        }
+        let first_new = faces.len();
+        for (a, b) in horizon {
+            let f = HullFace::new(&pp, [a, b, eye]);
+            push_face(&mut faces, &mut edges, f);
+        }
+        let new_faces = first_new..faces.len();
+        for i in orphans.into_iter().filter(|&i| i != eye) {
+            assign_outside(&pp, &mut faces, new_faces.clone(), i, eps);
+        }
+    }
+
+    let mut remap = HashMap::new();
+    let mut vv = vec![];
+    let ff = faces
+        .iter()
+        .filter(|f| f.alive)
+        .map(|f| {
+            f.vv.map(|i| {
+                *remap.entry(i).or_insert_with(|| {
+                    vv.push(points[i]);
+                    (vv.len() - 1) as u32
+                })
+            })
+        })
+        .collect();
+    Some(Hull { vv, ff })
+}
+
+fn assign_outside(
+    pp: &[Vector3<f64>],
+    faces: &mut [HullFace],
+    candidates: std::ops::Range<usize>,
+    i: usize,
+    eps: f64,
+) {
+    let best = candidates
+        .map(|f| (f, faces[f].dist(&pp[i])))
+        .filter(|(_, d)| *d > eps)
+        .max_by(|a, b| a.1.total_cmp(&b.1));
+    if let Some((f, _)) = best {
+        faces[f].outside.push(i);
+    }
+}
+
+impl TriMeshGeom {
+    /// Volume enclosed by the mesh. Only meaningful for closed meshes with consistent winding.
+    pub fn volume(&self) -> f32 {
+        signed_volume(
+            self.vv.column_iter().map(|c| c.into_owned()),
+            self.ff.column_iter().map(|c| [c[0], c[1], c[2]]),
+        )
+    }
+    /// Convex hull of the mesh's vertices.
+    pub fn convex_hull(&self, alloc: &mut MeshAlloc) -> Option<TriMeshGeom> {
+        let points = self
+            .vv
+            .column_iter()
+            .map(|c| c.into_owned())
+            .collect::<Vec<_>>();
+        quickhull(&points).map(|h| h.into_geom(alloc, self.tex_file.clone()))
+    }
+    /// Volume of the mesh's convex hull not covered by the mesh itself. Zero for convex meshes.
+    pub fn concavity(&self) -> f32 {
+        let points = self
+            .vv
+            .column_iter()
+            .map(|c| c.into_owned())
+            .collect::<Vec<_>>();
+        quickhull(&points).map_or(0., |h| (h.volume() - self.volume()).max(0.))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn cube_hull_ignores_interior_points() {
+        let mut pp = vec![];
+        for i in 0..8 {
+            pp.push(Vector3::new(
+                (i & 1) as f32,
+                ((i >> 1) & 1) as f32,
+                ((i >> 2) & 1) as f32,
+            ));
+        }
+        pp.push(Vector3::new(0.5, 0.5, 0.5));
+        pp.push(Vector3::new(0.25, 0.75, 0.5));
+        pp.push(Vector3::new(0.5, 0.5, 1.)); // on a face
+        let hull = quickhull(&pp).expect("Cube should have a hull.");
+        assert_eq!(hull.vv.len(), 8);
+        assert_eq!(hull.ff.len(), 12);
+        assert!(
+            (hull.volume() - 1.).abs() < 1e-5,
+            "Volume was {}.",
+            hull.volume()
+        );
+    }
+
+    #[test]
+    fn coplanar_points_have_no_hull() {
+        let pp = (0..10)
+            .map(|i| Vector3::new(i as f32, (i * i) as f32, 0.))
+            .collect::<Vec<_>>();
+        assert!(quickhull(&pp).is_none());
+    }
+
+    #[test]
+    fn sphere_hull_contains_all_points() {
+        let mut pp = vec![];
+        for i in 0..20 {
+            for j in 0..20 {
+                let (th, ph) = (i as f32 * 0.31, j as f32 * 0.17);
+                pp.push(
+                    Vector3::new(th.cos() * ph.sin(), th.sin() * ph.sin(), ph.cos())
+                        * (1. + (i % 3) as f32 * 0.1),
+                );
+            }
+        }
+        let hull = quickhull(&pp).expect("Sphere should have a hull.");
+        for f in hull.ff.iter() {
+            let [a, b, c] = f.map(|i| hull.vv[i as usize]);
+            let n = (b - a).cross(&(c - a)).normalize();
+            for p in pp.iter() {
+                assert!(n.dot(&(p - a)) < 1e-4);
+            }
+        }
+    }
+
+    #[test]
+    fn unit_cube_is_convex() {
+        let mut alloc = MeshAlloc::new();
+        let cube = crate::unit_cube(&mut alloc, None);
+        assert!((cube.volume() - 1.).abs() < 1e-5);
+        assert!(cube.concavity() < 1e-5);
+        let hull = cube.convex_hull(&mut alloc).unwrap();
+        assert!((hull.volume() - 1.).abs() < 1e-5);
+    }
+}
@@ -1,5 +1,9 @@
 pub mod tri;
 pub mod tet;
+pub mod hull;
+pub mod decomp;
+pub mod csg;
+pub mod uv;
 
 use std::{
     fmt::Debug,
@@ -0,0 +1,579 @@
+//! UV generation: projection mappings, and chart based unwrapping packed into a single atlas.
+//!
+//! Unwrapping splits the mesh into charts of faces with similar normals, flattens every chart with
+//! least squares conformal maps (LSCM), and packs the charts' bounding rectangles into the unit
+//! square. Vertices on chart seams (or wherever a projection needs them to) are duplicated, so
+//! every vertex ends up with exactly one `uv`.
+
+use std::collections::HashMap;
+
+use na::{Vector2, Vector3};
+
+use super::{tri::TriMeshGeom, FMat, MeshAlloc, VMat};
+
+#[derive(Debug, Copy, Clone)]
+pub enum Projection {
+    /// Orthographic projection along `normal`.
+    Planar { normal: Vector3<f32> },
+    /// Every face is projected along whichever axis its normal is closest to.
+    Box,
+    /// Wrapped around the line through `center` along `axis`. `u` follows the angle around the axis
+    /// and `v` the height along it.
+    Cylindrical {
+        center: Vector3<f32>,
+        axis: Vector3<f32>,
+    },
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct UnwrapParams {
+    /// Faces are only grouped into a chart if their normal is within this angle (in radians) of
+    /// the chart's first face.
+    pub max_chart_angle: f32,
+    /// Space left around every chart, as a fraction of the atlas's side.
+    pub padding: f32,
+}
+impl Default for UnwrapParams {
+    fn default() -> Self {
+        Self {
+            max_chart_angle: std::f32::consts::FRAC_PI_3,
+            padding: 0.01,
+        }
+    }
+}
+
+/// An orthonormal pair spanning the plane perpendicular to `n`, such that `(t, b, n)` is right
+/// handed.
+fn tangent_basis(n: &Vector3<f32>) -> (Vector3<f32>, Vector3<f32>) {
+    let helper = if n[0].abs() < 0.9 {
+        Vector3::x()
+    } else {
+        Vector3::y()
+    };
+    let t = helper.cross(n).normalize();
+    (t, n.cross(&t))
+}
+
+impl TriMeshGeom {
+    fn positions(&self) -> Vec<Vector3<f32>> {
+        self.vv.column_iter().map(|c| c.into_owned()).collect()
+    }
+    fn faces(&self) -> Vec<[u32; 3]> {
+        self.ff.column_iter().map(|c| [c[0], c[1], c[2]]).collect()
+    }
+
+    /// A copy of this mesh with UVs from `projection`, normalized into the unit square.
+    pub fn project_uvs(&self, alloc: &mut MeshAlloc, projection: Projection) -> TriMeshGeom {
+        let pp = self.positions();
+        let ff = self.faces();
+        let mut corners = match projection {
+            Projection::Planar { normal } => {
+                let (t, b) = tangent_basis(&normal.normalize());
+                ff.iter()
+                    .map(|f| {
+                        f.map(|i| Vector2::new(pp[i as usize].dot(&t), pp[i as usize].dot(&b)))
+                    })
+                    .collect::<Vec<_>>()
+            }
+            Projection::Box => ff
+                .iter()
+                .map(|f| {
+                    let [a, b, c] = f.map(|i| pp[i as usize]);
+                    let n = (b - a).cross(&(c - a));
+                    let k = n.iamax();
+                    let (i, j) = ((k + 1) % 3, (k + 2) % 3);
+                    let flip = if n[k] < 0. { -1. } else { 1. };
+                    f.map(|v| Vector2::new(flip * pp[v as usize][i], pp[v as usize][j]))
+                })
+                .collect(),
+            Projection::Cylindrical { center, axis } => {
+                let axis = axis.normalize();
+                let (t, b) = tangent_basis(&axis);
+                ff.iter()
+                    .map(|f| {
+                        let mut uvs = f.map(|i| {
+                            let d = pp[i as usize] - center;
+                            let angle = d.dot(&b).atan2(d.dot(&t));
+                            Vector2::new(angle / std::f32::consts::TAU + 0.5, d.dot(&axis))
+                        });
+                        // Faces straddling the seam get their low side wrapped around past 1.
+                        let (lo, hi) = uvs.iter().fold((f32::MAX, f32::MIN), |(lo, hi), uv| {
+                            (lo.min(uv[0]), hi.max(uv[0]))
+                        });
+                        if hi - lo > 0.5 {
+                            for uv in uvs.iter_mut().filter(|uv| uv[0] < 0.5) {
+                                uv[0] += 1.;
+                            }
+                        }
+                        uvs
+                    })
+                    .collect()
+            }
+        };
+        match projection {
+            // Angle is already normalized, so only the height needs fitting.
+            Projection::Cylindrical { .. } => {
+                let (lo, hi) = corners
+                    .iter()
+                    .flatten()
+                    .fold((f32::MAX, f32::MIN), |(lo, hi), uv| {
+                        (lo.min(uv[1]), hi.max(uv[1]))
+                    });
+                let extent = (hi - lo).max(f32::EPSILON);
+                for uv in corners.iter_mut().flatten() {
+                    uv[1] = (uv[1] - lo) / extent;
+                }
+            }
+            _ => normalize_into_unit_square(&mut corners),
+        }
+        self.with_corner_uvs(alloc, &corners)
+    }
+
+    /// A copy of this mesh with its UVs generated by LSCM charts packed into a single atlas.
+    pub fn unwrap_uvs(&self, alloc: &mut MeshAlloc, params: &UnwrapParams) -> TriMeshGeom {
+        let pp = self.positions();
+        let ff = self.faces();
+        // Vertices are frequently split along normal or UV seams, so charts are built on welded
+        // positions instead of indices.
+        let mut welded: HashMap<[u32; 3], usize> = HashMap::new();
+        let wid = pp
+            .iter()
+            .map(|p| {
+                let n = welded.len();
+                *welded
+                    .entry([p[0].to_bits(), p[1].to_bits(), p[2].to_bits()])
+                    .or_insert(n)
+            })
+            .collect::<Vec<_>>();
+        let face_normals = ff
+            .iter()
+            .map(|f| {
+                let [a, b, c] = f.map(|i| pp[i as usize]);
+                (b - a)
+                    .cross(&(c - a))
+                    .try_normalize(0.)
+                    .unwrap_or_else(Vector3::zeros)
+            })
+            .collect::<Vec<_>>();
+        let mut edge_faces: HashMap<(usize, usize), Vec<usize>> = HashMap::new();
+        for (fi, f) in ff.iter().enumerate() {
+            for (a, b) in [(f[0], f[1]), (f[1], f[2]), (f[2], f[0])] {
+                let (a, b) = (wid[a as usize], wid[b as usize]);
+                edge_faces.entry((a.min(b), a.max(b))).or_default().push(fi);
+            }
+        }
+
+        // Grow charts outwards from unassigned faces.
+        let min_cos = params.max_chart_angle.cos();
+        let mut chart_of = vec![usize::MAX; ff.len()];
+        let mut charts: Vec<Vec<usize>> = vec![];
+        for seed in 0..ff.len() {
+            if chart_of[seed] != usize::MAX {
+                continue;
+            }
+            let id = charts.len();
+            chart_of[seed] = id;
+            let mut chart = vec![seed];
+            let mut cursor = 0;
+            while cursor < chart.len() {
+                let f = ff[chart[cursor]];
+                cursor += 1;
+                for (a, b) in [(f[0], f[1]), (f[1], f[2]), (f[2], f[0])] {
+                    let (a, b) = (wid[a as usize], wid[b as usize]);
+                    for &other in edge_faces[&(a.min(b), a.max(b))].iter() {
+                        if chart_of[other] == usize::MAX
+                            && face_normals[other].dot(&face_normals[seed]) >= min_cos
+                        {
+                            chart_of[other] = id;
+                            chart.push(other);
+                        }
+                    }
+                }
+            }
+            charts.push(chart);
+        }
+
+        let mut corners = vec![[Vector2::zeros(); 3]; ff.len()];
+        let mut extents = Vec::with_capacity(charts.len());
+        for chart in charts.iter() {
+            let mut local: HashMap<usize, usize> = HashMap::new();
+            let mut cpp = vec![];
+            let cff = chart
+                .iter()
+                .map(|&fi| {
+                    ff[fi].map(|i| {
+                        *local.entry(wid[i as usize]).or_insert_with(|| {
+                            cpp.push(pp[i as usize]);
+                            cpp.len() - 1
+                        })
+                    })
+                })
+                .collect::<Vec<_>>();
+            let mut uvs = lscm(&cpp, &cff);
+            let (lo, hi) = uvs.iter().fold(
+                (Vector2::repeat(f32::MAX), Vector2::repeat(f32::MIN)),
+                |(lo, hi), uv| (lo.inf(uv), hi.sup(uv)),
+            );
+            for uv in uvs.iter_mut() {
+                *uv -= lo;
+            }
+            extents.push(hi - lo);
+            for (&fi, f) in chart.iter().zip(cff.iter()) {
+                corners[fi] = f.map(|i| uvs[i]);
+            }
+        }
+
+        let (offsets, side) = pack_rects(&extents, params.padding);
+        for (chart, offset) in charts.iter().zip(offsets.iter()) {
+            for &fi in chart.iter() {
+                for uv in corners[fi].iter_mut() {
+                    *uv = (*uv + offset) / side;
+                }
+            }
+        }
+        self.with_corner_uvs(alloc, &corners)
+    }
+
+    /// Rebuilds the mesh with a UV per face corner, duplicating vertices whose corners disagree.
+    fn with_corner_uvs(&self, alloc: &mut MeshAlloc, corners: &[[Vector2<f32>; 3]]) -> TriMeshGeom {
+        let mut ids: HashMap<(u32, [u32; 2]), u32> = HashMap::new();
+        let mut sources: Vec<(u32, Vector2<f32>)> = vec![];
+        let ff = self
+            .faces()
+            .iter()
+            .zip(corners.iter())
+            .flat_map(|(f, uvs)| {
+                [0, 1, 2].map(|k| {
+                    *ids.entry((f[k], [uvs[k][0].to_bits(), uvs[k][1].to_bits()]))
+                        .or_insert_with(|| {
+                            sources.push((f[k], uvs[k]));
+                            (sources.len() - 1) as u32
+                        })
+                })
+            })
+            .collect::<Vec<_>>();
+        TriMeshGeom::new(
+            alloc,
+            VMat::from_columns(
+                &sources
+                    .iter()
+                    .map(|(i, _)| self.vv.column(*i as usize).into_owned())
+                    .collect::<Vec<_>>(),
+            ),
+            FMat::from_iterator(ff.len() / 3, ff),
+            sources
+                .iter()
+                .map(|(i, _)| self.vec_vv[*i as usize].norm)
+                .collect(),
+            self.vec_ff.iter().map(|f| f.norm).collect(),
+            sources.iter().map(|(_, uv)| [uv[0], uv[1]]).collect(),
+            self.tex_file.clone(),
+        )
+    }
+}
+
+fn normalize_into_unit_square(corners: &mut [[Vector2<f32>; 3]]) {
+    let (lo, hi) = corners.iter().flatten().fold(
+        (Vector2::repeat(f32::MAX), Vector2::repeat(f32::MIN)),
+        |(lo, hi), uv| (lo.inf(uv), hi.sup(uv)),
+    );
+    let extent = (hi - lo).max().max(f32::EPSILON);
+    for uv in corners.iter_mut().flatten() {
+        *uv = (*uv - lo) / extent;
+    }
+}
+
+/// Flattens a chart with least squares conformal maps, pinning its two farthest apart vertices so
+/// that the result keeps roughly the same scale as the chart.
+pub fn lscm(pp: &[Vector3<f32>], ff: &[[usize; 3]]) -> Vec<Vector2<f32>> {
+    if pp.len() < 3 {
+        return vec![Vector2::zeros(); pp.len()];
+    }
+    let pp = pp.iter().map(|p| p.cast::<f64>()).collect::<Vec<_>>();
+    let farthest = |from: usize| {
+        (0..pp.len())
+            .max_by(|&a, &b| {
+                (pp[a] - pp[from])
+                    .norm_squared()
+                    .total_cmp(&(pp[b] - pp[from]).norm_squared())
+            })
+            .unwrap()
+    };
+    let p0 = farthest(0);
+    let p1 = farthest(p0);
+    let mut pinned = vec![None; pp.len()];
+    pinned[p0] = Some(Vector2::new(0., 0.));
+    pinned[p1] = Some(Vector2::new((pp[p1] - pp[p0]).norm(), 0.));
+    // Free vertices map into the unknown vector as (u, v) pairs.
+    let mut unknown = vec![usize::MAX; pp.len()];
+    let mut n = 0;
+    for (i, u) in unknown.iter_mut().enumerate() {
+        if pinned[i].is_none() {
+            *u = n;
+            n += 1;
+        }
+    }
+
+    // Each triangle contributes the real and imaginary part of sum_j W_j U_j, where W_j is the
+    // (complex) edge opposite of corner j in the triangle's own frame, and U_j = u_j + i v_j.
+    let mut rows: Vec<Vec<(usize, f64)>> = vec![];
+    let mut rhs: Vec<f64> = vec![];
+    for f in ff.iter() {
+        let [a, b, c] = f.map(|i| pp[i]);
+        let n_f = (b - a).cross(&(c - a));
+        let area2 = n_f.norm();
+        if area2 <= f64::EPSILON {
+            continue;
+        }
+        let x = (b - a).normalize();
+        let y = (n_f / area2).cross(&x);
+        let local = [
+            Vector2::zeros(),
+            Vector2::new((b - a).dot(&x), 0.),
+            Vector2::new((c - a).dot(&x), (c - a).dot(&y)),
+        ];
+        let scale = 1. / area2.sqrt();
+        let w = [0, 1, 2].map(|j| (local[(j + 2) % 3] - local[(j + 1) % 3]) * scale);
+        let (mut re, mut im) = (vec![], vec![]);
+        let (mut re_rhs, mut im_rhs) = (0., 0.);
+        for j in 0..3 {
+            let (wa, wb) = (w[j][0], w[j][1]);
+            match pinned[f[j]] {
+                Some(uv) => {
+                    re_rhs -= wa * uv[0] - wb * uv[1];
+                    im_rhs -= wb * uv[0] + wa * uv[1];
+                }
+                None => {
+                    let k = unknown[f[j]];
+                    re.extend([(2 * k, wa), (2 * k + 1, -wb)]);
+                    im.extend([(2 * k, wb), (2 * k + 1, wa)]);
+                }
+            }
+        }
+        rows.extend([re, im]);
+        rhs.extend([re_rhs, im_rhs]);
+    }
+    let x = least_squares_cg(&rows, &rhs, 2 * n);
+    (0..pp.len())
+        .map(|i| match pinned[i] {
+            Some(uv) => uv.cast(),
+            None => Vector2::new(x[2 * unknown[i]] as f32, x[2 * unknown[i] + 1] as f32),
+        })
+        .collect()
+}
+
+/// Minimizes |Ax - b| for a sparse `A` with conjugate gradients on the normal equations.
+fn least_squares_cg(rows: &[Vec<(usize, f64)>], b: &[f64], n: usize) -> Vec<f64> {
+    let apply = |x: &[f64]| {
+        rows.iter()
+            .map(|r| r.iter().map(|(k, w)| w * x[*k]).sum::<f64>())
+            .collect::<Vec<_>>()
+    };
+    let apply_t = |y: &[f64]| {
+        let mut out = vec![0.; n];
+        for (r, yi) in rows.iter().zip(y.iter()) {
+            for (k, w) in r.iter() {
+                out[*k] += w * yi;
+            }
+        }
+        out
+    };
+    let dot = |a: &[f64], b: &[f64]| a.iter().zip(b.iter()).map(|(a, b)| a * b).sum::<f64>();
+    let mut x = vec![0.; n];
+    let mut r = apply_t(b);
+    let mut p = r.clone();
+    let mut rr = dot(&r, &r);
+    let tolerance = rr * 1e-20;
+    for _ in 0..(n * 4).max(16) {
+        if rr <= tolerance || rr == 0. {
+            break;
+        }
+        let ap = apply_t(&apply(&p));
+        let alpha = rr / dot(&p, &ap);
+        for k in 0..n {
+            x[k] += alpha * p[k];
+            r[k] -= alpha * ap[k];
+        }
+        let rr_next = dot(&r, &r);
+        for k in 0..n {
+            p[k] = r[k] + p[k] * (rr_next / rr);
+        }
+        rr = rr_next;
+    }
+    x
+}
+
+/// Shelf packs rectangles of the given sizes into a square.
+///
+/// Returns the offset of every rectangle and the side of the square. `padding` is relative to the
+/// side, and is left around every rectangle.
+pub fn pack_rects(sizes: &[Vector2<f32>], padding: f32) -> (Vec<Vector2<f32>>, f32) {
+    let area: f32 = sizes.iter().map(|s| s[0] * s[1]).sum();
+    let widest = sizes.iter().map(|s| s[0]).fold(0., f32::max);
+    let pad = area.sqrt() * padding;
+    let mut order = (0..sizes.len()).collect::<Vec<_>>();
+    order.sort_by(|&a, &b| sizes[b][1].total_cmp(&sizes[a][1]));
+    // Aim for a square, but never narrower than the widest rectangle.
+    let width = (area.sqrt() * 1.1).max(widest) + 2. * pad;
+    let mut offsets = vec![Vector2::zeros(); sizes.len()];
+    let (mut x, mut y, mut shelf) = (pad, pad, 0f32);
+    let mut used_width = 0f32;
+    for i in order {
+        if x + sizes[i][0] + pad > width && x > pad {
+            x = pad;
+            y += shelf + pad;
+            shelf = 0.;
+        }
+        offsets[i] = Vector2::new(x, y);
+        x += sizes[i][0] + pad;
+        used_width = used_width.max(x);
+        shelf = shelf.max(sizes[i][1]);
+    }
+    let side = used_width.max(y + shelf + pad).max(f32::EPSILON);
+    (offsets, side)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn uvs(m: &TriMeshGeom) -> Vec<Vector2<f32>> {
+        m.vec_vv.iter().map(|v| Vector2::from(v.uv)).collect()
+    }
+
+    fn signed_uv_area(m: &TriMeshGeom, f: usize) -> f32 {
+        let uv = uvs(m);
+        let [a, b, c] = [0, 1, 2].map(|k| uv[m.ff[(k, f)] as usize]);
+        (b - a).perp(&(c - a))
+    }
+
+    #[test]
+    fn planar_projection_of_plane() {
+        let mut alloc = MeshAlloc::new();
+        let plane = crate::plane(&mut alloc, None).project_uvs(
+            &mut alloc,
+            Projection::Planar {
+                normal: Vector3::y(),
+            },
+        );
+        assert_eq!(plane.vec_vv.len(), 4);
+        for uv in uvs(&plane) {
+            assert!(
+                (uv[0] == 0. || uv[0] == 1.) && (uv[1] == 0. || uv[1] == 1.),
+                "Unexpected uv {:?}.",
+                uv
+            );
+        }
+        for f in 0..plane.ff.ncols() {
+            assert!(signed_uv_area(&plane, f) > 0.);
+        }
+    }
+
+    #[test]
+    fn box_projection_of_cube() {
+        let mut alloc = MeshAlloc::new();
+        let cube = crate::unit_cube(&mut alloc, None).project_uvs(&mut alloc, Projection::Box);
+        for f in 0..cube.ff.ncols() {
+            assert!((signed_uv_area(&cube, f) - 1.).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn cylindrical_projection_wraps_seam() {
+        let mut alloc = MeshAlloc::new();
+        let cube = crate::unit_cube(&mut alloc, None);
+        let wrapped = cube.project_uvs(
+            &mut alloc,
+            Projection::Cylindrical {
+                center: Vector3::zeros(),
+                axis: Vector3::y(),
+            },
+        );
+        assert!(wrapped.vec_vv.len() > cube.vec_vv.len());
+        for f in wrapped.ff.column_iter() {
+            let u = [0, 1, 2].map(|k| wrapped.vec_vv[f[k] as usize].uv[0]);
+            assert!(
+                u.iter().cloned().fold(f32::MIN, f32::max)
+                    - u.iter().cloned().fold(f32::MAX, f32::min)
+                    <= 0.5
+            );
+        }
+    }
+
+    #[test]
+    fn lscm_keeps_flat_charts_undistorted() {
+        // A skewed, flat grid should flatten into a similar copy of itself.
+        let mut pp = vec![];
+        for y in 0..4 {
+            for x in 0..5 {
+                pp.push(Vector3::new(x as f32 + 0.3 * y as f32, y as f32 * 0.7, 0.));
+            }
+        }
+        let mut ff = vec![];
+        for y in 0..3 {
+            for x in 0..4 {
+                let i = y * 5 + x;
+                ff.push([i, i + 1, i + 6]);
+                ff.push([i, i + 6, i + 5]);
+            }
+        }
+        let uv = lscm(&pp, &ff);
+        for [a, b, c] in ff.iter().copied() {
+            let l3 = [(a, b), (b, c), (c, a)].map(|(i, j)| (pp[i] - pp[j]).norm());
+            let l2 = [(a, b), (b, c), (c, a)].map(|(i, j)| (uv[i] - uv[j]).norm());
+            for k in 0..3 {
+                assert!(
+                    (l3[k] - l2[k]).abs() < 1e-3,
+                    "Edge {} of {:?} changed from {} to {}.",
+                    k,
+                    (a, b, c),
+                    l3[k],
+                    l2[k]
+                );
+            }
+            assert!((uv[b] - uv[a]).perp(&(uv[c] - uv[a])) > 0.);
+        }
+    }
+
+    #[test]
+    fn unwrapped_cube_charts_do_not_overlap() {
+        let mut alloc = MeshAlloc::new();
+        let cube =
+            crate::unit_cube(&mut alloc, None).unwrap_uvs(&mut alloc, &UnwrapParams::default());
+        assert_eq!(cube.ff.ncols(), 12);
+        for uv in uvs(&cube) {
+            assert!(
+                uv.iter().all(|x| (0. ..=1.).contains(x)),
+                "UV {:?} is outside of the atlas.",
+                uv
+            );
+        }
+        // Each side is its own chart, so the atlas holds 6 non overlapping unit squares.
+        let total: f32 = (0..12).map(|f| signed_uv_area(&cube, f).abs() / 2.).sum();
+        let side_area = total / 6.;
+        for f in 0..12 {
+            assert!((signed_uv_area(&cube, f).abs() / 2. - side_area / 2.).abs() < 1e-4);
+        }
+        assert!(total <= 1.);
+        let uv = uvs(&cube);
+        let normal = |f: usize| {
+            let [a, b, c] =
+                [0, 1, 2].map(|k| cube.vv.column(cube.ff[(k, f)] as usize).into_owned());
+            (b - a).cross(&(c - a)).normalize()
+        };
+        let tri = |f: usize| [0, 1, 2].map(|k| uv[cube.ff[(k, f)] as usize]);
+        let inside = |p: Vector2<f32>, [a, b, c]: [Vector2<f32>; 3]| {
+            (b - a).perp(&(p - a)) > 1e-6
+                && (c - b).perp(&(p - b)) > 1e-6
+                && (a - c).perp(&(p - c)) > 1e-6
+        };
+        for f in 0..12 {
+            assert!(signed_uv_area(&cube, f) > 0.);
+            let [a, b, c] = tri(f);
+            let centroid = (a + b + c) / 3.;
+            for g in (0..12).filter(|&g| normal(g).dot(&normal(f)) < 0.5) {
+                assert!(!inside(centroid, tri(g)), "Faces {} and {} overlap.", f, g);
+            }
+        }
+    }
+}
@@ -1,5 +1,6 @@
 use crate::{Model, geom::tri::TriMeshGeom};
 use std::sync::Arc;
+use sync::triple_buffer::{DirtyRanges, Patch};
 
 #[derive(Debug)]
 pub struct Static {
@@ -9,6 +10,15 @@ pub struct Static {
 pub struct Dynamic {
     pub mm: Vec<Model>,
 }
+/// Dirty ranges index into `mm`.
+impl Patch for Dynamic {
+    fn patch(&mut self, src: &Self, dirty: &DirtyRanges) {
+        self.mm.patch(&src.mm, dirty);
+    }
+    fn elements(&self) -> usize {
+        self.mm.elements()
+    }
+}
 
 pub struct Scene(Static, Dynamic);
 impl Scene {
@@ -1,12 +1,13 @@
 pub mod task;
 mod shaders;
 
-use std::{sync::Arc, collections::{HashMap, hash_map::Entry}, time::{Instant, Duration}};
+use std::{sync::Arc, collections::{HashMap, hash_map::Entry}};
 
 use img::{ImageDecoder, codecs::png::{PngDecoder, PngReader}, ImageFormat};
 use raw_window_handle::HandleError;
 use tap::{TapFallible, TapOptional};
 use task::RenderTask;
+use th::profile;
 use thiserror::Error;
 
 use vulkano::{
@@ -789,70 +790,7 @@ impl Renderer {
     }
 
     pub fn render_to<'a>(&mut self, window: Arc<Window>, task: RenderTask<'a>) -> Result<(), Validated<VulkanError>> {
-        struct RenderTimer {
-            start: Instant,
-            inflight_load: Option<Instant>,
-            loads: Vec<Duration>,
-            inflight_command_record: Option<Instant>,
-            command_record_duration: Option<Duration>,
-            inflight_draw: Option<Instant>,
-            draw_duration: Option<Duration>,
-        }
-
-        impl RenderTimer {
-            fn begin() -> Self {
-                Self {
-                    start: Instant::now(),
-                    inflight_load: None,
-                    loads: Vec::with_capacity(20),
-                    inflight_command_record: None,
-                    command_record_duration: None,
-                    inflight_draw: None,
-                    draw_duration: None,
-                }
-            }
-
-            fn record_load_start(&mut self) {
-                self.inflight_load = Some(Instant::now());
-            }
-
-            fn record_load_end(&mut self) {
-                let Some(start) = self.inflight_load else {
-                    return;
-                };
-                self.loads.push(Instant::now() - start);
-            }
-
-            fn record_command_record_start(&mut self) {
-                self.inflight_command_record = Some(Instant::now());
-            }
-
-            fn record_command_record_end(&mut self) {
-                let Some(start) = self.inflight_command_record else {
-                    return;
-                };
-                self.command_record_duration = Some(Instant::now() - start);
-            }
-
-            fn record_draw_start(&mut self) {
-                self.inflight_draw = Some(Instant::now());
-            }
-
-            fn record_draw_end(&mut self) {
-                let Some(start) = self.inflight_draw else {
-                    return;
-                };
-                self.draw_duration = Some(Instant::now() - start);
-            }
-
-            fn record_end(self) {
-                let over = Instant::now() - self.start;
-                let a = 1000. / over.as_millis() as f64;
-                log::info!("RENDER-PASS-TIMING fps={a} loads={:?} submit={:?} draw={:?} total={:?}", self.loads, self.command_record_duration, self.draw_duration, over);
-            }
-        }
-
-        let mut perf = RenderTimer::begin();
+        let _frame = profile::scope("Renderer::render_to");
 
 
         let mut e = self.windowed_swapchain.entry(window.id());
@@ -879,7 +817,7 @@ impl Renderer {
                     continue;
                 }
 
-                perf.record_load_start();
+                let _load = profile::scope("load mesh");
 
                 Self::copy_sized_slice_to_buffer(&self.vertex_buffer.clone().slice(self.vertex_free_byte_start..), draw.mesh.vec_vv.as_slice()).unwrap();
                 Self::copy_sized_slice_to_buffer(&self.face_buffer.clone().slice(self.index_free_byte_start..), draw.mesh.ff.as_slice()).unwrap();
@@ -958,15 +896,12 @@ impl Renderer {
                 self.index_free_byte_start += fblen;
 
                 log::info!("RENDER-COPY mesh={mesh_id} vertex_start={} vertex_len={vblen} face_start={} face_len={fblen}", self.vertex_free_byte_start, self.index_free_byte_start);
-
-                perf.record_load_end();
             }
 
-            perf.record_load_start();
+            let _upload = profile::scope("upload uniforms");
             Self::copy_sized_slice_to_buffer(&self.uniform_per_mesh_buffer, task.instancing_information_bytes().as_slice()).unwrap();
             Self::copy_sized_slice_to_buffer(&self.uniform_light_buffer, task.lights.to_bytes().as_slice()).unwrap();
             Self::copy_sized_slice_to_buffer(&self.uniform_counts_buffer, &[0u32, task.lights.0.len() as u32, 0u32, 0u32]).unwrap();
-            perf.record_load_end();
         }
 
         let (active_framebuffer, afidx, framebuffer_future) = {
@@ -1080,7 +1015,7 @@ impl Renderer {
         ).unwrap();
         log::info!("RENDER-PASS-PIPELINE descriptor_set={}", pipeline.num_used_descriptor_sets());
 
-        perf.record_command_record_start();
+        let record = profile::scope("record commands");
 
         let base_queue = &self.selected_device_queues[0];
         let mut builder = AutoCommandBufferBuilder::primary(
@@ -1182,9 +1117,9 @@ impl Renderer {
             .unwrap();
         let clear_buffer = builder.build().unwrap();
 
-        perf.record_command_record_end();
+        drop(record);
 
-        perf.record_draw_start();
+        let present = profile::scope("submit and present");
 
         vulkano::sync::now(Arc::clone(&self.selected_device))
             .join(framebuffer_future)
@@ -1194,9 +1129,8 @@ impl Renderer {
             .flush()
             .unwrap();
 
-        perf.record_draw_end();
+        drop(present);
 
-        perf.record_end();
         log::info!("RENDER-PASS-COMPLETE");
 
         Ok(())
@@ -1,6 +1,8 @@
 #![feature(unboxed_closures, fn_traits)]
 
-use std::{sync::{Arc, mpsc::{self, Receiver}, Mutex}, borrow::Cow, time::Instant};
+use std::{sync::{Arc, atomic::{AtomicBool, Ordering}, mpsc::{self, Receiver, Sender}, Mutex}, borrow::Cow, time::Instant};
+
+use events::{action::{ActionMap, ActionValue}, hal::b};
 
 use model::{geom::{tri::TriMeshGeom, MeshAlloc}, AffineTransform, camera::{Camera, PerspectiveCamera}};
 use na::{Matrix3, Vector3, UnitQuaternion, UnitVector3, Vector4};
@@ -414,6 +416,7 @@ fn main() {
     std::thread::spawn(RenderThread::new(Arc::clone(&sim_state), &window));
 
     // We could *try* to seed this, but I'm lazy.
+    let (mut actions, exiting) = bind_actions(&tx);
     let mut warp_mouse_detected = false;
     let mut last_mouse_x = None;
     let mut last_mouse_y = None;
@@ -428,43 +431,14 @@ fn main() {
                 _ => {},
             },
             Event::DeviceEvent { device_id: _device_id, event } => match event {
-                DeviceEvent::Key(key_in) => match key_in.physical_key {
-                    PhysicalKey::Code(keycode) => match keycode {
+                DeviceEvent::Key(key_in) => {
+                    if let Some(button) = to_button(key_in.physical_key) {
                         // We'll just ignore modifiers for now.
-                        KeyCode::Escape => { elwt.exit(); },
-                        KeyCode::KeyW => {
-                            tx.send(WorldEvent::SetMoveForward(key_in.state.is_pressed())).unwrap();
-                        },
-                        KeyCode::KeyA => {
-                            tx.send(WorldEvent::SetMoveLeft(key_in.state.is_pressed())).unwrap();
-                        },
-                        KeyCode::KeyS => {
-                            tx.send(WorldEvent::SetMoveBackward(key_in.state.is_pressed())).unwrap();
-                        },
-                        KeyCode::KeyD => {
-                            tx.send(WorldEvent::SetMoveRight(key_in.state.is_pressed())).unwrap();
-                        },
-                        KeyCode::KeyQ => {
-                            tx.send(WorldEvent::SetRollRight(key_in.state.is_pressed())).unwrap();
-                        },
-                        KeyCode::KeyE => {
-                            tx.send(WorldEvent::SetRollLeft(key_in.state.is_pressed())).unwrap();
-                        },
-                        KeyCode::Space => {
-                            tx.send(WorldEvent::SetMoveUp(key_in.state.is_pressed())).unwrap();
-                        },
-                        KeyCode::ControlLeft => {
-                            tx.send(WorldEvent::SetMoveDown(key_in.state.is_pressed())).unwrap();
-                        },
-                        KeyCode::KeyN => {
-                            tx.send(WorldEvent::ShiftBackground(key_in.state.is_pressed())).unwrap();
-                        },
-                        KeyCode::Tab => {
-                            tx.send(WorldEvent::ToggleWireFrame(key_in.state.is_pressed())).unwrap();
-                        },
-                        _ => {},
-                    },
-                    PhysicalKey::Unidentified(_native) => {},
+                        actions.handle(&b::V(button, key_in.state.is_pressed().into(), b::Mods::NONE).into());
+                        if exiting.load(Ordering::Relaxed) {
+                            elwt.exit();
+                        }
+                    }
                 },
                 DeviceEvent::MouseMotion { delta: (maybe_xd, maybe_yd) } => {
                     let (xd, yd) = match FORCE_MOUSE_MOTION_MODE {
@@ -508,6 +482,134 @@ fn main() {
     }).unwrap();
 }
 
+/// Loads the viewer's bindings, and has its actions send the world events they stand for. The flag
+/// is raised once the viewer should exit.
+fn bind_actions(tx: &Sender<WorldEvent>) -> (ActionMap, Arc<AtomicBool>) {
+    let mut actions = ActionMap::load("resources/bindings.cfg").expect("Could not load bindings!");
+    actions.push("viewer");
+    let exiting = Arc::new(AtomicBool::new(false));
+    {
+        let exiting = Arc::clone(&exiting);
+        actions.subscribe("exit", move |v| {
+            if let ActionValue::Digital(true) = v {
+                exiting.store(true, Ordering::Relaxed);
+            }
+        });
+    }
+    {
+        let tx = tx.clone();
+        actions.subscribe("move", move |v| {
+            if let ActionValue::Axis2D(xy) = v {
+                tx.send(WorldEvent::SetMoveForward(xy.y > 0.)).unwrap();
+                tx.send(WorldEvent::SetMoveBackward(xy.y < 0.)).unwrap();
+                tx.send(WorldEvent::SetMoveRight(xy.x > 0.)).unwrap();
+                tx.send(WorldEvent::SetMoveLeft(xy.x < 0.)).unwrap();
+            }
+        });
+    }
+    {
+        let tx = tx.clone();
+        actions.subscribe("rise", move |v| {
+            if let ActionValue::Axis1D(y) = v {
+                tx.send(WorldEvent::SetMoveUp(*y > 0.)).unwrap();
+                tx.send(WorldEvent::SetMoveDown(*y < 0.)).unwrap();
+            }
+        });
+    }
+    {
+        let tx = tx.clone();
+        actions.subscribe("roll", move |v| {
+            if let ActionValue::Axis1D(r) = v {
+                tx.send(WorldEvent::SetRollLeft(*r > 0.)).unwrap();
+                tx.send(WorldEvent::SetRollRight(*r < 0.)).unwrap();
+            }
+        });
+    }
+    {
+        let tx = tx.clone();
+        actions.subscribe("shift_background", move |v| {
+            if let ActionValue::Digital(down) = v {
+                tx.send(WorldEvent::ShiftBackground(*down)).unwrap();
+            }
+        });
+    }
+    {
+        let tx = tx.clone();
+        actions.subscribe("toggle_wireframe", move |v| {
+            if let ActionValue::Digital(down) = v {
+                tx.send(WorldEvent::ToggleWireFrame(*down)).unwrap();
+            }
+        });
+    }
+    (actions, exiting)
+}
+
+/// The button a physical key is bound as. Keys that print something are named after what they print
+/// on a US layout.
+fn to_button(key: PhysicalKey) -> Option<b::C> {
+    use b::{Key, Side};
+    let key = match key {
+        PhysicalKey::Code(key) => key,
+        PhysicalKey::Unidentified(_native) => return None,
+    };
+    Some(match key {
+        KeyCode::KeyA => b::C::A('a'),
+        KeyCode::KeyB => b::C::A('b'),
+        KeyCode::KeyC => b::C::A('c'),
+        KeyCode::KeyD => b::C::A('d'),
+        KeyCode::KeyE => b::C::A('e'),
+        KeyCode::KeyF => b::C::A('f'),
+        KeyCode::KeyG => b::C::A('g'),
+        KeyCode::KeyH => b::C::A('h'),
+        KeyCode::KeyI => b::C::A('i'),
+        KeyCode::KeyJ => b::C::A('j'),
+        KeyCode::KeyK => b::C::A('k'),
+        KeyCode::KeyL => b::C::A('l'),
+        KeyCode::KeyM => b::C::A('m'),
+        KeyCode::KeyN => b::C::A('n'),
+        KeyCode::KeyO => b::C::A('o'),
+        KeyCode::KeyP => b::C::A('p'),
+        KeyCode::KeyQ => b::C::A('q'),
+        KeyCode::KeyR => b::C::A('r'),
+        KeyCode::KeyS => b::C::A('s'),
+        KeyCode::KeyT => b::C::A('t'),
+        KeyCode::KeyU => b::C::A('u'),
+        KeyCode::KeyV => b::C::A('v'),
+        KeyCode::KeyW => b::C::A('w'),
+        KeyCode::KeyX => b::C::A('x'),
+        KeyCode::KeyY => b::C::A('y'),
+        KeyCode::KeyZ => b::C::A('z'),
+        KeyCode::Digit0 => b::C::A('0'),
+        KeyCode::Digit1 => b::C::A('1'),
+        KeyCode::Digit2 => b::C::A('2'),
+        KeyCode::Digit3 => b::C::A('3'),
+        KeyCode::Digit4 => b::C::A('4'),
+        KeyCode::Digit5 => b::C::A('5'),
+        KeyCode::Digit6 => b::C::A('6'),
+        KeyCode::Digit7 => b::C::A('7'),
+        KeyCode::Digit8 => b::C::A('8'),
+        KeyCode::Digit9 => b::C::A('9'),
+        KeyCode::Space => b::C::A(' '),
+        KeyCode::Escape => Key::Esc.into(),
+        KeyCode::Tab => Key::Tab.into(),
+        KeyCode::Enter => Key::Enter.into(),
+        KeyCode::Backspace => Key::Backspace.into(),
+        KeyCode::AltLeft => Key::Alt(Side::L).into(),
+        KeyCode::AltRight => Key::Alt(Side::R).into(),
+        KeyCode::ShiftLeft => Key::Shift(Side::L).into(),
+        KeyCode::ShiftRight => Key::Shift(Side::R).into(),
+        KeyCode::ControlLeft => Key::Ctrl(Side::L).into(),
+        KeyCode::ControlRight => Key::Ctrl(Side::R).into(),
+        KeyCode::SuperLeft => Key::Mod(Side::L).into(),
+        KeyCode::SuperRight => Key::Mod(Side::R).into(),
+        KeyCode::ArrowUp => Key::Up.into(),
+        KeyCode::ArrowLeft => Key::Left.into(),
+        KeyCode::ArrowDown => Key::Down.into(),
+        KeyCode::ArrowRight => Key::Right.into(),
+        _ => return None,
+    })
+}
+
 fn calc_relative_motion(last: &mut Option<f64>, curr: f64) -> f64 {
     match last {
         None => {
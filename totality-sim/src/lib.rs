@@ -1,9 +1,16 @@
 pub mod linkage;
 use linkage::*;
 
-use std::time::Duration;
+use std::{convert::Infallible, time::Duration};
 
 use log::{info, trace};
+use th::{
+    builder::{Flow, Pacing, ThreadBuilder},
+    clock::{Clock, SystemClock},
+    control::Control,
+    killable_thread::KillableThread,
+    profile,
+};
 
 pub trait PhysicsHook<T>: FnMut(&geom::scene::Static, &mut T) + Send + 'static {}
 impl<T, F: FnMut(&geom::scene::Static, &mut T) + Send + 'static> PhysicsHook<T> for F {}
@@ -17,6 +24,7 @@ struct Simulation<T: Simulated, DL: DataLinkage<T>> {
 unsafe impl<T: Simulated, DL: DataLinkage<T>> Send for Simulation<T, DL> {}
 impl<T: Simulated, DL: DataLinkage<T>> Simulation<T, DL> {
     pub fn step(&mut self) {
+        let _span = profile::scope("Simulation::step");
         trace!("Simulating a single step.");
         // call pre
         // simulate
@@ -33,30 +41,38 @@ impl<T: Simulated, DL: DataLinkage<T>> Simulation<T, DL> {
         dlink: DL,
         post_cbs: Vec<Box<dyn PhysicsHook<T>>>,
         pre_cbs: Vec<Box<dyn PhysicsHook<T>>>,
-    ) -> Result<th::killable_thread::KillableThread<(), ()>, std::io::Error> {
-        th::create_duration_kt!(d, "Simulation", {
-            let mut sim = {
-                Simulation {
+        clock: impl Clock + 'static,
+    ) -> Result<SimThread, std::io::Error> {
+        ThreadBuilder::new("Simulation")
+            .pacing(Pacing::Duration(d))
+            .clock(clock)
+            .setup(move || {
+                Ok(Simulation {
                     dlink,
-                    post_cbs: post_cbs,
-                    pre_cbs: pre_cbs,
+                    post_cbs,
+                    pre_cbs,
                     time_step: d,
-                }
-            }
-        }, {
-            // for pre in sim.pre_cbs {
-            //     pre(sim.mutated.statics, sim.mutated.dynamics);
-            // }
-            sim.step();
-            // for post in sim.post_cbs {
-            //     post(sc);
-            // }
-        }, {})
+                })
+            })
+            .body(|sim| {
+                // for pre in sim.pre_cbs {
+                //     pre(sim.mutated.statics, sim.mutated.dynamics);
+                // }
+                sim.step();
+                // for post in sim.post_cbs {
+                //     post(sc);
+                // }
+                Ok(Flow::Continue)
+            })
+            .teardown(|_| Ok(()))
+            .spawn()
     }
 }
 
+type SimThread = KillableThread<Control<()>, Result<(), Infallible>>;
+
 pub struct Manager {
-    sim_th: Option<th::killable_thread::KillableThread<(), ()>>,
+    sim_th: Option<SimThread>,
 }
 impl Manager {
     pub fn new<T: Simulated, DL: DataLinkage<T>>(
@@ -64,9 +80,19 @@ impl Manager {
         dl: DL,
         post_cbs: Vec<Box<dyn PhysicsHook<T>>>,
         pre_cbs: Vec<Box<dyn PhysicsHook<T>>>,
+    ) -> Result<Self, std::io::Error> {
+        Self::with_clock(d, dl, post_cbs, pre_cbs, SystemClock)
+    }
+    /// Like `new`, but paces the simulation with `clock`.
+    pub fn with_clock<T: Simulated, DL: DataLinkage<T>>(
+        d: Duration,
+        dl: DL,
+        post_cbs: Vec<Box<dyn PhysicsHook<T>>>,
+        pre_cbs: Vec<Box<dyn PhysicsHook<T>>>,
+        clock: impl Clock + 'static,
     ) -> Result<Self, std::io::Error> {
         Ok(Self {
-            sim_th: Some(Simulation::as_thread(d, dl, post_cbs, pre_cbs)?),
+            sim_th: Some(Simulation::as_thread(d, dl, post_cbs, pre_cbs, clock)?),
         })
     }
 }
@@ -76,3 +102,65 @@ impl Drop for Manager {
         drop(self.sim_th.take());
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::{cell::UnsafeCell, sync::mpsc, time::Instant};
+
+    use th::clock::ManualClock;
+
+    use super::*;
+
+    struct Steps(u32);
+    impl Simulated for Steps {
+        fn step(_: Duration, source: &Self, target: &mut Self) {
+            target.0 = source.0 + 1;
+        }
+    }
+
+    /// Steps back and forth between two values, reporting each step and when it started.
+    struct Ticks {
+        source: UnsafeCell<Steps>,
+        target: UnsafeCell<Steps>,
+        clock: ManualClock,
+        tx: mpsc::Sender<(u32, Instant)>,
+    }
+    impl DataLinkage<Steps> for Ticks {
+        fn advance(&self) -> Option<DataLinkageGuard<'_, Steps, Self>> {
+            let _ = self.tx.send((self.source().unwrap().0, self.clock.now()));
+            Some(DataLinkageGuard::new(self))
+        }
+        fn source(&self) -> Option<&Steps> {
+            Some(unsafe { &*self.source.get() })
+        }
+        fn target(&self) -> Option<&mut Steps> {
+            Some(unsafe { &mut *self.target.get() })
+        }
+        fn cleanup(&self) {
+            unsafe { std::ptr::swap(self.source.get(), self.target.get()) };
+        }
+    }
+
+    #[test]
+    fn steps_keep_time_with_the_clock() {
+        let clock = ManualClock::new();
+        let start = clock.now();
+        let (tx, rx) = mpsc::channel();
+        let step = Duration::from_millis(10);
+        let ticks = Ticks {
+            source: UnsafeCell::new(Steps(0)),
+            target: UnsafeCell::new(Steps(0)),
+            clock: clock.clone(),
+            tx,
+        };
+        let man = Manager::with_clock(step, ticks, vec![], vec![], clock).unwrap();
+        // Sleeping on a manual clock returns right away, so each step starts exactly one step after
+        // the last, however long it actually took.
+        for n in 0..100 {
+            let (steps, at) = rx.recv_timeout(Duration::from_millis(500)).unwrap();
+            assert_eq!(steps, n);
+            assert_eq!(at - start, step * n);
+        }
+        drop(man);
+    }
+}
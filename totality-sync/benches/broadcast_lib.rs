@@ -0,0 +1,71 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use totality_sync::broadcast::buffer;
+
+pub fn benchmark(c: &mut Criterion) {
+    let (mut r, mut e) = buffer::<[f32; 16]>([0.; 16]);
+    {
+        let mut uncontended = c.benchmark_group("broadcast uncontended");
+        uncontended.bench_function("read", |b| {
+            b.iter(|| {
+                black_box(r.grab()[0]);
+            });
+        });
+        uncontended.bench_function("check", |b| {
+            b.iter(|| {
+                black_box(r.has_new());
+            });
+        });
+        uncontended.bench_function("publish", |b| {
+            b.iter(|| {
+                e.publish(black_box([0.; 16]));
+            });
+        });
+        uncontended.bench_function("transmit", |b| {
+            b.iter(|| {
+                e.publish(black_box([0.; 16]));
+                black_box(r.grab()[0]);
+            });
+        });
+    }
+
+    let mut contender = r.clone();
+    {
+        let mut read_contended = c.benchmark_group("broadcast read contention");
+        testbench::run_under_contention(
+            || {
+                black_box(contender.grab()[0]);
+            },
+            || {
+                read_contended.bench_function("publish", |b| {
+                    b.iter(|| {
+                        e.publish(black_box([0.; 16]));
+                    })
+                });
+            },
+        );
+    }
+
+    {
+        let mut write_contended = c.benchmark_group("broadcast write contention");
+        testbench::run_under_contention(
+            || {
+                e.publish(black_box([0.; 16]));
+            },
+            || {
+                write_contended.bench_function("read", |b| {
+                    b.iter(|| {
+                        black_box(r.grab()[0]);
+                    })
+                });
+                write_contended.bench_function("check", |b| {
+                    b.iter(|| {
+                        black_box(r.has_new());
+                    })
+                });
+            },
+        );
+    }
+}
+
+criterion_group!(benches, benchmark);
+criterion_main!(benches);
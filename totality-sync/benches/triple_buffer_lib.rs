@@ -1,82 +1,83 @@
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
-use totality_sync::triple_buffer::{buffer, TripleBuffer};
+use totality_sync::triple_buffer::buffer;
 
 pub fn benchmark(c: &mut Criterion) {
-    let buffer = TripleBuffer::<u8>::raw(0);
+    let (mut r, mut e) = buffer::<u8>(0);
     {
         let mut uncontended = c.benchmark_group("uncontended");
         uncontended.bench_function("read output", |b| {
+            let g = r.grab();
             b.iter(|| {
-                black_box(*buffer.reader_r());
+                black_box(*g);
             });
         });
         uncontended.bench_function("clean update", |b| {
             b.iter(|| {
-                buffer.snatch();
+                r.grab();
             });
         });
         uncontended.bench_function("clean receive", |b| {
             b.iter(|| {
-                buffer.snatch();
-                black_box(*buffer.reader_r());
+                black_box(*r.grab());
             });
         });
         uncontended.bench_function("write input", |b| {
+            let mut g = e.grab();
             b.iter(|| {
-                *buffer.editor_w() = black_box(0);
+                *g.w() = black_box(0);
             });
         });
         uncontended.bench_function("publish", |b| {
             b.iter(|| {
-                buffer.advance();
+                e.grab().commit();
             });
         });
         uncontended.bench_function("send", |b| {
             b.iter(|| {
-                *buffer.editor_w() = black_box(0);
-                buffer.advance();
+                let mut g = e.grab();
+                *g.w() = black_box(0);
+                g.commit();
             });
         });
         uncontended.bench_function("publish + dirty update", |b| {
             b.iter(|| {
-                buffer.advance();
-                buffer.snatch();
+                e.grab().commit();
+                r.grab();
             });
         });
         uncontended.bench_function("transmit", |b| {
             b.iter(|| {
-                *buffer.editor_w() = black_box(0);
-                buffer.advance();
-                buffer.snatch();
-                black_box(*buffer.reader_r());
+                let mut g = e.grab();
+                *g.w() = black_box(0);
+                g.commit();
+                black_box(*r.grab());
             });
         });
     }
 
-    let rbuf = std::sync::Arc::clone(&buffer);
-    let wbuf = buffer;
     {
         let mut read_contended = c.benchmark_group("read contention");
         testbench::run_under_contention(
             || {
-                rbuf.snatch();
-                black_box(*rbuf.reader_r());
+                black_box(*r.grab());
             },
             || {
                 read_contended.bench_function("write input", |b| {
+                    let mut g = e.grab();
                     b.iter(|| {
-                        *wbuf.editor_w() = black_box(0);
+                        *g.w() = black_box(0);
                     })
                 });
                 read_contended.bench_function("publish", |b| {
                     b.iter(|| {
-                        wbuf.advance();
+                        e.grab().commit();
                     })
                 });
                 read_contended.bench_function("send", |b| {
                     b.iter(|| {
-                        *wbuf.editor_w() = black_box(0);
-                        wbuf.advance();
+                        let mut g = e.grab();
+                        *g.w() = black_box(0);
+                        g.commit();
                     })
                 });
             },
@@ -87,24 +88,25 @@ pub fn benchmark(c: &mut Criterion) {
         let mut write_contended = c.benchmark_group("write contention");
         testbench::run_under_contention(
             || {
-                *wbuf.editor_w() = black_box(0);
-                wbuf.advance();
+                let mut g = e.grab();
+                *g.w() = black_box(0);
+                g.commit();
             },
             || {
                 write_contended.bench_function("read output", |b| {
+                    let g = r.grab();
                     b.iter(|| {
-                        black_box(*rbuf.reader_r());
+                        black_box(*g);
                     })
                 });
                 write_contended.bench_function("update", |b| {
                     b.iter(|| {
-                        rbuf.snatch();
+                        r.grab();
                     })
                 });
                 write_contended.bench_function("receive", |b| {
                     b.iter(|| {
-                        rbuf.snatch();
-                        black_box(*rbuf.reader_r());
+                        black_box(*r.grab());
                     })
                 });
             },
@@ -0,0 +1,217 @@
+//! # Broadcast
+//!
+//! Publishes the latest value from one editor to any number of readers, each of which keeps track
+//! of the last version it has seen.
+//!
+//! Every publish swaps a freshly allocated node into a single atomic pointer. Readers load it under
+//! an epoch guard, and the old node is reclaimed once no reader can still be looking at it, so
+//! neither side ever waits on the other. Unlike the seqlock, values need not be `Copy`, and reading
+//! one is a borrow rather than a copy.
+
+use std::{
+    ops::Deref,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use cb::{
+    epoch::{self, Atomic, Guard, Owned},
+    utils::CachePadded,
+};
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+struct Node<T> {
+    version: u64,
+    value: T,
+}
+
+struct Shared<T> {
+    latest: CachePadded<Atomic<Node<T>>>,
+    /// Mirrors the version of `latest` so that readers can check for news without pinning.
+    version: CachePadded<AtomicU64>,
+}
+impl<T> Drop for Shared<T> {
+    fn drop(&mut self) {
+        // Nobody else holds the shared state anymore, so nobody can be looking at the node.
+        unsafe {
+            let guard = epoch::unprotected();
+            drop(self.latest.load(Ordering::Relaxed, guard).into_owned());
+        }
+    }
+}
+
+/// A reading handle. Cloning it creates another independent reader.
+pub struct Reader<T> {
+    shared: Arc<Shared<T>>,
+    last_seen: u64,
+}
+impl<T> Reader<T> {
+    /// Whether the editor has published since this reader last grabbed. Never pins.
+    pub fn has_new(&self) -> bool {
+        self.shared.version.load(Ordering::Relaxed) > self.last_seen
+    }
+    /// Borrows the latest published value.
+    pub fn grab(&mut self) -> Latest<'_, T> {
+        let guard = epoch::pin();
+        let node = self.shared.latest.load(Ordering::Acquire, &guard).as_raw();
+        // Published nodes are never null, and the guard keeps this one alive.
+        let version = unsafe { (*node).version };
+        self.last_seen = version;
+        Latest {
+            node,
+            _guard: guard,
+            _reader: std::marker::PhantomData,
+        }
+    }
+    /// Borrows the latest value, but only if the editor has published since this reader last
+    /// grabbed.
+    pub fn try_new(&mut self) -> Option<Latest<'_, T>> {
+        if self.has_new() {
+            Some(self.grab())
+        } else {
+            None
+        }
+    }
+    /// Number of publishes up to the value this reader last grabbed.
+    pub fn version(&self) -> u64 {
+        self.last_seen
+    }
+}
+impl<T> Clone for Reader<T> {
+    fn clone(&self) -> Self {
+        Self {
+            shared: self.shared.clone(),
+            last_seen: self.last_seen,
+        }
+    }
+}
+impl<T> std::fmt::Debug for Reader<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Reader")
+            .field("last_seen", &self.last_seen)
+            .finish()
+    }
+}
+
+/// A borrow of a published value. Holding on to it keeps that value from being reclaimed, so it
+/// should not be kept around for long.
+pub struct Latest<'a, T> {
+    node: *const Node<T>,
+    _guard: Guard,
+    _reader: std::marker::PhantomData<&'a mut Reader<T>>,
+}
+impl<'a, T> Latest<'a, T> {
+    /// Number of publishes up to this value.
+    pub fn version(&self) -> u64 {
+        unsafe { (*self.node).version }
+    }
+}
+impl<'a, T> Deref for Latest<'a, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &(*self.node).value }
+    }
+}
+
+/// The sole publishing handle.
+pub struct Editor<T> {
+    shared: Arc<Shared<T>>,
+}
+impl<T: Send + 'static> Editor<T> {
+    /// Replaces the value seen by readers.
+    pub fn publish(&mut self, value: T) {
+        let version = self.version() + 1;
+        let guard = epoch::pin();
+        let old = self.shared.latest.swap(
+            Owned::new(Node { version, value }),
+            Ordering::AcqRel,
+            &guard,
+        );
+        self.shared.version.store(version, Ordering::Release);
+        // Readers that loaded the old node before the swap are still pinned, so it has to outlive
+        // them.
+        unsafe { guard.defer_destroy(old) };
+        trace!("Published version {:?}.", version);
+    }
+    /// Number of publishes made so far.
+    pub fn version(&self) -> u64 {
+        self.shared.version.load(Ordering::Relaxed)
+    }
+}
+impl<T> std::fmt::Debug for Editor<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Editor").finish()
+    }
+}
+
+/// Creates a broadcast cell holding `src`. Clone the reader to hand it out to more threads.
+pub fn buffer<T>(src: T) -> (Reader<T>, Editor<T>) {
+    let shared = Arc::new(Shared {
+        latest: CachePadded::new(Atomic::new(Node {
+            version: 0,
+            value: src,
+        })),
+        version: CachePadded::new(AtomicU64::new(0)),
+    });
+    (
+        Reader {
+            shared: shared.clone(),
+            last_seen: 0,
+        },
+        Editor { shared },
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const ROUNDS: usize = if cfg!(miri) { 20 } else { 5_000 };
+
+    #[test]
+    fn readers_track_their_own_versions() {
+        let (mut a, mut e) = buffer(String::from("zero"));
+        let mut b = a.clone();
+        assert!(a.try_new().is_none());
+        e.publish("one".into());
+        e.publish("two".into());
+        assert_eq!(a.try_new().as_deref().map(String::as_str), Some("two"));
+        assert!(a.try_new().is_none());
+        assert!(b.has_new());
+        let latest = b.grab();
+        assert_eq!(latest.version(), 2);
+        assert_eq!(&*latest, "two");
+    }
+
+    #[test]
+    fn readers_see_whole_values_in_order() {
+        let (r, mut e) = buffer(vec![0usize; 16]);
+        let readers = (0..3)
+            .map(|_| {
+                let mut r = r.clone();
+                std::thread::spawn(move || {
+                    let mut last = 0;
+                    loop {
+                        let v = r.grab();
+                        assert!(v.iter().all(|x| *x == v[0]));
+                        assert!(v[0] >= last);
+                        assert_eq!(v.version(), v[0] as u64);
+                        last = v[0];
+                        if last == ROUNDS {
+                            break;
+                        }
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for i in 1..=ROUNDS {
+            e.publish(vec![i; 16]);
+        }
+        for reader in readers {
+            reader.join().unwrap();
+        }
+    }
+}
@@ -1 +1,16 @@
+mod primitives;
 pub mod triple_buffer;
+pub mod snapshot_ring;
+pub mod seqlock;
+pub mod broadcast;
+
+#[derive(Debug)]
+pub struct LockedError<T>(pub T);
+#[derive(Debug)]
+pub struct UnlockedError<T>(pub T);
+
+#[derive(Debug)]
+pub struct RWPair<R, W> {
+    pub r: R,
+    pub w: W,
+}
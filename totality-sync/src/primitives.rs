@@ -0,0 +1,58 @@
+//! Synchronization primitives, swapped out for their model-checked counterparts when building with
+//! `--cfg loom`.
+
+#[cfg(loom)]
+pub(crate) use loom::{
+    cell::{ConstPtr, MutPtr, UnsafeCell},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, Condvar, Mutex, MutexGuard,
+    },
+};
+
+#[cfg(not(loom))]
+pub(crate) use std::sync::{
+    atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+    Arc, Condvar, Mutex, MutexGuard,
+};
+#[cfg(not(loom))]
+pub(crate) use cell::{ConstPtr, MutPtr, UnsafeCell};
+
+/// Mirrors the tracked cell API of loom on top of the standard `UnsafeCell`.
+#[cfg(not(loom))]
+mod cell {
+    #[derive(Debug)]
+    pub(crate) struct UnsafeCell<T>(std::cell::UnsafeCell<T>);
+    impl<T> UnsafeCell<T> {
+        pub(crate) fn new(data: T) -> Self {
+            Self(std::cell::UnsafeCell::new(data))
+        }
+        pub(crate) fn get(&self) -> ConstPtr<T> {
+            ConstPtr(self.0.get())
+        }
+        pub(crate) fn get_mut(&self) -> MutPtr<T> {
+            MutPtr(self.0.get())
+        }
+    }
+
+    #[derive(Debug)]
+    pub(crate) struct ConstPtr<T>(*const T);
+    impl<T> ConstPtr<T> {
+        /// # Safety
+        /// Same as dereferencing a `*const T`.
+        pub(crate) unsafe fn deref(&self) -> &T {
+            &*self.0
+        }
+    }
+
+    #[derive(Debug)]
+    pub(crate) struct MutPtr<T>(*mut T);
+    impl<T> MutPtr<T> {
+        /// # Safety
+        /// Same as dereferencing a `*mut T`.
+        #[allow(clippy::mut_from_ref)]
+        pub(crate) unsafe fn deref(&self) -> &mut T {
+            &mut *self.0
+        }
+    }
+}
@@ -0,0 +1,188 @@
+//! # SeqLock
+//!
+//! Publishes `Copy` data from one editor to any number of readers without either side ever
+//! blocking the other.
+//!
+//! The editor bumps a sequence number to odd before writing and back to even afterwards. Readers
+//! copy the value out optimistically and retry if the sequence number was odd or changed while they
+//! were copying, so a reader may spin while the editor is mid-write but never holds the editor up.
+//! Best suited to small values that are read far more often than they are written.
+
+use std::{
+    cell::UnsafeCell,
+    mem::MaybeUninit,
+    ptr,
+    sync::{
+        atomic::{self, AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use cb::utils::{Backoff, CachePadded};
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+struct Shared<T> {
+    seq: CachePadded<AtomicUsize>,
+    value: UnsafeCell<T>,
+}
+unsafe impl<T: Copy + Send> Send for Shared<T> {}
+unsafe impl<T: Copy + Send> Sync for Shared<T> {}
+
+/// A reading handle. Cloning it creates another independent reader.
+pub struct Reader<T> {
+    shared: Arc<Shared<T>>,
+    last_seen: usize,
+}
+impl<T: Copy> Reader<T> {
+    /// Copies out a consistent value along with its version, spinning while the editor is
+    /// mid-write.
+    fn load(&self) -> (T, usize) {
+        let backoff = Backoff::new();
+        loop {
+            let before = self.shared.seq.load(Ordering::Acquire);
+            if before & 1 == 0 {
+                // The copy may be torn, so it stays uninitialized until the sequence number
+                // confirms it was not.
+                let copy =
+                    unsafe { ptr::read_volatile(self.shared.value.get() as *const MaybeUninit<T>) };
+                atomic::fence(Ordering::Acquire);
+                if self.shared.seq.load(Ordering::Relaxed) == before {
+                    return (unsafe { copy.assume_init() }, before / 2);
+                }
+            }
+            backoff.snooze();
+        }
+    }
+    /// The latest value written by the editor.
+    pub fn read(&mut self) -> T {
+        let (value, version) = self.load();
+        self.last_seen = version;
+        value
+    }
+    /// The latest value, but only if the editor has written since this reader last read.
+    pub fn try_new(&mut self) -> Option<T> {
+        if self.has_new() {
+            Some(self.read())
+        } else {
+            None
+        }
+    }
+    /// Whether the editor has written since this reader last read. Never spins.
+    pub fn has_new(&self) -> bool {
+        self.shared.seq.load(Ordering::Relaxed) / 2 != self.last_seen
+    }
+    /// Number of writes up to the value this reader last read.
+    pub fn version(&self) -> usize {
+        self.last_seen
+    }
+}
+impl<T> Clone for Reader<T> {
+    fn clone(&self) -> Self {
+        Self {
+            shared: self.shared.clone(),
+            last_seen: self.last_seen,
+        }
+    }
+}
+impl<T> std::fmt::Debug for Reader<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Reader")
+            .field("last_seen", &self.last_seen)
+            .finish()
+    }
+}
+
+/// The sole writing handle.
+pub struct Editor<T> {
+    shared: Arc<Shared<T>>,
+}
+impl<T: Copy> Editor<T> {
+    /// The current value. The editor is the only one writing, so this never spins.
+    pub fn get(&self) -> T {
+        unsafe { *self.shared.value.get() }
+    }
+    pub fn write(&mut self, value: T) {
+        let seq = self.shared.seq.load(Ordering::Relaxed);
+        self.shared.seq.store(seq + 1, Ordering::Relaxed);
+        atomic::fence(Ordering::Release);
+        unsafe { ptr::write_volatile(self.shared.value.get(), value) };
+        self.shared.seq.store(seq + 2, Ordering::Release);
+        trace!("Wrote version {:?}.", seq / 2 + 1);
+    }
+    /// Applies `f` to a copy of the current value and writes the result.
+    pub fn modify(&mut self, f: impl FnOnce(&mut T)) {
+        let mut value = self.get();
+        f(&mut value);
+        self.write(value);
+    }
+    /// Number of writes made so far.
+    pub fn version(&self) -> usize {
+        self.shared.seq.load(Ordering::Relaxed) / 2
+    }
+}
+impl<T> std::fmt::Debug for Editor<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Editor").finish()
+    }
+}
+
+/// Creates a seqlock holding `src`. Clone the reader to hand it out to more threads.
+pub fn buffer<T: Copy>(src: T) -> (Reader<T>, Editor<T>) {
+    let shared = Arc::new(Shared {
+        seq: CachePadded::new(AtomicUsize::new(0)),
+        value: UnsafeCell::new(src),
+    });
+    (
+        Reader {
+            shared: shared.clone(),
+            last_seen: 0,
+        },
+        Editor { shared },
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const ROUNDS: u64 = if cfg!(miri) { 20 } else { 10_000 };
+
+    #[test]
+    fn readers_track_their_own_versions() {
+        let (mut a, mut e) = buffer(0u32);
+        let mut b = a.clone();
+        assert!(a.try_new().is_none());
+        e.write(1);
+        e.modify(|v| *v += 1);
+        assert_eq!(e.version(), 2);
+        assert_eq!(a.try_new(), Some(2));
+        assert!(a.try_new().is_none());
+        assert!(b.has_new());
+        assert_eq!(b.read(), 2);
+        assert_eq!(b.version(), 2);
+    }
+
+    #[test]
+    fn reads_are_never_torn() {
+        let (r, mut e) = buffer([0u64; 8]);
+        let readers = (0..3)
+            .map(|_| {
+                let mut r = r.clone();
+                std::thread::spawn(move || loop {
+                    let v = r.read();
+                    assert!(v.iter().all(|x| *x == v[0]), "Torn read {:?}.", v);
+                    if v[0] == ROUNDS {
+                        break;
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for i in 1..=ROUNDS {
+            e.write([i; 8]);
+        }
+        for reader in readers {
+            reader.join().unwrap();
+        }
+    }
+}
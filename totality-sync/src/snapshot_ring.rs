@@ -0,0 +1,416 @@
+//! # SnapshotRing
+//!
+//! A generalization of the triple buffer that keeps the last `history` committed values around.
+//! The reader sees the newest committed value along with the ones before it, each tagged with the
+//! version and time of its commit.
+//!
+//! The buffer holds `2 * history + 1` slots: the committed window, whatever window the reader has
+//! pinned, and the slot the editor is writing into. Each slot carries an atomic stamp packing the
+//! version committed into it with a bit marking it as pinned by the reader. The reader pins a slot
+//! by swapping in the pinned bit only if the version is still the one it expects, and the editor
+//! claims a slot by clearing the version only if the slot is unpinned, so the two can never touch
+//! the same slot at the same time.
+//!
+//! As with the triple buffer, every borrow of the ring's contents goes through a guard that mutably
+//! borrows its half. Reader guards keep their slots pinned until they are dropped.
+
+use std::{
+    cell::UnsafeCell,
+    ops::Deref,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
+
+use cb::utils::CachePadded;
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+pub use crate::RWPair;
+
+const PINNED: u64 = 1 << 63;
+/// Stamp of a slot that holds no committed value, most likely because it is being written to.
+const UNCOMMITTED: u64 = 0;
+const NO_SLOT: usize = usize::MAX;
+
+#[inline]
+fn stamp_of(version: u64) -> u64 {
+    version + 1
+}
+
+struct Slot<T> {
+    stamp: CachePadded<AtomicU64>,
+    value: UnsafeCell<T>,
+    committed: UnsafeCell<Instant>,
+}
+
+/// A committed value, along with the version and time of its commit.
+#[derive(Debug)]
+pub struct Snapshot<'a, T> {
+    pub value: &'a T,
+    pub version: u64,
+    pub committed: Instant,
+}
+impl<'a, T> Clone for Snapshot<'a, T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+impl<'a, T> Copy for Snapshot<'a, T> {}
+
+pub struct SnapshotRing<T> {
+    slots: Box<[Slot<T>]>,
+    /// Slot holding the value committed as `version`, at `version % history`.
+    window: Box<[CachePadded<AtomicUsize>]>,
+    newest: CachePadded<AtomicU64>,
+}
+impl<T: Clone> SnapshotRing<T> {
+    fn new(src: T, history: usize) -> Self {
+        let history = history.max(1);
+        let now = Instant::now();
+        let slots = (0..2 * history + 1)
+            .map(|i| Slot {
+                stamp: CachePadded::new(AtomicU64::new(if i == 0 {
+                    stamp_of(0)
+                } else {
+                    UNCOMMITTED
+                })),
+                value: UnsafeCell::new(src.clone()),
+                committed: UnsafeCell::new(now),
+            })
+            .collect();
+        let window = (0..history)
+            .map(|i| CachePadded::new(AtomicUsize::new(if i == 0 { 0 } else { NO_SLOT })))
+            .collect();
+        Self {
+            slots,
+            window,
+            newest: CachePadded::new(AtomicU64::new(0)),
+        }
+    }
+}
+impl<T> SnapshotRing<T> {
+    pub fn history(&self) -> usize {
+        self.window.len()
+    }
+    /// Pins as much of the committed window as possible, newest first. Stops at the first entry
+    /// that has already been recycled by the editor.
+    fn pin(&self) -> Vec<usize> {
+        loop {
+            let newest = self.newest.load(Ordering::Acquire);
+            let mut pinned = Vec::with_capacity(self.history());
+            for version in (0..=newest).rev().take(self.history()) {
+                let slot = self.window[version as usize % self.history()].load(Ordering::Acquire);
+                if slot == NO_SLOT
+                    || self.slots[slot]
+                        .stamp
+                        .compare_exchange(
+                            stamp_of(version),
+                            stamp_of(version) | PINNED,
+                            Ordering::Acquire,
+                            Ordering::Relaxed,
+                        )
+                        .is_err()
+                {
+                    break;
+                }
+                pinned.push(slot);
+            }
+            if !pinned.is_empty() {
+                trace!("Pinned slots {:?} for version {:?}.", pinned, newest);
+                return pinned;
+            }
+            // The editor lapped us between reading the newest version and pinning it. Try again.
+        }
+    }
+    fn unpin(&self, pinned: &[usize]) {
+        for slot in pinned.iter() {
+            self.slots[*slot]
+                .stamp
+                .fetch_and(!PINNED, Ordering::Release);
+        }
+    }
+    /// Claims any slot that is neither pinned by the reader nor part of the committed window.
+    fn claim(&self, window: &[usize]) -> usize {
+        for (i, slot) in self.slots.iter().enumerate() {
+            if window.contains(&i) {
+                continue;
+            }
+            let stamp = slot.stamp.load(Ordering::Relaxed);
+            if stamp & PINNED == 0
+                && slot
+                    .stamp
+                    .compare_exchange(stamp, UNCOMMITTED, Ordering::Acquire, Ordering::Relaxed)
+                    .is_ok()
+            {
+                return i;
+            }
+        }
+        unreachable!("There are more slots than the reader and editor can hold at once.")
+    }
+    fn publish(&self, slot: usize, version: u64) {
+        unsafe { *self.slots[slot].committed.get() = Instant::now() };
+        self.slots[slot]
+            .stamp
+            .store(stamp_of(version), Ordering::Release);
+        self.window[version as usize % self.history()].store(slot, Ordering::Release);
+        self.newest.store(version, Ordering::Release);
+    }
+    fn snapshot(&self, slot: usize) -> Snapshot<'_, T> {
+        let s = &self.slots[slot];
+        Snapshot {
+            value: unsafe { &*s.value.get() },
+            version: (s.stamp.load(Ordering::Relaxed) & !PINNED) - 1,
+            committed: unsafe { *s.committed.get() },
+        }
+    }
+}
+unsafe impl<T: Send> Send for SnapshotRing<T> {}
+unsafe impl<T: Send + Sync> Sync for SnapshotRing<T> {}
+
+/// The reading half of a snapshot ring.
+#[derive(Debug)]
+pub struct Reader<T> {
+    origin: Arc<SnapshotRing<T>>,
+}
+impl<T> Reader<T> {
+    /// Pins as much of the committed window as it can, keeping it from being recycled until the
+    /// guard is dropped.
+    pub fn grab(&mut self) -> ReadGuard<'_, T> {
+        ReadGuard {
+            pinned: self.origin.pin(),
+            reader: self,
+        }
+    }
+    /// Number of commits the ring keeps around.
+    pub fn history(&self) -> usize {
+        self.origin.history()
+    }
+}
+
+/// A borrow of the committed window as it was when the reader grabbed it. Derefs to the newest
+/// committed value.
+pub struct ReadGuard<'a, T> {
+    reader: &'a mut Reader<T>,
+    pinned: Vec<usize>,
+}
+impl<'a, T> ReadGuard<'a, T> {
+    /// The newest committed value, with its version and commit time.
+    pub fn latest(&self) -> Snapshot<'_, T> {
+        self.reader.origin.snapshot(self.pinned[0])
+    }
+    /// Committed values, newest first. May be shorter than the ring's history if the editor has
+    /// not committed that many times yet, or recycled older slots while the reader was grabbing.
+    pub fn history(&self) -> impl Iterator<Item = Snapshot<'_, T>> + '_ {
+        self.pinned
+            .iter()
+            .map(move |slot| self.reader.origin.snapshot(*slot))
+    }
+}
+impl<'a, T> Deref for ReadGuard<'a, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        self.latest().value
+    }
+}
+impl<'a, T> Drop for ReadGuard<'a, T> {
+    fn drop(&mut self) {
+        self.reader.origin.unpin(&self.pinned);
+    }
+}
+
+/// The editing half of a snapshot ring.
+#[derive(Debug)]
+pub struct Editor<T> {
+    origin: Arc<SnapshotRing<T>>,
+    /// The editor's copy of the committed window, newest first.
+    window: Vec<usize>,
+    /// The slot claimed for the next commit, kept across grabs until it is committed.
+    write: Option<usize>,
+    version: u64,
+}
+impl<T> Editor<T> {
+    /// Borrows the newest committed value along with the slot to write the next one into.
+    ///
+    /// Like the triple buffer, the write slot is not reset, so it holds whatever was last written
+    /// into it, be it an uncommitted edit or a value committed a few rounds ago.
+    pub fn grab(&mut self) -> EditGuard<'_, T> {
+        let write = match self.write {
+            Some(write) => write,
+            None => {
+                let write = self.origin.claim(&self.window);
+                trace!(
+                    "Claimed slot {:?} for version {:?}.",
+                    write,
+                    self.version + 1
+                );
+                self.write = Some(write);
+                write
+            }
+        };
+        EditGuard {
+            editor: self,
+            write,
+        }
+    }
+    /// Number of commits made so far.
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+    fn advance(&mut self, write: usize) {
+        self.version += 1;
+        self.origin.publish(write, self.version);
+        self.window.insert(0, write);
+        self.window.truncate(self.origin.history());
+        self.write = None;
+    }
+}
+
+/// A borrow of the editor's slots. Dropping it without committing keeps the edits in the claimed
+/// slot for the next grab.
+pub struct EditGuard<'a, T> {
+    editor: &'a mut Editor<T>,
+    write: usize,
+}
+impl<'a, T> EditGuard<'a, T> {
+    /// The newest committed value.
+    pub fn r(&self) -> &T {
+        // Committed slots are only ever read until the editor claims them back.
+        unsafe { &*self.editor.origin.slots[self.editor.window[0]].value.get() }
+    }
+    /// The value to be committed next.
+    pub fn w(&mut self) -> &mut T {
+        // The claimed slot belongs to the editor, which we are mutably borrowing.
+        unsafe { &mut *self.editor.origin.slots[self.write].value.get() }
+    }
+    pub fn rw(&mut self) -> RWPair<&T, &mut T> {
+        let slots = &self.editor.origin.slots;
+        unsafe {
+            RWPair {
+                r: &*slots[self.editor.window[0]].value.get(),
+                w: &mut *slots[self.write].value.get(),
+            }
+        }
+    }
+    /// Publishes the claimed slot as the newest committed value.
+    pub fn commit(self) {
+        self.editor.advance(self.write);
+    }
+}
+
+impl<T> std::fmt::Debug for SnapshotRing<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SnapshotRing")
+            .field("slots", &self.slots.len())
+            .field("newest", &self.newest.load(Ordering::Relaxed))
+            .finish()
+    }
+}
+
+/// Creates a ring holding the last `history` committed values, initially containing only `src`
+/// as version 0.
+pub fn buffer<T: Clone>(src: T, history: usize) -> (Reader<T>, Editor<T>) {
+    let ring = Arc::new(SnapshotRing::new(src, history));
+    (
+        Reader {
+            origin: ring.clone(),
+        },
+        Editor {
+            origin: ring,
+            window: vec![0],
+            write: None,
+            version: 0,
+        },
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn commit(e: &mut Editor<u64>, v: u64) {
+        let mut guard = e.grab();
+        *guard.w() = v;
+        guard.commit();
+    }
+
+    #[test]
+    fn history_is_newest_first_and_bounded() {
+        let (mut r, mut e) = buffer(0u64, 3);
+        for v in 1..=5 {
+            commit(&mut e, v * 10);
+        }
+        let guard = r.grab();
+        let history = guard.history().collect::<Vec<_>>();
+        assert_eq!(
+            history.iter().map(|s| *s.value).collect::<Vec<_>>(),
+            vec![50, 40, 30]
+        );
+        assert_eq!(
+            history.iter().map(|s| s.version).collect::<Vec<_>>(),
+            vec![5, 4, 3]
+        );
+        assert!(history.windows(2).all(|w| w[0].committed >= w[1].committed));
+        assert_eq!(*guard, 50);
+    }
+
+    #[test]
+    fn pinned_history_survives_commits() {
+        let (mut r, mut e) = buffer(0u64, 2);
+        commit(&mut e, 1);
+        let guard = r.grab();
+        for v in 2..10 {
+            commit(&mut e, v);
+        }
+        assert_eq!(
+            guard.history().map(|s| *s.value).collect::<Vec<_>>(),
+            vec![1, 0]
+        );
+        drop(guard);
+        let guard = r.grab();
+        assert_eq!(guard.latest().version, 9);
+        assert_eq!(*guard, 9);
+    }
+
+    #[test]
+    fn uncommitted_edits_survive_regrabs() {
+        let (mut r, mut e) = buffer(0u64, 2);
+        *e.grab().w() = 7;
+        assert_eq!(*e.grab().w(), 7);
+        assert_eq!(*r.grab(), 0);
+        e.grab().commit();
+        assert_eq!(*r.grab(), 7);
+    }
+
+    #[test]
+    fn threaded_versions_are_monotonic() {
+        let (mut r, mut e) = buffer(0u64, 4);
+        let editor = std::thread::spawn(move || {
+            for v in 1..=10_000 {
+                let mut guard = e.grab();
+                let RWPair { r, w } = guard.rw();
+                *w = *r + 1;
+                assert_eq!(*w, v);
+                guard.commit();
+            }
+        });
+        let mut last = 0;
+        while last < 10_000 {
+            let guard = r.grab();
+            let history = guard.history().collect::<Vec<_>>();
+            for (i, s) in history.iter().enumerate() {
+                assert_eq!(*s.value, s.version, "Value and version disagree.");
+                assert_eq!(
+                    s.version,
+                    history[0].version - i as u64,
+                    "History has gaps."
+                );
+            }
+            assert!(history[0].version >= last);
+            last = history[0].version;
+        }
+        editor.join().unwrap();
+    }
+}
@@ -1,524 +1,423 @@
+//! # TripleBuffer
+//!
+//! A wait-free channel between exactly one reader and one editor that always holds the most
+//! recently committed value.
+//!
+//! Three slots rotate between the two halves. The reader owns the front slot, the editor owns the
+//! slot it is writing into, and the back slot holds the newest commit that the reader has not yet
+//! picked up. A single atomic packs the index of the back slot with a bit marking it as newer than
+//! the front one, so committing and picking up are each one swap.
+//!
+//! Neither half can be cloned, and every borrow of the buffer's contents goes through a guard that
+//! mutably borrows its half, so no slot is ever written while it might be read.
+//!
+//! For large values, `Editor::into_diff` and `diff_buffer` switch the editor to replaying only the
+//! elements it marks as changed into stale slots, instead of copying the whole value each commit.
+//!
+//! With the `async` feature, the reader can also await commits or be turned into a `Stream` of
+//! snapshots, without tying the crate to any executor.
+
 use std::{
-    cell::UnsafeCell,
-    sync::Arc,
-    marker::Send,
+    marker::PhantomData,
+    ops::Deref,
+    task::Waker,
+    time::{Duration, Instant},
 };
 
 use cb::utils::CachePadded;
 #[allow(unused_imports)]
 use log::{debug, error, info, trace, warn};
 
-#[cfg(feature = "sub")]
-mod tb {
-    use std::sync::atomic::{AtomicU8, Ordering};
-    use cb::utils::CachePadded;
-    #[allow(unused_imports)]
-    use log::{debug, error, info, trace, warn};
+use crate::primitives::{
+    Arc, AtomicBool, AtomicU64, AtomicUsize, Condvar, ConstPtr, Mutex, MutexGuard, MutPtr, Ordering, UnsafeCell,
+};
+pub use crate::RWPair;
 
-    #[derive(Debug)]
-    pub struct TripleBufferIndices {
-        pub snatched_read: CachePadded<u8>,     // unique
-        packed: CachePadded<AtomicU8>, // shared
-        pub edit_rw: CachePadded<(u8, u8)>,  // unique
-    }
-    impl TripleBufferIndices {
-        const BUFFER_ID_MASK: u8 = 0b11;
-        const IS_NEW_MASK: u8 = 0b100;
-        #[inline]
-        fn pack(is_new: bool, v: u8) -> u8 {
-            if is_new {
-                v | 0b100
-            } else {
-                v
-            }
-        }
-        #[inline]
-        fn unpack(packed: u8) -> (bool, u8) {
-            let is_new = (packed & Self::IS_NEW_MASK) != 0;
-            let next_write = packed & Self::BUFFER_ID_MASK;
-            (is_new, next_write)
-        }
-        pub fn snatch(&mut self) {
-            let old_snatched = self.snatched_read;
-            if Self::unpack(self.packed.load(Ordering::Acquire)).0 {
-                *self.snatched_read = Self::unpack(
-                    self.packed.swap(Self::pack(true, *old_snatched), Ordering::AcqRel),
-                )
-                .1;
-                trace!(
-                    "Snatching indices {:?} and returning indices {:?}.",
-                    old_snatched,
-                    self.snatched_read
-                );
-            }
-        }
-        pub fn advance(&mut self) {
-            let curr_read = self.edit_rw.1;
-            let curr_write = Self::unpack(self.packed.swap(
-                Self::pack(true, curr_read),
-                std::sync::atomic::Ordering::Release,
-            ))
-            .1;
-            trace!(
-                "Advancing indices from {:?} to {:?}.",
-                curr_read,
-                curr_write
-            );
-            self.edit_rw.0 = curr_read;
-            self.edit_rw.1 = curr_write;
-        }
-    }
-    impl Default for TripleBufferIndices {
-        fn default() -> Self {
-            Self {
-                snatched_read: CachePadded::new(0),
-                packed: CachePadded::new(AtomicU8::new(Self::pack(false, 2))),
-                edit_rw: CachePadded::new((1, 2)),
-            }
-        }
-    }
-}
+mod diff;
+pub use diff::{diff_buffer, DiffEditor, DiffGuard, DirtyRanges, Patch};
+#[cfg(feature = "async")]
+mod future;
+#[cfg(feature = "async")]
+pub use future::{Snapshots, Versioned};
 
-#[cfg(any(feature = "fna_usize", feature = "fna"))]
-mod tb {
-    #[cfg(feature = "fna")]
-    use std::sync::atomic::{AtomicU8, Ordering};
-    #[cfg(feature = "fna_usize")]
-    use std::sync::atomic::{AtomicUsize, Ordering};
-    use cb::utils::CachePadded;
-    #[allow(unused_imports)]
-    use log::{debug, error, info, trace, warn};
+const INDEX_MASK: usize = 0b11;
+/// The back slot holds a commit the reader has not picked up yet.
+const DIRTY: usize = 0b100;
+/// The reader is parked waiting for the back slot to become dirty.
+const WAITING: usize = 0b1000;
 
-    #[cfg(feature = "fna")]
-    type SyncType = AtomicU8;
-    #[cfg(feature = "fna_usize")]
-    type SyncType = AtomicUsize;
-    #[cfg(feature = "fna")]
-    type MaskType = u8;
-    #[cfg(feature = "fna_usize")]
-    type MaskType = usize;
-    #[cfg(feature = "fna")]
-    type Index = u8;
-    #[cfg(feature = "fna_usize")]
-    type Index = usize;
+/// The editor has been dropped, so nothing new will ever be committed.
+#[derive(Debug)]
+pub struct ClosedError;
 
-    #[derive(Debug)]
-    pub struct TripleBufferIndices {
-        pub snatched_read: CachePadded<Index>,     // unique
-        packed: CachePadded<SyncType>, // shared
-        pub edit_rw: CachePadded<(Index, Index)>,  // unique
-    }
-    impl TripleBufferIndices {
-        const BUFFER_ID_MASK: MaskType = 0b11;
-        const IS_NEW_MASK: MaskType = 0b100;
-        #[inline]
-        fn pack(v: MaskType) -> MaskType {
-            !v & Self::BUFFER_ID_MASK
-        }
-        #[inline]
-        fn unpack(packed: MaskType) -> (bool, Index) {
-            let is_new = (packed & Self::IS_NEW_MASK) == 0;
-            let next_write = !packed & Self::BUFFER_ID_MASK;
-            (is_new, next_write)
-        }
-        #[inline]
-        fn mask(v: MaskType) -> MaskType {
-            match v {
-                0 => 0b000,
-                1 => 0b001,
-                2 => 0b010,
-                _ => panic!("We done goofed!"),
-            }
-        }
-    }
-    impl TripleBufferIndices {
-        pub fn snatch(&mut self) {
-            if Self::unpack(self.packed.load(Ordering::Acquire)).0 {
-                let old_snatched = self.snatched_read;
-                *self.snatched_read = Self::unpack(
-                    self.packed.fetch_nand(Self::mask(*old_snatched), Ordering::AcqRel),
-                )
-                .1;
-                trace!(
-                    "Snatching indices {:?} and returning indices {:?}.",
-                    old_snatched,
-                    self.snatched_read
-                );
-            }
-        }
-        pub fn advance(&mut self) {
-            let curr_read = self.edit_rw.1;
-            let curr_write = Self::unpack(self.packed.swap(
-                Self::pack(curr_read),
-                Ordering::Release,
-            ))
-            .1;
-            trace!(
-                "Advancing indices from {:?} to {:?}.",
-                curr_read,
-                curr_write
-            );
-            *self.edit_rw = (curr_read, curr_write);
-        }
-    }
-    impl Default for TripleBufferIndices {
-        fn default() -> Self {
-            Self {
-                snatched_read: CachePadded::new(0),
-                packed: CachePadded::new(SyncType::new(Self::pack(1))),
-                edit_rw: CachePadded::new((1, 2)),
-            }
-        }
-    }
+struct Slot<T> {
+    value: UnsafeCell<T>,
+    /// Number of commits made up to and including the one this slot holds.
+    version: AtomicU64,
 }
 
-#[cfg(feature = "old")]
-mod tb {
-    use std::sync::atomic::{AtomicU8, AtomicUsize, AtomicBool, Ordering};
-    use cb::utils::CachePadded;
-    #[allow(unused_imports)]
-    use log::{debug, error, info, trace, warn};
-
-    #[derive(Debug)]
-    pub struct TripleBufferIndices {
-        pub snatched_read: CachePadded<usize>,     // unique
-        packed_vals: CachePadded<AtomicUsize>, // shared
-        stale: CachePadded<AtomicBool>,        // shared
-        pub edit_rw: CachePadded<(usize, usize)>,  // unique
-    }
-    impl TripleBufferIndices {
-        #[inline]
-        fn pack(v0: usize, v1: usize) -> usize {
-            (0b0 << 4) + (v0 << 2) + ((!v1) & 0b11)
-        }
-        #[inline]
-        fn unpack(packed: usize) -> (usize, usize) {
-            let should_negate = ((packed >> 4) & 0b1) != 0;
-            let most_recent = (if should_negate { !packed } else { packed } >> 2) & 0b11;
-            let next_write = !packed & 0b11;
-            (most_recent, next_write)
-        }
-        pub fn snatch(&mut self) {
-            let mask = (0b1 << 4)
-                + (0b11 << 2)
-                + match *self.snatched_read {
-                    0 => 0b00,
-                    1 => 0b01,
-                    2 => 0b10,
-                    _ => panic!("We done goofed!"),
-                };
-            if !self.stale.swap(true, std::sync::atomic::Ordering::Acquire) {
-                let old_snatched = *self.snatched_read;
-                *self.snatched_read = Self::unpack(
-                    self.packed_vals
-                        .fetch_nand(mask, std::sync::atomic::Ordering::AcqRel),
-                )
-                .0;
-                trace!(
-                    "Snatching indices {:?} and returning indices {:?}.",
-                    old_snatched,
-                    self.snatched_read
-                );
-            }
-        }
-        pub fn advance(&mut self) {
-            let next_write = Self::unpack(self.packed_vals.swap(
-                Self::pack(self.edit_rw.1, self.edit_rw.1),
-                std::sync::atomic::Ordering::AcqRel,
-            ))
-            .1;
-            self.stale.swap(false, std::sync::atomic::Ordering::Release);
-            trace!(
-                "Advancing indices from {:?} to {:?}.",
-                self.edit_rw.1,
-                next_write
-            );
-            self.edit_rw = (self.edit_rw.1, next_write);
-        }
-    }
-    impl Default for TripleBufferIndices {
-        fn default() -> Self {
-            Self {
-                snatched_read: CachePadded::new(0),
-                packed_vals: CachePadded::new(AtomicUsize::new(Self::pack(0, 2))),
-                stale: CachePadded::new(AtomicBool::new(true)),
-                edit_rw: CachePadded::new((1, 2)),
-            }
-        }
-    }
+struct Shared<T> {
+    slots: [CachePadded<Slot<T>>; 3],
+    back: CachePadded<AtomicUsize>,
+    closed: AtomicBool,
+    /// Guards the waker of an asynchronous reader, and doubles as the lock for the condvar.
+    lock: Mutex<Option<Waker>>,
+    cv: Condvar,
 }
-
-// NOTE Dummy doesn't actually work, don't use it outside of benchmarking
-#[cfg(all(feature = "dummy", test))]
-mod tb {
-    use std::{
-        sync::{
-            atomic::{AtomicUsize, AtomicBool},
-        },
-    };
-    use cb::utils::CachePadded;
-
-    #[derive(Debug)]
-    pub struct TripleBufferIndices {
-        pub snatched_read: CachePadded<usize>,     // unique
-        packed_vals: CachePadded<AtomicUsize>, // shared
-        stale: CachePadded<AtomicBool>,        // shared
-        pub edit_rw: CachePadded<(usize, usize)>,  // unique
-    }
-    impl TripleBufferIndices {
-        pub fn snatch(&mut self) {
-        }
-        pub fn advance(&mut self) {
-        }
-    }
-    impl Default for TripleBufferIndices {
-        fn default() -> Self {
-            Self {
-                snatched_read: CachePadded::new(0),
-                packed_vals: CachePadded::new(AtomicUsize::new(0)),
-                stale: CachePadded::new(AtomicBool::new(true)),
-                edit_rw: CachePadded::new((1, 2)),
-            }
-        }
+impl<T> Shared<T> {
+    fn lock(&self) -> MutexGuard<'_, Option<Waker>> {
+        self.lock.lock().unwrap_or_else(|e| e.into_inner())
+    }
+    /// Wakes the reader, be it parked on the condvar or awaiting a commit.
+    fn wake(&self) {
+        let waker = self.lock().take();
+        self.cv.notify_all();
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+    /// Marks the reader as waiting, returning the state of the back slot from right before.
+    ///
+    /// The waiting bit lives in the same atomic the editor swaps on commit, so either we see the
+    /// commit here or the editor sees the bit and wakes us. The editor has to take the lock to do
+    /// so, so this has to be called with the lock held until the reader is ready to be woken.
+    fn register_waiting(&self) -> usize {
+        self.back.fetch_or(WAITING, Ordering::AcqRel)
     }
 }
+unsafe impl<T: Send> Send for Shared<T> {}
+// Both halves may read the last committed value at the same time.
+unsafe impl<T: Send + Sync> Sync for Shared<T> {}
 
-use tb::TripleBufferIndices;
-
-#[derive(Debug, Clone)]
-pub struct TB<T:Clone>(Arc<TripleBuffer<T>>);
-
-#[derive(Debug)]
-pub struct TripleBuffer<T: Clone> {
-    ii: UnsafeCell<TripleBufferIndices>,
-    backing_mem: *const [UnsafeCell<CachePadded<T>>; 3],
-    tt: [*mut T; 3],
+/// The reading half of a triple buffer.
+pub struct Reader<T> {
+    shared: Arc<Shared<T>>,
+    front: usize,
 }
-impl<T: Clone> TripleBuffer<T> {
-    #[inline]
-    fn alloc(src: T) -> TB<T> {
-        TB(Self::raw(src))
-    }
-    pub fn raw(src: T) -> Arc<TripleBuffer<T>> {
-        use std::mem::MaybeUninit;
-        let backing_mem = Box::into_raw(Box::new([
-            UnsafeCell::new(CachePadded::new(src.clone())),
-            UnsafeCell::new(CachePadded::new(src.clone())),
-            UnsafeCell::new(CachePadded::new(src)),
-        ]));
-        let mut tt: MaybeUninit<[*mut T; 3]> = MaybeUninit::uninit();
-        unsafe {
-            for i in 0..3 {
-                (*tt.as_mut_ptr())[i] = &mut **(*backing_mem)[i].get();
+impl<T> Reader<T> {
+    /// Whether the editor has committed since this reader last picked up a value. Never blocks.
+    pub fn has_new(&self) -> bool {
+        self.shared.back.load(Ordering::Relaxed) & DIRTY != 0
+    }
+    /// Whether the editor has been dropped. Whatever it committed last can still be picked up.
+    pub fn is_closed(&self) -> bool {
+        self.shared.closed.load(Ordering::Acquire)
+    }
+    /// Number of commits up to and including the value this reader last picked up.
+    pub fn version(&self) -> u64 {
+        self.shared.slots[self.front].version.load(Ordering::Relaxed)
+    }
+    fn snatch(&mut self) {
+        if self.has_new() {
+            let old_front = self.front;
+            self.front = self.shared.back.swap(self.front, Ordering::AcqRel) & INDEX_MASK;
+            trace!("Snatching slot {:?} and returning slot {:?}.", self.front, old_front);
+        }
+    }
+    fn guard(&mut self) -> ReadGuard<'_, T> {
+        ReadGuard {
+            value: self.shared.slots[self.front].value.get(),
+            _reader: PhantomData,
+        }
+    }
+    /// Picks up the newest committed value, or keeps the current one if nothing was committed
+    /// since.
+    pub fn grab(&mut self) -> ReadGuard<'_, T> {
+        self.snatch();
+        self.guard()
+    }
+    /// Picks up the newest committed value only if the editor has committed since this reader last
+    /// picked one up. Never blocks.
+    pub fn try_new(&mut self) -> Option<ReadGuard<'_, T>> {
+        if self.has_new() {
+            Some(self.grab())
+        } else {
+            None
+        }
+    }
+    /// Parks the thread until the editor commits, then picks up the new value. Returns immediately
+    /// if there is already something new, or with the current value if the editor has been dropped.
+    pub fn wait_for_new(&mut self) -> ReadGuard<'_, T> {
+        self.wait(None);
+        self.grab()
+    }
+    /// Like `wait_for_new`, but gives up after `timeout` or once the editor has been dropped.
+    pub fn wait_timeout(&mut self, timeout: Duration) -> Option<ReadGuard<'_, T>> {
+        if self.wait(Some(Instant::now() + timeout)) {
+            Some(self.grab())
+        } else {
+            None
+        }
+    }
+    /// Blocks until the back slot is dirty, the editor is dropped, or the deadline passes.
+    /// Returns whether the back slot is dirty.
+    fn wait(&self, deadline: Option<Instant>) -> bool {
+        if self.has_new() {
+            return true;
+        }
+        let shared = &*self.shared;
+        let mut guard = shared.lock();
+        loop {
+            if shared.register_waiting() & DIRTY != 0 {
+                return true;
+            }
+            if self.is_closed() {
+                return false;
+            }
+            match deadline {
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        shared.back.fetch_and(!WAITING, Ordering::Relaxed);
+                        return false;
+                    }
+                    guard = shared
+                        .cv
+                        .wait_timeout(guard, deadline - now)
+                        .unwrap_or_else(|e| e.into_inner())
+                        .0;
+                }
+                None => guard = shared.cv.wait(guard).unwrap_or_else(|e| e.into_inner()),
             }
         }
-        Arc::new(Self {
-            ii: UnsafeCell::new(TripleBufferIndices::default()),
-            backing_mem,
-            tt: unsafe { tt.assume_init() },
-        })
-    }
-    #[inline]
-    pub fn snatch(&self) {
-        let ii = self.ii.get();
-        unsafe { (*ii).snatch() };
-    }
-    #[inline]
-    pub fn advance(&self) {
-        let ii = self.ii.get();
-        unsafe { (*ii).advance() };
-    }
-    #[inline]
-    fn rr(&self) -> *const T {
-        let ii = self.ii.get();
-        self.tt[unsafe { *(*ii).snatched_read as usize }]
-    }
-    #[inline]
-    fn er(&self) -> *const T {
-        let ii = self.ii.get();
-        self.tt[unsafe { (*ii).edit_rw.0 as usize }]
-    }
-    #[inline]
-    fn ew(&self) -> *mut T {
-        let ii = self.ii.get();
-        self.tt[unsafe { (*ii).edit_rw.1 as usize }]
-    }
-    #[inline]
-    pub fn reader_r(&self) -> & T {
-        unsafe { & *self.rr() }
-    }
-    #[inline]
-    pub fn editor_r(&self) -> & T {
-        unsafe { & *self.er() }
-    }
-    #[inline]
-    pub fn editor_w(&self) -> &mut T {
-        unsafe { &mut *self.ew() }
     }
 }
-impl<T: Clone> Drop for TripleBuffer<T> {
-    fn drop(&mut self) {
-        unsafe {
-            Box::from_raw(self.backing_mem as *mut [CachePadded<T>; 3]);
-        };
+impl<T> std::fmt::Debug for Reader<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Reader").field("front", &self.front).finish()
     }
 }
-unsafe impl<T: Clone + Send> Send for TripleBuffer<T> {}
-unsafe impl<T: Clone + Sync> Sync for TripleBuffer<T> {}
-
-#[derive(Debug)]
-pub struct LockedError<T>(pub T);
-#[derive(Debug)]
-pub struct UnlockedError<T>(pub T);
 
-#[derive(Debug)]
-pub enum Reader<T:Clone> {
-    Free(TB<T>),
-    Locked(LockedReader<T>),
+/// A borrow of the value the reader last picked up.
+pub struct ReadGuard<'a, T> {
+    value: ConstPtr<T>,
+    _reader: PhantomData<&'a mut Reader<T>>,
 }
-impl<T:Clone> Reader<T> {
-    #[must_use]
-    pub fn grab(self) -> Result<Self, LockedError<Self>> {
-        match self {
-            Reader::Free(tb) => Ok(Reader::Locked(LockedReader::lock(tb.0))),
-            _ => Err(LockedError(self)), // TODO consider returning self
-        }
+impl<'a, T> Deref for ReadGuard<'a, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        // The front slot belongs to the reader, which we are mutably borrowing.
+        unsafe { self.value.deref() }
     }
-    pub fn grab_always(self) -> Self {
-        match self {
-            Reader::Free(tb) => Reader::Locked(LockedReader::lock(tb.0)),
-            _ => self,
-        }
-    }
-    #[must_use]
-    pub fn fetch<'a>(&'a self) -> Result<&'a T, UnlockedError<()>> {
-        match self {
-            Reader::Locked(lr) => Ok(lr.r()),
-            _ => Err(UnlockedError(())), // TODO consider returning self
-        }
-    }
-    pub fn fetch_unsafe<'a>(&'a self) -> &'a T {
-        match self {
-            Reader::Locked(lr) => lr.r(),
-            _ => panic!("Attempted to get buffers from old buffer."),
+}
+
+/// The editing half of a triple buffer.
+pub struct Editor<T> {
+    shared: Arc<Shared<T>>,
+    /// The slot most recently committed. It is either the back slot or the reader's front slot, so
+    /// it may only be read.
+    committed: usize,
+    write: usize,
+}
+impl<T> Editor<T> {
+    /// Borrows the last committed value along with the slot to write the next one into.
+    ///
+    /// The write slot is not reset between grabs, so it holds whatever was last written into it,
+    /// be it an uncommitted edit or a value committed a few rounds ago.
+    pub fn grab(&mut self) -> EditGuard<'_, T> {
+        EditGuard {
+            r: self.shared.slots[self.committed].value.get(),
+            w: self.shared.slots[self.write].value.get_mut(),
+            editor: self,
+        }
+    }
+    /// Number of commits made so far.
+    pub fn version(&self) -> u64 {
+        self.shared.slots[self.committed].version.load(Ordering::Relaxed)
+    }
+    fn advance(&mut self) {
+        let version = self.version() + 1;
+        self.shared.slots[self.write].version.store(version, Ordering::Relaxed);
+        let back = self.shared.back.swap(self.write | DIRTY, Ordering::AcqRel);
+        trace!("Committing slot {:?} and taking slot {:?}.", self.write, back & INDEX_MASK);
+        self.committed = self.write;
+        self.write = back & INDEX_MASK;
+        if back & WAITING != 0 {
+            self.shared.wake();
         }
     }
-    #[must_use]
-    pub fn release(self) -> Result<Self, UnlockedError<Self>> {
-        match self {
-            Reader::Locked(lr) => Ok(Reader::Free(lr.release())),
-            _ => Err(UnlockedError(self)), // TODO consider returning self
+}
+impl<T> Drop for Editor<T> {
+    fn drop(&mut self) {
+        self.shared.closed.store(true, Ordering::Release);
+        // Going through the back slot's atomic pairs this with `register_waiting`, like a commit.
+        if self.shared.back.fetch_and(!WAITING, Ordering::AcqRel) & WAITING != 0 {
+            self.shared.wake();
         }
     }
-    pub fn release_always(self) -> Self {
-        match self {
-            Reader::Locked(lr) => Reader::Free(lr.release()),
-            _ => self,
-        }
+}
+impl<T> std::fmt::Debug for Editor<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Editor")
+            .field("committed", &self.committed)
+            .field("write", &self.write)
+            .finish()
     }
 }
-#[derive(Debug)]
-pub struct LockedReader<T:Clone> {
-    origin: Arc<TripleBuffer<T>>,
-    locked: *const T,
+
+/// A borrow of the editor's slots. Dropping it without committing keeps the edits in the write slot
+/// for the next grab.
+pub struct EditGuard<'a, T> {
+    editor: &'a mut Editor<T>,
+    r: ConstPtr<T>,
+    w: MutPtr<T>,
 }
-impl<T:Clone> LockedReader<T> {
-    fn lock(rv: Arc<TripleBuffer<T>>) -> LockedReader<T> {
-        rv.snatch();
-        Self {
-            locked: rv.rr(),
-            origin: rv,
+impl<'a, T> EditGuard<'a, T> {
+    /// The last committed value.
+    pub fn r(&self) -> &T {
+        // The committed slot is only ever read until the editor takes it back.
+        unsafe { self.r.deref() }
+    }
+    /// The value to be committed next.
+    pub fn w(&mut self) -> &mut T {
+        // The write slot belongs to the editor, which we are mutably borrowing.
+        unsafe { self.w.deref() }
+    }
+    pub fn rw(&mut self) -> RWPair<&T, &mut T> {
+        unsafe {
+            RWPair {
+                r: self.r.deref(),
+                w: self.w.deref(),
+            }
         }
     }
-    pub fn r<'a>(&'a self) -> &'a T {
-        unsafe { &*self.locked }
+    /// Publishes the write slot to the reader.
+    pub fn commit(self) {
+        self.commit_into();
     }
-    fn release(self) -> TB<T> {
-        TB(self.origin)
+    fn commit_into(self) -> &'a mut Editor<T> {
+        let EditGuard { editor, r, w } = self;
+        // Under loom, this ends the tracked accesses before the slots change hands.
+        #[allow(clippy::drop_non_drop)]
+        drop((r, w));
+        editor.advance();
+        editor
     }
 }
-unsafe impl<T: Clone + Send> Send for LockedReader<T> {}
-unsafe impl<T: Clone + Sync> Sync for LockedReader<T> {}
 
-#[derive(Debug)]
-pub struct RWPair<R, W> {
-    pub r: R,
-    pub w: W,
-}
-#[derive(Debug)]
-pub enum Editor<T:Clone> {
-    Free(TB<T>),
-    Locked(LockedEditor<T>),
+/// Creates a triple buffer with every slot initialized to `src`.
+pub fn buffer<T: Clone>(src: T) -> (Reader<T>, Editor<T>) {
+    let shared = Arc::new(Shared {
+        slots: [src.clone(), src.clone(), src].map(|value| {
+            CachePadded::new(Slot {
+                value: UnsafeCell::new(value),
+                version: AtomicU64::new(0),
+            })
+        }),
+        back: CachePadded::new(AtomicUsize::new(1)),
+        closed: AtomicBool::new(false),
+        lock: Mutex::new(None),
+        cv: Condvar::new(),
+    });
+    (
+        Reader {
+            shared: shared.clone(),
+            front: 0,
+        },
+        Editor {
+            shared,
+            committed: 1,
+            write: 2,
+        },
+    )
 }
-impl<T:Clone> Editor<T> {
-    #[must_use]
-    pub fn grab(self) -> Result<Self, LockedError<Self>> {
-        match self {
-            Editor::Free(tb) => Ok(Editor::Locked(LockedEditor::lock(tb.0))),
-            _ => Err(LockedError(self)),
-        }
-    }
-    pub fn grab_always(self) -> Self {
-        match self {
-            Editor::Free(tb) => Editor::Locked(LockedEditor::lock(tb.0)),
-            _ => self,
-        }
-    }
-    #[must_use]
-    pub fn fetch<'a>(&'a self) -> Result<RWPair<&'a T, &'a mut T>, UnlockedError<()>> {
-        match self {
-            Editor::Locked(lr) => Ok(RWPair { r: lr.r(), w: lr.w() }),
-            _ => Err(UnlockedError(())),
-        }
-    }
-    pub fn fetch_unsafe<'a>(&'a self) -> RWPair<&'a T, &'a mut T> {
-        self.fetch().expect("Called fetch on an unlock!")
+
+#[cfg(all(test, not(loom)))]
+mod test {
+    use super::*;
+
+    const ROUNDS: u32 = if cfg!(miri) { 20 } else { 1000 };
+
+    #[test]
+    fn starts_with_source_value() {
+        let (mut r, mut e) = buffer(7u32);
+        assert!(!r.has_new());
+        assert_eq!(*r.grab(), 7);
+        assert_eq!(*e.grab().r(), 7);
     }
-    #[must_use]
-    pub fn commit(self) -> Result<Self, UnlockedError<Self>> {
-        match self {
-            Editor::Locked(lr) => Ok(Editor::Free(lr.release())),
-            _ => Err(UnlockedError(self)),
-        }
+
+    #[test]
+    fn try_new_only_succeeds_after_commit() {
+        let (mut r, mut e) = buffer(0u32);
+        assert!(r.try_new().is_none());
+        let mut g = e.grab();
+        *g.w() = 1;
+        g.commit();
+        assert_eq!(r.try_new().map(|g| *g), Some(1));
+        assert!(r.try_new().is_none());
+        assert_eq!(*r.grab(), 1);
     }
-    pub fn commit_always(self) -> Self {
-        match self {
-            Editor::Locked(lr) => Editor::Free(lr.release()),
-            _ => self,
-        }
+
+    #[test]
+    fn uncommitted_edits_are_kept() {
+        let (mut r, mut e) = buffer(0u32);
+        *e.grab().w() = 3;
+        assert_eq!(*e.grab().w(), 3);
+        assert!(!r.has_new());
+        e.grab().commit();
+        assert_eq!(*r.grab(), 3);
+        assert_eq!(*e.grab().r(), 3);
     }
-}
-#[derive(Debug)]
-pub struct LockedEditor<T:Clone> {
-    origin: Arc<TripleBuffer<T>>,
-    rw_lock: RWPair<*const T, *mut T>,
-}
-impl<T:Clone> LockedEditor<T> {
-    fn lock(tb: Arc<TripleBuffer<T>>) -> LockedEditor<T> {
-        LockedEditor {
-            rw_lock: RWPair {
-                r: tb.er(),
-                w: tb.ew(),
-            },
-            origin: tb,
+
+    #[test]
+    fn reader_sees_only_the_newest_commit() {
+        let (mut r, mut e) = buffer(0u32);
+        for i in 1..=3 {
+            let mut g = e.grab();
+            *g.w() = i;
+            g.commit();
         }
+        assert_eq!(*r.grab(), 3);
     }
-    pub fn r<'a>(&'a self) -> &'a T {
-        unsafe { &*self.rw_lock.r }
+
+    #[test]
+    fn wait_timeout_gives_up() {
+        let (mut r, _e) = buffer(0u32);
+        let start = Instant::now();
+        assert!(r.wait_timeout(Duration::from_millis(20)).is_none());
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        assert!(!r.has_new());
     }
-    pub fn w<'a>(&'a self) -> &'a mut T {
-        unsafe { &mut *self.rw_lock.w }
+
+    #[test]
+    fn versions_count_commits() {
+        let (mut r, mut e) = buffer(());
+        e.grab().commit();
+        e.grab().commit();
+        assert_eq!(e.version(), 2);
+        assert_eq!(r.version(), 0);
+        r.grab();
+        assert_eq!(r.version(), 2);
     }
-    fn release(self) -> TB<T> {
-        self.origin.advance();
-        TB(self.origin)
+
+    #[test]
+    fn waiting_ends_when_the_editor_is_dropped() {
+        let (mut r, e) = buffer(0u32);
+        let editor = std::thread::spawn(move || drop(e));
+        assert_eq!(*r.wait_for_new(), 0);
+        assert!(r.is_closed());
+        assert!(r.wait_timeout(Duration::from_secs(10)).is_none());
+        editor.join().unwrap();
     }
-}
-unsafe impl<T: Clone + Send> Send for LockedEditor<T> {}
-unsafe impl<T: Clone + Sync> Sync for LockedEditor<T> {}
 
-pub fn buffer<T:Clone>(src: T) -> (Reader<T>, Editor<T>) {
-    let arc = TripleBuffer::alloc(src);
-    (Reader::Free(arc.clone()), Editor::Free(arc))
+    #[test]
+    fn values_never_go_backwards_across_threads() {
+        let (mut r, mut e) = buffer(0u32);
+        let editor = std::thread::spawn(move || {
+            for i in 1..=ROUNDS {
+                let mut g = e.grab();
+                let RWPair { r, w } = g.rw();
+                assert_eq!(*r, i - 1);
+                *w = i;
+                g.commit();
+            }
+        });
+        let mut last = 0;
+        while last != ROUNDS {
+            let curr = *r.wait_for_new();
+            assert!(curr > last);
+            last = curr;
+        }
+        editor.join().unwrap();
+    }
 }
-
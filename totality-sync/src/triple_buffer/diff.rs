@@ -0,0 +1,293 @@
+//! Editing mode that keeps stale slots up to date by replaying only what changed.
+//!
+//! A plain editor gets back whatever slot the reader was last done with, so it has to copy the whole
+//! committed value over before editing. A `DiffEditor` instead has the editor mark which elements it
+//! touches, remembers for every slot which elements it has missed since it last held the committed
+//! value, and patches just those in when the slot comes back around.
+
+use std::ops::Range;
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+use super::{EditGuard, Editor, RWPair};
+
+/// Sorted, disjoint, non-adjacent ranges of element indices.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DirtyRanges(Vec<Range<usize>>);
+impl DirtyRanges {
+    pub fn new() -> Self {
+        Self(vec![])
+    }
+    /// Every possible element.
+    pub fn all() -> Self {
+        let mut all = Self::new();
+        all.mark(0..usize::MAX);
+        all
+    }
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+    pub fn clear(&mut self) {
+        self.0.clear();
+    }
+    pub fn iter(&self) -> impl Iterator<Item = Range<usize>> + '_ {
+        self.0.iter().cloned()
+    }
+    pub fn mark(&mut self, range: Range<usize>) {
+        if range.is_empty() {
+            return;
+        }
+        // Everything that overlaps or touches the new range gets merged into it.
+        let lo = self.0.partition_point(|r| r.end < range.start);
+        let hi = self.0.partition_point(|r| r.start <= range.end);
+        let merged = if lo < hi {
+            self.0[lo].start.min(range.start)..self.0[hi - 1].end.max(range.end)
+        } else {
+            range
+        };
+        self.0.splice(lo..hi, std::iter::once(merged));
+    }
+    pub fn union(&mut self, other: &DirtyRanges) {
+        for range in other.iter() {
+            self.mark(range);
+        }
+    }
+}
+
+/// Types that can be brought up to date with a newer copy of themselves given which elements
+/// changed in between.
+pub trait Patch {
+    /// Makes `self` equal to `src`, assuming that they only differ in the `dirty` elements and in
+    /// length.
+    fn patch(&mut self, src: &Self, dirty: &DirtyRanges);
+    /// How many elements there are, so that changes in length can be marked.
+    fn elements(&self) -> usize;
+}
+impl<T: Clone> Patch for Vec<T> {
+    fn patch(&mut self, src: &Self, dirty: &DirtyRanges) {
+        self.truncate(src.len());
+        let len = self.len();
+        for range in dirty.iter() {
+            if range.start >= len {
+                break;
+            }
+            let range = range.start..range.end.min(len);
+            self[range.clone()].clone_from_slice(&src[range]);
+        }
+        self.extend_from_slice(&src[len..]);
+    }
+    fn elements(&self) -> usize {
+        self.len()
+    }
+}
+
+/// An editor that only copies the elements marked dirty into stale slots.
+///
+/// Every change made through `w` must be covered by a `mark` before committing, otherwise the
+/// slots drift apart. Changes in length are picked up without marking.
+#[derive(Debug)]
+pub struct DiffEditor<T: Patch> {
+    editor: Editor<T>,
+    /// Elements changed by the edit in progress.
+    dirty: DirtyRanges,
+    /// Elements each slot has missed since it last held the committed value.
+    stale: [DirtyRanges; 3],
+}
+impl<T: Patch> DiffEditor<T> {
+    /// Starts from the committed value with the write slot patched up to it, so edits build on the
+    /// last commit without copying it.
+    pub fn grab(&mut self) -> DiffGuard<'_, T> {
+        DiffGuard {
+            guard: self.editor.grab(),
+            dirty: &mut self.dirty,
+            stale: &mut self.stale,
+        }
+    }
+    pub fn version(&self) -> u64 {
+        self.editor.version()
+    }
+    /// Drops back to a plain editor, which copies or rebuilds the whole value on its own.
+    pub fn into_editor(self) -> Editor<T> {
+        self.editor
+    }
+}
+
+/// A borrow of the diff editor's slots. Dropping it without committing keeps both the edits and
+/// their marks for the next grab.
+pub struct DiffGuard<'a, T: Patch> {
+    guard: EditGuard<'a, T>,
+    dirty: &'a mut DirtyRanges,
+    stale: &'a mut [DirtyRanges; 3],
+}
+impl<'a, T: Patch> DiffGuard<'a, T> {
+    /// The last committed value.
+    pub fn r(&self) -> &T {
+        self.guard.r()
+    }
+    /// The value to be committed next. Starts out equal to `r`.
+    pub fn w(&mut self) -> &mut T {
+        self.guard.w()
+    }
+    pub fn rw(&mut self) -> RWPair<&T, &mut T> {
+        self.guard.rw()
+    }
+    /// Records that the elements in `range` changed.
+    pub fn mark(&mut self, range: Range<usize>) {
+        self.dirty.mark(range);
+    }
+    /// Publishes the write slot to the reader, then patches the slot handed back up to it.
+    pub fn commit(self) {
+        let DiffGuard {
+            mut guard,
+            dirty,
+            stale,
+        } = self;
+        // Slots that missed a shrink would otherwise keep their old elements past the new end, and
+        // hand them out again when it grows back.
+        let (before, after) = (guard.r().elements(), guard.w().elements());
+        dirty.mark(before.min(after)..before.max(after));
+        let editor = guard.commit_into();
+        for (i, slot) in stale.iter_mut().enumerate() {
+            if i == editor.committed {
+                slot.clear();
+            } else {
+                slot.union(dirty);
+            }
+        }
+        dirty.clear();
+        let write = &mut stale[editor.write];
+        trace!("Patching slot {:?} with {:?}.", editor.write, write);
+        let mut g = editor.grab();
+        let RWPair { r, w } = g.rw();
+        w.patch(r, write);
+        write.clear();
+    }
+}
+
+impl<T: Patch> Editor<T> {
+    /// Switches to recording dirty ranges. What the other slots missed beforehand is unknown, so
+    /// each is copied over in full the first time it comes back.
+    pub fn into_diff(self) -> DiffEditor<T> {
+        let mut stale = [DirtyRanges::all(), DirtyRanges::all(), DirtyRanges::all()];
+        stale[self.committed].clear();
+        let mut editor = DiffEditor {
+            editor: self,
+            dirty: DirtyRanges::new(),
+            stale,
+        };
+        // Bring the write slot up to date right away, so the first edit starts from the committed
+        // value like every other.
+        let write = editor.editor.write;
+        let mut g = editor.editor.grab();
+        let RWPair { r, w } = g.rw();
+        w.patch(r, &editor.stale[write]);
+        editor.stale[write].clear();
+        editor
+    }
+}
+
+/// Creates a triple buffer with a diff editor. The slots start out identical, so nothing is stale.
+pub fn diff_buffer<T: Clone + Patch>(src: T) -> (super::Reader<T>, DiffEditor<T>) {
+    let (r, e) = super::buffer(src);
+    (
+        r,
+        DiffEditor {
+            editor: e,
+            dirty: DirtyRanges::new(),
+            stale: Default::default(),
+        },
+    )
+}
+
+#[cfg(all(test, not(loom)))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn ranges_merge() {
+        let mut d = DirtyRanges::new();
+        d.mark(5..7);
+        d.mark(0..2);
+        d.mark(10..12);
+        assert_eq!(d.iter().collect::<Vec<_>>(), vec![0..2, 5..7, 10..12]);
+        d.mark(2..5);
+        assert_eq!(d.iter().collect::<Vec<_>>(), vec![0..7, 10..12]);
+        d.mark(3..11);
+        assert_eq!(d.iter().collect::<Vec<_>>(), vec![0..12]);
+        d.mark(20..20);
+        assert_eq!(d.iter().collect::<Vec<_>>(), vec![0..12]);
+    }
+
+    #[test]
+    fn vec_patch_handles_length_changes() {
+        let src = vec![1, 2, 3, 4];
+        let mut dst = vec![1, 0, 3, 0, 9, 9];
+        let mut dirty = DirtyRanges::new();
+        dirty.mark(1..2);
+        dirty.mark(3..6);
+        dst.patch(&src, &dirty);
+        assert_eq!(dst, src);
+        let mut short = vec![1];
+        short.patch(&src, &DirtyRanges::new());
+        assert_eq!(short, src);
+    }
+
+    fn edit(e: &mut DiffEditor<Vec<u32>>, shadow: &mut Vec<u32>, step: u32) {
+        let mut g = e.grab();
+        assert_eq!(g.w(), shadow);
+        let i = (step as usize * 7) % shadow.len();
+        g.w()[i] = step;
+        shadow[i] = step;
+        g.mark(i..i + 1);
+        if step.is_multiple_of(5) {
+            g.w().push(step);
+            shadow.push(step);
+        }
+        g.commit();
+    }
+
+    #[test]
+    fn reader_sees_every_commit_while_lagging() {
+        let (mut r, mut e) = diff_buffer(vec![0u32; 32]);
+        let mut shadow = vec![0u32; 32];
+        for step in 1..50 {
+            edit(&mut e, &mut shadow, step);
+            // Only pick up every few commits, so slots sit stale for a while.
+            if step % 3 == 0 {
+                assert_eq!(*r.grab(), shadow);
+            }
+        }
+        assert_eq!(*r.grab(), shadow);
+    }
+
+    #[test]
+    fn regrown_elements_are_not_stale() {
+        let (mut r, mut e) = diff_buffer(vec![0u32; 10]);
+        let mut g = e.grab();
+        g.w().truncate(5);
+        g.commit();
+        assert_eq!(*r.grab(), vec![0; 5]);
+        let mut g = e.grab();
+        g.w().extend([7, 7, 7]);
+        g.commit();
+        e.grab().commit();
+        assert_eq!(*r.grab(), vec![0, 0, 0, 0, 0, 7, 7, 7]);
+    }
+
+    #[test]
+    fn converting_a_used_editor_copies_once() {
+        let (mut r, mut e) = super::super::buffer(vec![0u32; 8]);
+        for i in 1..4 {
+            let mut g = e.grab();
+            g.w()[0] = i;
+            g.commit();
+        }
+        let mut e = e.into_diff();
+        let mut shadow = vec![3, 0, 0, 0, 0, 0, 0, 0];
+        for step in 4..20 {
+            edit(&mut e, &mut shadow, step);
+            assert_eq!(*r.grab(), shadow);
+        }
+    }
+}
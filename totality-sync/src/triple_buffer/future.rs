@@ -0,0 +1,155 @@
+//! Asynchronous counterparts to the reader's blocking waits.
+//!
+//! An awaiting reader parks its `Waker` in the buffer, and the editor wakes it on its next commit
+//! the same way it would wake a thread parked on the condvar. There is room for a single waker,
+//! which is why awaiting mutably borrows the reader.
+
+use std::{
+    future::poll_fn,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures_core::Stream;
+
+use super::{ClosedError, Reader, DIRTY};
+
+impl<T> Reader<T> {
+    fn poll_changed(&self, cx: &mut Context<'_>) -> Poll<Result<(), ClosedError>> {
+        if self.has_new() {
+            return Poll::Ready(Ok(()));
+        }
+        let shared = &*self.shared;
+        let mut waker = shared.lock();
+        if shared.register_waiting() & DIRTY != 0 {
+            return Poll::Ready(Ok(()));
+        }
+        if self.is_closed() {
+            return Poll::Ready(Err(ClosedError));
+        }
+        match &*waker {
+            Some(w) if w.will_wake(cx.waker()) => {}
+            _ => *waker = Some(cx.waker().clone()),
+        }
+        Poll::Pending
+    }
+    /// Resolves once the editor commits something this reader has not picked up yet. Resolves
+    /// immediately if there already is something new, and fails once the editor has been dropped.
+    ///
+    /// Like `grab`, this borrows the reader mutably, so only one future can be waiting on it at a
+    /// time.
+    pub async fn changed(&mut self) -> Result<(), ClosedError> {
+        poll_fn(|cx| self.poll_changed(cx)).await
+    }
+    /// Turns the reader into a stream that yields the current value, then every value it picks up
+    /// afterwards. Commits made in between polls are skipped, like with `grab`.
+    pub fn into_stream(self) -> Snapshots<T>
+    where
+        T: Clone,
+    {
+        Snapshots {
+            reader: self,
+            started: false,
+        }
+    }
+}
+
+/// A value picked up by the reader along with the number of commits made up to it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Versioned<T> {
+    pub version: u64,
+    pub value: T,
+}
+
+/// Stream of the values committed to a triple buffer. Ends once the editor is dropped and its last
+/// commit has been yielded.
+#[derive(Debug)]
+pub struct Snapshots<T> {
+    reader: Reader<T>,
+    started: bool,
+}
+impl<T: Clone> Snapshots<T> {
+    fn snapshot(&mut self) -> Versioned<T> {
+        let value = self.reader.grab().clone();
+        Versioned {
+            version: self.reader.version(),
+            value,
+        }
+    }
+    pub fn into_reader(self) -> Reader<T> {
+        self.reader
+    }
+}
+impl<T: Clone> Stream for Snapshots<T> {
+    type Item = Versioned<T>;
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        if !this.started {
+            this.started = true;
+            return Poll::Ready(Some(this.snapshot()));
+        }
+        match this.reader.poll_changed(cx) {
+            Poll::Ready(Ok(())) => Poll::Ready(Some(this.snapshot())),
+            Poll::Ready(Err(ClosedError)) => Poll::Ready(None),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod test {
+    use std::{thread, time::Duration};
+
+    use futures::{executor::block_on, StreamExt};
+
+    use super::super::buffer;
+
+    #[test]
+    fn changed_wakes_on_commit() {
+        let (mut r, mut e) = buffer(0u32);
+        let editor = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(10));
+            let mut g = e.grab();
+            *g.w() = 1;
+            g.commit();
+            e
+        });
+        block_on(r.changed()).unwrap();
+        let _e = editor.join().unwrap();
+        assert!(r.has_new());
+    }
+
+    #[test]
+    fn changed_fails_once_editor_is_dropped() {
+        let (mut r, e) = buffer(0u32);
+        let editor = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(10));
+            drop(e);
+        });
+        assert!(block_on(r.changed()).is_err());
+        editor.join().unwrap();
+    }
+
+    #[test]
+    fn stream_yields_increasing_versions_then_ends() {
+        let (r, mut e) = buffer(0u32);
+        let editor = thread::spawn(move || {
+            for i in 1..=100 {
+                let mut g = e.grab();
+                *g.w() = i;
+                g.commit();
+            }
+        });
+        let snapshots = block_on(r.into_stream().collect::<Vec<_>>());
+        editor.join().unwrap();
+        assert_eq!(snapshots.first().map(|s| s.version), Some(0));
+        assert_eq!(
+            snapshots.last().map(|s| (s.version, s.value)),
+            Some((100, 100))
+        );
+        for pair in snapshots.windows(2) {
+            assert!(pair[0].version < pair[1].version);
+            assert_eq!(pair[1].version, pair[1].value as u64);
+        }
+    }
+}
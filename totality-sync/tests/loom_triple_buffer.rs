@@ -0,0 +1,124 @@
+//! Model checks for the triple buffer. Run with
+//! `RUSTFLAGS="--cfg loom" cargo test -p totality-sync --features async --test loom_triple_buffer --release`.
+#![cfg(loom)]
+
+use loom::thread;
+use totality_sync::triple_buffer::{buffer, RWPair};
+
+#[test]
+fn reader_never_sees_a_torn_or_stale_commit() {
+    loom::model(|| {
+        let (mut r, mut e) = buffer((0u32, 0u32));
+        let editor = thread::spawn(move || {
+            for i in 1..=2 {
+                let mut g = e.grab();
+                let RWPair { r, w } = g.rw();
+                assert_eq!(r.0, i - 1);
+                *w = (i, i);
+                g.commit();
+            }
+        });
+        let mut last = 0;
+        for _ in 0..2 {
+            let g = r.grab();
+            assert_eq!(g.0, g.1);
+            assert!(g.0 >= last);
+            last = g.0;
+        }
+        editor.join().unwrap();
+        assert_eq!(*r.grab(), (2, 2));
+    });
+}
+
+#[test]
+fn try_new_reports_every_unseen_commit() {
+    loom::model(|| {
+        let (mut r, mut e) = buffer(0u32);
+        let editor = thread::spawn(move || {
+            let mut g = e.grab();
+            *g.w() = 1;
+            g.commit();
+        });
+        let seen = r.try_new().map(|g| *g);
+        editor.join().unwrap();
+        match seen {
+            Some(v) => {
+                assert_eq!(v, 1);
+                assert!(r.try_new().is_none());
+            }
+            None => assert_eq!(r.try_new().map(|g| *g), Some(1)),
+        }
+    });
+}
+
+#[test]
+fn wait_for_new_is_always_woken() {
+    loom::model(|| {
+        let (mut r, mut e) = buffer(0u32);
+        let editor = thread::spawn(move || {
+            let mut g = e.grab();
+            *g.w() = 1;
+            g.commit();
+        });
+        assert_eq!(*r.wait_for_new(), 1);
+        editor.join().unwrap();
+    });
+}
+
+#[test]
+fn editor_keeps_committing_while_reader_holds_a_value() {
+    loom::model(|| {
+        let (mut r, mut e) = buffer(0u32);
+        let reader = thread::spawn(move || {
+            let g = r.grab();
+            let v = *g;
+            // Hold on to the slot while the editor keeps cycling through the other two.
+            thread::yield_now();
+            assert_eq!(*g, v);
+        });
+        for i in 1..=3 {
+            let mut g = e.grab();
+            *g.w() = i;
+            g.commit();
+        }
+        reader.join().unwrap();
+    });
+}
+
+#[test]
+fn wait_for_new_returns_once_editor_is_dropped() {
+    loom::model(|| {
+        let (mut r, e) = buffer(0u32);
+        let editor = thread::spawn(move || drop(e));
+        assert_eq!(*r.wait_for_new(), 0);
+        editor.join().unwrap();
+    });
+}
+
+#[cfg(feature = "async")]
+#[test]
+fn changed_is_always_woken() {
+    loom::model(|| {
+        let (mut r, mut e) = buffer(0u32);
+        let editor = thread::spawn(move || {
+            let mut g = e.grab();
+            *g.w() = 1;
+            g.commit();
+            e
+        });
+        loom::future::block_on(r.changed()).unwrap();
+        drop(editor.join().unwrap());
+        assert!(loom::future::block_on(r.changed()).is_ok());
+    });
+}
+
+#[cfg(feature = "async")]
+#[test]
+fn changed_fails_once_editor_is_dropped() {
+    loom::model(|| {
+        let (mut r, e) = buffer(0u32);
+        let editor = thread::spawn(move || drop(e));
+        assert!(loom::future::block_on(r.changed()).is_err());
+        editor.join().unwrap();
+    });
+}
@@ -0,0 +1,425 @@
+//! # Builder
+//!
+//! Starts looping `KillableThread`s from closures.
+//!
+//! The thread's state is created by a setup closure on the thread itself, handed to the body every
+//! loop and to the command handler whenever a command arrives, and finally consumed by a teardown
+//! closure to produce the thread's result. Any of them can fail, which stops the thread and comes
+//! back out of `try_finish`.
+//!
+//! ```ignore
+//! let kt = ThreadBuilder::new("Simulation")
+//!     .pacing(Pacing::Rate(60))
+//!     .setup(|| Ok::<_, SimError>(Simulation::new()))
+//!     .body(|sim| sim.step().map(|_| Flow::Continue))
+//!     .on_command(|sim, c: SimCommand| sim.apply(c).map(|_| Flow::Continue))
+//!     .spawn()?;
+//! kt.command(SimCommand::Reset)?;
+//! let sim = kt.try_finish()?;
+//! ```
+
+use std::{
+    any::Any,
+    io,
+    sync::{mpsc, Arc},
+    time::Duration,
+};
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+use crate::{
+    clock::{Clock, SystemClock},
+    control::{Control, Controller, Event},
+    killable_thread::KillableThread,
+    telemetry::Registry,
+    timestep::Alpha,
+};
+
+/// How often a thread loops.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Pacing {
+    /// As fast as possible.
+    #[default]
+    Free,
+    /// Aim to take exactly this long per loop.
+    Duration(Duration),
+    /// Aim to loop this many times per second. Must be nonzero.
+    Rate(u32),
+    /// Loop once per `step` of elapsed time, running several loops back to back to catch up after
+    /// an overrun, but no more than `max_steps` at once. See `timestep`.
+    Fixed { step: Duration, max_steps: u32 },
+    /// Only loop once after every batch of commands.
+    Messages,
+}
+
+/// Whether a thread should keep looping.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flow {
+    Continue,
+    Stop,
+}
+
+/// Why a thread built with closures did not produce a result.
+#[derive(Debug)]
+pub enum ThreadError<E> {
+    Panicked(Box<dyn Any + Send>),
+    /// One of the closures returned an error.
+    Failed(E),
+}
+
+impl<P: Send + 'static, T: Send + 'static, E: Send + 'static> KillableThread<P, Result<T, E>> {
+    /// Like `finish`, but with the thread's own errors and panics folded together.
+    pub fn try_finish(self) -> Result<T, ThreadError<E>> {
+        match self
+            .finish()
+            .expect("The handle is only taken when finishing.")
+        {
+            Ok(Ok(t)) => Ok(t),
+            Ok(Err(e)) => Err(ThreadError::Failed(e)),
+            Err(panic) => Err(ThreadError::Panicked(panic)),
+        }
+    }
+}
+
+/// The name and pacing of a thread yet to be started.
+#[derive(Debug, Clone)]
+pub struct ThreadBuilder {
+    name: String,
+    pacing: Pacing,
+    alpha: Alpha,
+    clock: Arc<dyn Clock>,
+    telemetry: Registry,
+}
+impl ThreadBuilder {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            pacing: Pacing::Free,
+            alpha: Alpha::new(),
+            clock: Arc::new(SystemClock),
+            telemetry: Registry::global().clone(),
+        }
+    }
+    pub fn pacing(mut self, pacing: Pacing) -> Self {
+        self.pacing = pacing;
+        self
+    }
+    /// Keeps time with `clock` instead of the system's.
+    pub fn clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+    /// Reports loop statistics to `registry` instead of the global one.
+    pub fn telemetry(mut self, registry: &Registry) -> Self {
+        self.telemetry = registry.clone();
+        self
+    }
+    /// How far a `Pacing::Fixed` thread is into its next step, for interpolating in between.
+    pub fn alpha(&self) -> Alpha {
+        self.alpha.clone()
+    }
+    /// Creates the thread's state on the thread itself before the first loop.
+    pub fn setup<S, E, F>(self, f: F) -> LoopBuilder<S, E>
+    where
+        S: 'static,
+        E: Send + 'static,
+        F: FnOnce() -> Result<S, E> + Send + 'static,
+    {
+        LoopBuilder {
+            builder: self,
+            setup: Box::new(f),
+            body: Box::new(|_| Ok(Flow::Continue)),
+            on_command: Box::new(|_, ()| Ok(Flow::Continue)),
+            teardown: Box::new(Ok),
+        }
+    }
+    /// Starts the thread with `f` driving the loop itself, for when closures are not enough. The
+    /// controller yields an event whenever `f` should do something.
+    pub fn spawn_with<C, T, F>(self, f: F) -> Result<KillableThread<Control<C>, T>, io::Error>
+    where
+        C: Send + 'static,
+        T: Send + 'static,
+        F: FnOnce(Controller<C>) -> T + Send + 'static,
+    {
+        match self.pacing {
+            Pacing::Rate(0) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "Rate must be nonzero.",
+                ));
+            }
+            Pacing::Fixed { step, .. } if step.is_zero() => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "Step must be nonzero.",
+                ));
+            }
+            Pacing::Fixed { max_steps: 0, .. } => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "Max steps must be nonzero.",
+                ));
+            }
+            _ => (),
+        }
+        let (tx, rx) = mpsc::channel();
+        let ThreadBuilder {
+            name,
+            pacing,
+            alpha,
+            clock,
+            telemetry,
+        } = self;
+        let probe = telemetry.probe(&name);
+        KillableThread::new(tx, name.clone(), move || {
+            info!("Starting {} thread.", name);
+            let ret = f(Controller::new(
+                rx,
+                name.clone(),
+                pacing,
+                alpha,
+                clock,
+                probe,
+            ));
+            trace!("{} thread winding down.", name);
+            ret
+        })
+    }
+}
+
+type Setup<S, E> = Box<dyn FnOnce() -> Result<S, E> + Send>;
+type Body<S, E> = Box<dyn FnMut(&mut S) -> Result<Flow, E> + Send>;
+type OnCommand<S, E, C> = Box<dyn FnMut(&mut S, C) -> Result<Flow, E> + Send>;
+type Teardown<S, E, T> = Box<dyn FnOnce(S) -> Result<T, E> + Send>;
+
+/// A thread with its state set up, waiting for the rest of its closures.
+pub struct LoopBuilder<S, E, C = (), T = S> {
+    builder: ThreadBuilder,
+    setup: Setup<S, E>,
+    body: Body<S, E>,
+    on_command: OnCommand<S, E, C>,
+    teardown: Teardown<S, E, T>,
+}
+impl<S, E, C, T> LoopBuilder<S, E, C, T>
+where
+    S: 'static,
+    E: Send + 'static,
+    C: Send + 'static,
+{
+    /// Runs every loop.
+    pub fn body(mut self, f: impl FnMut(&mut S) -> Result<Flow, E> + Send + 'static) -> Self {
+        self.body = Box::new(f);
+        self
+    }
+    /// Runs for every command sent to the thread, in between loops. Without one, commands are
+    /// ignored.
+    pub fn on_command<C2: Send + 'static>(
+        self,
+        f: impl FnMut(&mut S, C2) -> Result<Flow, E> + Send + 'static,
+    ) -> LoopBuilder<S, E, C2, T> {
+        LoopBuilder {
+            builder: self.builder,
+            setup: self.setup,
+            body: self.body,
+            on_command: Box::new(f),
+            teardown: self.teardown,
+        }
+    }
+    /// Turns the state into the thread's result once it stops. Without one, the state itself is
+    /// the result. Does not run if another closure failed.
+    pub fn teardown<T2: Send + 'static>(
+        self,
+        f: impl FnOnce(S) -> Result<T2, E> + Send + 'static,
+    ) -> LoopBuilder<S, E, C, T2> {
+        LoopBuilder {
+            builder: self.builder,
+            setup: self.setup,
+            body: self.body,
+            on_command: self.on_command,
+            teardown: Box::new(f),
+        }
+    }
+    pub fn spawn(self) -> Result<KillableThread<Control<C>, Result<T, E>>, io::Error>
+    where
+        T: Send + 'static,
+    {
+        let LoopBuilder {
+            builder,
+            setup,
+            mut body,
+            mut on_command,
+            teardown,
+        } = self;
+        builder.spawn_with(move |ctl| {
+            let mut state = setup()?;
+            for event in ctl {
+                let flow = match event {
+                    Event::Tick => body(&mut state)?,
+                    Event::Command(c) => on_command(&mut state, c)?,
+                };
+                if flow == Flow::Stop {
+                    break;
+                }
+            }
+            teardown(state)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::clock::ManualClock;
+
+    #[test]
+    fn state_flows_from_setup_to_teardown() {
+        let (tx, rx) = mpsc::channel();
+        let kt = ThreadBuilder::new("builder")
+            .pacing(Pacing::Duration(Duration::from_millis(1)))
+            .setup(|| Ok::<_, String>((vec![], tx)))
+            .body(|(v, tx)| {
+                v.push(0);
+                if v.len() < 5 {
+                    Ok(Flow::Continue)
+                } else {
+                    tx.send(()).unwrap();
+                    Ok(Flow::Stop)
+                }
+            })
+            .teardown(|(v, _)| Ok(v.len()))
+            .spawn()
+            .unwrap();
+        // Finishing kills the thread, so let it stop by itself first.
+        rx.recv().unwrap();
+        assert_eq!(kt.try_finish().unwrap(), 5);
+    }
+
+    #[test]
+    fn message_driven_threads_loop_after_commands() {
+        let kt = ThreadBuilder::new("builder")
+            .pacing(Pacing::Messages)
+            .setup(|| Ok::<_, ()>((0, 0)))
+            .body(|(_, loops)| {
+                *loops += 1;
+                Ok(Flow::Continue)
+            })
+            .on_command(|(sum, _), n: u32| {
+                *sum += n;
+                Ok(Flow::Continue)
+            })
+            .spawn()
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+        for n in 1..=4 {
+            kt.command(n).unwrap();
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        let (sum, loops) = kt.try_finish().unwrap();
+        assert_eq!(sum, 10);
+        // Commands may arrive in batches, but never loop without one.
+        assert!((1..=4).contains(&loops), "Looped {} times.", loops);
+    }
+
+    #[test]
+    fn fixed_steps_catch_up() {
+        let clock = ManualClock::new();
+        let start = clock.now();
+        let builder = ThreadBuilder::new("builder")
+            .pacing(Pacing::Fixed {
+                step: Duration::from_millis(5),
+                max_steps: 100,
+            })
+            .clock(clock.clone());
+        let alpha = builder.alpha();
+        let (tx, rx) = mpsc::channel();
+        let overrun = clock.clone();
+        let kt = builder
+            .setup(move || Ok::<_, ()>((0u32, tx)))
+            .body(move |(loops, tx)| {
+                *loops += 1;
+                // Overrun once by several steps, which has to be made up for.
+                if *loops == 2 {
+                    overrun.advance(Duration::from_millis(30));
+                }
+                if *loops == 12 {
+                    tx.send(()).unwrap();
+                    return Ok(Flow::Stop);
+                }
+                Ok(Flow::Continue)
+            })
+            .spawn()
+            .unwrap();
+        rx.recv_timeout(Duration::from_millis(500)).unwrap();
+        kt.try_finish().unwrap();
+        // Twelve steps of 5ms take 55ms after the first, regardless of the overrun.
+        assert_eq!(clock.now() - start, Duration::from_millis(55));
+        assert_eq!(alpha.get(), 0.);
+    }
+
+    #[test]
+    fn paced_threads_keep_time_with_their_clock() {
+        let clock = ManualClock::new();
+        let start = clock.now();
+        // Sleeping on a manual clock returns right away, so a second of loops takes no time.
+        let (tx, rx) = mpsc::channel();
+        let kt = ThreadBuilder::new("builder")
+            .pacing(Pacing::Rate(100))
+            .clock(clock.clone())
+            .setup(move || Ok::<_, ()>((0, tx)))
+            .body(|(loops, tx)| {
+                *loops += 1;
+                if *loops == 101 {
+                    tx.send(()).unwrap();
+                    return Ok(Flow::Stop);
+                }
+                Ok(Flow::Continue)
+            })
+            .spawn()
+            .unwrap();
+        rx.recv_timeout(Duration::from_millis(500)).unwrap();
+        kt.try_finish().unwrap();
+        assert_eq!(clock.now() - start, Duration::from_secs(1));
+    }
+
+    #[test]
+    fn errors_and_panics_come_out_of_finish() {
+        let kt = ThreadBuilder::new("builder")
+            .setup(|| Ok::<_, &str>(()))
+            .on_command(|_, fail: bool| {
+                if fail {
+                    Err("failed")
+                } else {
+                    Ok(Flow::Continue)
+                }
+            })
+            .spawn()
+            .unwrap();
+        kt.command(false).unwrap();
+        kt.command(true).unwrap();
+        assert!(matches!(
+            kt.try_finish(),
+            Err(ThreadError::Failed("failed"))
+        ));
+
+        let kt = ThreadBuilder::new("builder")
+            .setup(|| -> Result<(), ()> { panic!("setup") })
+            .spawn()
+            .unwrap();
+        assert!(matches!(kt.try_finish(), Err(ThreadError::Panicked(_))));
+
+        assert!(ThreadBuilder::new("builder")
+            .pacing(Pacing::Rate(0))
+            .setup(|| Ok::<(), ()>(()))
+            .spawn()
+            .is_err());
+        assert!(ThreadBuilder::new("builder")
+            .pacing(Pacing::Fixed {
+                step: Duration::from_millis(1),
+                max_steps: 0,
+            })
+            .setup(|| Ok::<(), ()>(()))
+            .spawn()
+            .is_err());
+    }
+}
@@ -0,0 +1,397 @@
+//! # Control
+//!
+//! The protocol threads made by `builder::ThreadBuilder` speak over a `KillableThread`'s channel.
+//!
+//! Dropping the sender still stops the thread. On top of that, the thread can be paused, resumed,
+//! stepped one loop at a time while paused, and retargeted to a new duration or rate, and it
+//! receives user-defined commands in between loops. A message-driven thread only loops after
+//! receiving commands, and a fixed-step thread runs as many loops as it takes to catch up.
+
+use std::{
+    sync::{
+        mpsc::{Receiver, RecvError, SendError, TryRecvError},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+use crate::{
+    builder::Pacing,
+    clock::Clock,
+    killable_thread::KillableThread,
+    profile::{self, Scope},
+    telemetry::Probe,
+    timestep::{Alpha, FixedStep},
+};
+
+/// A message to a looping thread.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Control<C> {
+    /// Stop looping until resumed, stepped, or killed.
+    Pause,
+    Resume,
+    /// Run a single loop while paused.
+    Step,
+    /// Aim to take this long per loop.
+    SetDuration(Duration),
+    /// Aim to run this many loops per second. Zero is ignored.
+    SetRate(u32),
+    /// Handed to the thread's command handler.
+    Command(C),
+}
+
+/// What a looping thread should do next.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Event<C> {
+    /// Run the loop's body.
+    Tick,
+    /// Handle a command.
+    Command(C),
+}
+
+/// The thread's end of the control channel, along with the state the messages change.
+///
+/// Iterating over it paces the loop and blocks while paused, and ends once the controlling side
+/// is gone.
+#[derive(Debug)]
+pub struct Controller<C> {
+    rx: Receiver<Control<C>>,
+    name: String,
+    paused: bool,
+    steps: usize,
+    target: Option<Duration>,
+    fixed: Option<FixedStep>,
+    /// Fixed steps still to be run before waiting for more.
+    due: u32,
+    /// Whether ticks only follow commands.
+    driven: bool,
+    /// Whether a command arrived since the last tick.
+    fresh: bool,
+    tick: Option<Instant>,
+    /// When the last loop's body finished.
+    idle_since: Option<Instant>,
+    /// Profiles the loop or command being handled.
+    span: Option<Scope>,
+    clock: Arc<dyn Clock>,
+    probe: Probe,
+}
+impl<C> Controller<C> {
+    /// Creates a controller that keeps time with `clock` and reports its loops to `probe`. A fixed
+    /// step publishes its interpolation alpha to `alpha`.
+    ///
+    /// # Panics
+    ///
+    /// If `pacing` is a rate of 0 or a step of zero length.
+    pub fn new(
+        rx: Receiver<Control<C>>,
+        name: String,
+        pacing: Pacing,
+        alpha: Alpha,
+        clock: Arc<dyn Clock>,
+        probe: Probe,
+    ) -> Self {
+        let fixed = match pacing {
+            Pacing::Fixed { step, max_steps } => {
+                Some(FixedStep::with_alpha(step, max_steps, alpha))
+            }
+            _ => None,
+        };
+        let (target, driven) = match pacing {
+            Pacing::Free => (None, false),
+            Pacing::Duration(d) => (Some(d), false),
+            Pacing::Rate(rate) => (
+                Some(
+                    Duration::from_secs(1)
+                        .checked_div(rate)
+                        .expect("Rate must be nonzero."),
+                ),
+                false,
+            ),
+            Pacing::Fixed { .. } | Pacing::Messages => (None, matches!(pacing, Pacing::Messages)),
+        };
+        Self {
+            rx,
+            name,
+            paused: false,
+            steps: 0,
+            target,
+            fixed,
+            due: 0,
+            driven,
+            fresh: false,
+            tick: None,
+            idle_since: None,
+            span: None,
+            clock,
+            probe,
+        }
+    }
+    /// How long a loop should take, if it is paced at all.
+    pub fn target(&self) -> Option<Duration> {
+        self.fixed.as_ref().map(FixedStep::step).or(self.target)
+    }
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+    /// Sleeps for `d`, reporting how much longer the sleep took.
+    fn sleep(&self, d: Duration) {
+        let start = self.clock.now();
+        self.clock.sleep(d);
+        self.probe.slept(d, self.clock.now() - start);
+    }
+    fn retarget(&mut self, target: Duration) {
+        match &mut self.fixed {
+            Some(fixed) => fixed.set_step(target),
+            None => self.target = Some(target),
+        }
+        self.driven = false;
+    }
+    /// Works through pending messages, blocking while paused or waiting for commands.
+    fn poll(&mut self) -> Option<Event<C>> {
+        loop {
+            let blocked = if self.paused {
+                self.steps == 0
+            } else {
+                self.driven && !self.fresh
+            };
+            let msg = if blocked {
+                self.rx
+                    .recv()
+                    .map_err(|RecvError| TryRecvError::Disconnected)
+            } else {
+                self.rx.try_recv()
+            };
+            match msg {
+                Ok(Control::Pause) => self.paused = true,
+                Ok(Control::Resume) => {
+                    self.paused = false;
+                    self.steps = 0;
+                    // Time spent paused is not to be caught up on.
+                    if let Some(fixed) = &mut self.fixed {
+                        fixed.reset(self.clock.now());
+                    }
+                }
+                Ok(Control::Step) => {
+                    if self.paused {
+                        self.steps += 1;
+                    }
+                }
+                Ok(Control::SetDuration(d)) if d.is_zero() && self.fixed.is_some() => {
+                    warn!("Ignoring a fixed step of 0.")
+                }
+                Ok(Control::SetDuration(d)) => self.retarget(d),
+                Ok(Control::SetRate(rate)) => match Duration::from_secs(1).checked_div(rate) {
+                    Some(d) => self.retarget(d),
+                    None => warn!("Ignoring a target rate of 0."),
+                },
+                Ok(Control::Command(c)) => {
+                    self.fresh = true;
+                    return Some(Event::Command(c));
+                }
+                Err(TryRecvError::Empty) => {
+                    if self.paused {
+                        self.steps -= 1;
+                    }
+                    self.fresh = false;
+                    return Some(Event::Tick);
+                }
+                Err(TryRecvError::Disconnected) => return None,
+            }
+        }
+    }
+}
+impl<C> Iterator for Controller<C> {
+    type Item = Event<C>;
+    fn next(&mut self) -> Option<Event<C>> {
+        self.span = None;
+        if let Some(start) = self.tick.take() {
+            let busy_time = self.clock.now() - start;
+            self.probe.busy(busy_time, self.target());
+            if let Some(target) = self.target {
+                if target > busy_time {
+                    self.sleep(target - busy_time);
+                }
+            }
+            trace!(
+                "{} thread spent {:?} busy and {:?} total in loop.",
+                self.name,
+                busy_time,
+                self.clock.now() - start
+            );
+            self.idle_since = Some(start + busy_time);
+        }
+        if self.due == 0 && !self.paused {
+            if let Some(wait) = self.fixed.as_ref().map(|f| f.until_next(self.clock.now())) {
+                if !wait.is_zero() {
+                    self.sleep(wait);
+                }
+                let now = self.clock.now();
+                if let Some(fixed) = &mut self.fixed {
+                    self.due = fixed.advance(now);
+                }
+            }
+        }
+        trace!("Checking {} thread's control channel.", self.name);
+        let event = self.poll();
+        match event {
+            Some(Event::Tick) => {
+                self.due = self.due.saturating_sub(1);
+                let now = self.clock.now();
+                if let Some(since) = self.idle_since.take() {
+                    self.probe.idle(now - since);
+                }
+                self.tick = Some(now);
+                self.span = Some(profile::scope("loop"));
+            }
+            Some(Event::Command(_)) => self.span = Some(profile::scope("command")),
+            // Outside was dropped, so stop this thread
+            None => info!("{} thread completed.", self.name),
+        }
+        event
+    }
+}
+
+impl<C> Drop for Controller<C> {
+    fn drop(&mut self) {
+        // A loop that stopped the thread still counts.
+        if let Some(start) = self.tick.take() {
+            self.probe.busy(self.clock.now() - start, self.target());
+        }
+    }
+}
+
+impl<C: Send + 'static, T: Send + 'static> KillableThread<Control<C>, T> {
+    pub fn pause(&self) -> Result<(), SendError<Control<C>>> {
+        self.send(Control::Pause)
+    }
+    pub fn resume(&self) -> Result<(), SendError<Control<C>>> {
+        self.send(Control::Resume)
+    }
+    pub fn step(&self) -> Result<(), SendError<Control<C>>> {
+        self.send(Control::Step)
+    }
+    pub fn set_duration(&self, d: Duration) -> Result<(), SendError<Control<C>>> {
+        self.send(Control::SetDuration(d))
+    }
+    pub fn set_rate(&self, rate: u32) -> Result<(), SendError<Control<C>>> {
+        self.send(Control::SetRate(rate))
+    }
+    pub fn command(&self, c: C) -> Result<(), SendError<Control<C>>> {
+        self.send(Control::Command(c))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use super::*;
+
+    fn wait_for(count: &AtomicUsize, at_least: usize) {
+        while count.load(Ordering::SeqCst) < at_least {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn pause_step_resume() {
+        let loops = Arc::new(AtomicUsize::new(0));
+        let counter = loops.clone();
+        let kt = crate::create_duration_kt!(
+            Duration::from_millis(1),
+            "control",
+            {},
+            {
+                counter.fetch_add(1, Ordering::SeqCst);
+            },
+            {}
+        )
+        .unwrap();
+        wait_for(&loops, 3);
+        kt.pause().unwrap();
+        // Let the thread get around to the pause before sampling.
+        std::thread::sleep(Duration::from_millis(20));
+        let paused_at = loops.load(Ordering::SeqCst);
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(loops.load(Ordering::SeqCst), paused_at);
+        kt.step().unwrap();
+        kt.step().unwrap();
+        wait_for(&loops, paused_at + 2);
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(loops.load(Ordering::SeqCst), paused_at + 2);
+        kt.resume().unwrap();
+        wait_for(&loops, paused_at + 10);
+        // Killing a paused thread still works.
+        kt.pause().unwrap();
+        assert!(kt.finish().unwrap().is_ok());
+    }
+
+    #[test]
+    fn built_threads_keep_time_with_their_clock() {
+        let clock = crate::clock::ManualClock::new();
+        let start = clock.now();
+        let (tx, rx) = std::sync::mpsc::channel();
+        let kt = crate::create_built_kt!(
+            crate::builder::ThreadBuilder::new("control")
+                .pacing(crate::builder::Pacing::Duration(Duration::from_millis(10)))
+                .clock(clock.clone()),
+            {
+                let mut loops = 0;
+            },
+            {
+                loops += 1;
+                if loops == 101 {
+                    break;
+                }
+            },
+            {
+                tx.send(()).unwrap();
+                loops
+            }
+        )
+        .unwrap();
+        // Finishing kills the thread, so let it stop by itself first.
+        rx.recv_timeout(Duration::from_millis(500)).unwrap();
+        assert_eq!(kt.finish().unwrap().unwrap(), 101);
+        assert_eq!(clock.now() - start, Duration::from_secs(1));
+    }
+
+    #[test]
+    fn commands_and_retargeting() {
+        let loops = Arc::new(AtomicUsize::new(0));
+        let counter = loops.clone();
+        let kt = crate::create_rated_kt!(
+            1000,
+            "control",
+            {
+                let mut total = 0;
+            },
+            {
+                counter.fetch_add(1, Ordering::SeqCst);
+            },
+            |n| {
+                total += n;
+            },
+            { total }
+        )
+        .unwrap();
+        kt.command(3).unwrap();
+        kt.command(4).unwrap();
+        // At 200ms per loop, only the loop already under way finishes in the window.
+        kt.set_rate(5).unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+        let slowed_at = loops.load(Ordering::SeqCst);
+        std::thread::sleep(Duration::from_millis(100));
+        assert!(loops.load(Ordering::SeqCst) <= slowed_at + 1);
+        kt.set_duration(Duration::from_millis(1)).unwrap();
+        kt.set_rate(0).unwrap();
+        kt.command(5).unwrap();
+        assert_eq!(kt.finish().unwrap().unwrap(), 12);
+    }
+}
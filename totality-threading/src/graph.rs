@@ -0,0 +1,591 @@
+//! # Graph
+//!
+//! A declarative per-frame task graph.
+//!
+//! Each node names the resources it reads and writes. A node that reads a resource runs after the
+//! node that writes it, nodes can be ordered explicitly on top of that, and everything that does
+//! not depend on each other runs in parallel on a `Pool`. Resources live in a `Resources` table
+//! keyed by name, which persists across frames.
+//!
+//! ```ignore
+//! let mut b = GraphBuilder::new();
+//! b.node("input").writes("events").run(|ctx| poll(&mut ctx.write::<Vec<Event>>("events")));
+//! b.node("sim").reads("events").writes("dynamic").run(|ctx| step(&ctx.read("events"), &mut ctx.write("dynamic")));
+//! b.node("render-prep").reads("dynamic").writes("draws").run(|ctx| prep(&ctx.read("dynamic"), &mut ctx.write("draws")));
+//! b.node("gui").reads("events").writes("gui").after("render-prep").run(|ctx| layout(&ctx.read("events"), &mut ctx.write("gui")));
+//! let graph = b.build()?;
+//! let timings = graph.run(&pool, &resources)?;
+//! ```
+
+use std::{
+    any::Any,
+    collections::HashMap,
+    ops::{Deref, DerefMut},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard,
+    },
+    time::{Duration, Instant},
+};
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+use crate::pool::{Pool, Scope};
+
+type Resource = Box<dyn Any + Send + Sync>;
+type NodeFn = Box<dyn Fn(&Ctx<'_>) + Send + Sync>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GraphError {
+    DuplicateNode(String),
+    /// A node was ordered after a node that does not exist.
+    UnknownNode(String),
+    /// More than one node writes the resource.
+    ConflictingWriters {
+        resource: String,
+        nodes: Vec<String>,
+    },
+    /// The nodes, in order, form a cycle.
+    Cycle(Vec<String>),
+    /// A node uses a resource that is missing from the table passed to `run`.
+    MissingResource(String),
+}
+
+/// Named, type-erased values shared between the nodes of a graph.
+#[derive(Default)]
+pub struct Resources(HashMap<String, RwLock<Resource>>);
+impl Resources {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn insert<T: Any + Send + Sync>(&mut self, name: &str, value: T) {
+        self.0
+            .insert(name.to_string(), RwLock::new(Box::new(value)));
+    }
+    pub fn remove<T: Any + Send + Sync>(&mut self, name: &str) -> Option<T> {
+        let value = self
+            .0
+            .remove(name)?
+            .into_inner()
+            .unwrap_or_else(|e| e.into_inner());
+        value.downcast().ok().map(|b| *b)
+    }
+    pub fn contains(&self, name: &str) -> bool {
+        self.0.contains_key(name)
+    }
+    /// Borrows a resource between frames.
+    pub fn get_mut<T: Any>(&mut self, name: &str) -> Option<&mut T> {
+        self.0
+            .get_mut(name)?
+            .get_mut()
+            .unwrap_or_else(|e| e.into_inner())
+            .downcast_mut()
+    }
+}
+impl std::fmt::Debug for Resources {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_set().entries(self.0.keys()).finish()
+    }
+}
+
+/// A read borrow of a resource.
+pub struct Read<'a, T> {
+    guard: RwLockReadGuard<'a, Resource>,
+    _ty: std::marker::PhantomData<T>,
+}
+impl<'a, T: Any> Deref for Read<'a, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        self.guard
+            .downcast_ref()
+            .expect("Type was checked when borrowing.")
+    }
+}
+/// A write borrow of a resource.
+pub struct Write<'a, T> {
+    guard: RwLockWriteGuard<'a, Resource>,
+    _ty: std::marker::PhantomData<T>,
+}
+impl<'a, T: Any> Deref for Write<'a, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        self.guard
+            .downcast_ref()
+            .expect("Type was checked when borrowing.")
+    }
+}
+impl<'a, T: Any> DerefMut for Write<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.guard
+            .downcast_mut()
+            .expect("Type was checked when borrowing.")
+    }
+}
+
+/// What a node gets to work with while it runs. Only the resources the node declared are
+/// reachable.
+pub struct Ctx<'a> {
+    node: &'a Node,
+    resources: &'a Resources,
+}
+impl<'a> Ctx<'a> {
+    pub fn name(&self) -> &str {
+        &self.node.name
+    }
+    fn resource(&self, name: &str) -> &'a RwLock<Resource> {
+        self.resources
+            .0
+            .get(name)
+            .expect("Resources were checked before the frame started.")
+    }
+    /// Borrows a resource the node reads or writes.
+    ///
+    /// # Panics
+    ///
+    /// If the node did not declare the resource, or it holds a different type.
+    pub fn read<T: Any>(&self, name: &str) -> Read<'a, T> {
+        assert!(
+            self.node
+                .reads
+                .iter()
+                .chain(self.node.writes.iter())
+                .any(|r| r == name),
+            "Node {} did not declare that it reads {}.",
+            self.node.name,
+            name
+        );
+        let guard = self
+            .resource(name)
+            .read()
+            .unwrap_or_else(|e| e.into_inner());
+        assert!(
+            guard.is::<T>(),
+            "Resource {} is not a {}.",
+            name,
+            std::any::type_name::<T>()
+        );
+        Read {
+            guard,
+            _ty: std::marker::PhantomData,
+        }
+    }
+    /// Mutably borrows a resource the node writes.
+    ///
+    /// # Panics
+    ///
+    /// If the node did not declare that it writes the resource, or it holds a different type.
+    pub fn write<T: Any>(&self, name: &str) -> Write<'a, T> {
+        assert!(
+            self.node.writes.iter().any(|r| r == name),
+            "Node {} did not declare that it writes {}.",
+            self.node.name,
+            name
+        );
+        let guard = self
+            .resource(name)
+            .write()
+            .unwrap_or_else(|e| e.into_inner());
+        assert!(
+            guard.is::<T>(),
+            "Resource {} is not a {}.",
+            name,
+            std::any::type_name::<T>()
+        );
+        Write {
+            guard,
+            _ty: std::marker::PhantomData,
+        }
+    }
+}
+
+struct Node {
+    name: String,
+    reads: Vec<String>,
+    writes: Vec<String>,
+    after: Vec<String>,
+    f: NodeFn,
+}
+
+/// Collects nodes before their dependencies are resolved by `build`.
+#[derive(Default)]
+pub struct GraphBuilder {
+    nodes: Vec<Node>,
+}
+impl GraphBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Starts declaring a node. It is only added once `run` is called on the result.
+    pub fn node(&mut self, name: &str) -> NodeBuilder<'_> {
+        NodeBuilder {
+            builder: self,
+            name: name.to_string(),
+            reads: vec![],
+            writes: vec![],
+            after: vec![],
+        }
+    }
+    /// Resolves dependencies, failing on anything that would make the frame ambiguous.
+    pub fn build(self) -> Result<TaskGraph, GraphError> {
+        let mut index = HashMap::new();
+        for (i, node) in self.nodes.iter().enumerate() {
+            if index.insert(node.name.clone(), i).is_some() {
+                return Err(GraphError::DuplicateNode(node.name.clone()));
+            }
+        }
+        let mut writers: HashMap<&str, Vec<usize>> = HashMap::new();
+        for (i, node) in self.nodes.iter().enumerate() {
+            for w in node.writes.iter() {
+                writers.entry(w).or_default().push(i);
+            }
+        }
+        if let Some((resource, ww)) = writers.iter().find(|(_, ww)| ww.len() > 1) {
+            return Err(GraphError::ConflictingWriters {
+                resource: resource.to_string(),
+                nodes: ww.iter().map(|&i| self.nodes[i].name.clone()).collect(),
+            });
+        }
+
+        let mut deps = vec![vec![]; self.nodes.len()];
+        for (i, node) in self.nodes.iter().enumerate() {
+            for r in node.reads.iter() {
+                if let Some(ww) = writers.get(r.as_str()) {
+                    deps[i].extend(ww.iter().copied().filter(|&w| w != i));
+                }
+            }
+            for a in node.after.iter() {
+                deps[i].push(
+                    *index
+                        .get(a)
+                        .ok_or_else(|| GraphError::UnknownNode(a.clone()))?,
+                );
+            }
+            deps[i].sort_unstable();
+            deps[i].dedup();
+        }
+        if let Some(cycle) = find_cycle(&deps) {
+            return Err(GraphError::Cycle(
+                cycle
+                    .into_iter()
+                    .map(|i| self.nodes[i].name.clone())
+                    .collect(),
+            ));
+        }
+        let mut dependents = vec![vec![]; self.nodes.len()];
+        for (i, dd) in deps.iter().enumerate() {
+            for &d in dd.iter() {
+                dependents[d].push(i);
+            }
+        }
+        Ok(TaskGraph {
+            nodes: self.nodes,
+            deps: deps.iter().map(|dd| dd.len()).collect(),
+            dependents,
+        })
+    }
+}
+
+/// Finds a cycle through depth-first search, returned in dependency order.
+fn find_cycle(deps: &[Vec<usize>]) -> Option<Vec<usize>> {
+    #[derive(Clone, Copy, PartialEq)]
+    enum Mark {
+        New,
+        OnPath,
+        Done,
+    }
+    fn visit(
+        deps: &[Vec<usize>],
+        marks: &mut [Mark],
+        path: &mut Vec<usize>,
+        i: usize,
+    ) -> Option<Vec<usize>> {
+        match marks[i] {
+            Mark::Done => return None,
+            Mark::OnPath => {
+                let start = path
+                    .iter()
+                    .position(|&p| p == i)
+                    .expect("Node is on the path.");
+                return Some(path[start..].iter().rev().copied().collect());
+            }
+            Mark::New => {}
+        }
+        marks[i] = Mark::OnPath;
+        path.push(i);
+        for &d in deps[i].iter() {
+            if let Some(cycle) = visit(deps, marks, path, d) {
+                return Some(cycle);
+            }
+        }
+        path.pop();
+        marks[i] = Mark::Done;
+        None
+    }
+    let mut marks = vec![Mark::New; deps.len()];
+    (0..deps.len()).find_map(|i| visit(deps, &mut marks, &mut vec![], i))
+}
+
+pub struct NodeBuilder<'a> {
+    builder: &'a mut GraphBuilder,
+    name: String,
+    reads: Vec<String>,
+    writes: Vec<String>,
+    after: Vec<String>,
+}
+impl<'a> NodeBuilder<'a> {
+    pub fn reads(mut self, resource: &str) -> Self {
+        self.reads.push(resource.to_string());
+        self
+    }
+    pub fn writes(mut self, resource: &str) -> Self {
+        self.writes.push(resource.to_string());
+        self
+    }
+    /// Orders this node after another one, regardless of what they read and write.
+    pub fn after(mut self, node: &str) -> Self {
+        self.after.push(node.to_string());
+        self
+    }
+    pub fn run(self, f: impl Fn(&Ctx<'_>) + Send + Sync + 'static) {
+        self.builder.nodes.push(Node {
+            name: self.name,
+            reads: self.reads,
+            writes: self.writes,
+            after: self.after,
+            f: Box::new(f),
+        });
+    }
+}
+
+/// How long a node took during a frame.
+#[derive(Debug, Clone)]
+pub struct NodeTiming {
+    pub name: String,
+    /// When the node started, relative to the start of the frame.
+    pub start: Duration,
+    pub duration: Duration,
+}
+
+/// Timings of every node that ran during a frame, in the order they finished.
+#[derive(Debug, Clone, Default)]
+pub struct FrameTimings {
+    pub total: Duration,
+    pub nodes: Vec<NodeTiming>,
+}
+impl FrameTimings {
+    pub fn get(&self, name: &str) -> Option<&NodeTiming> {
+        self.nodes.iter().find(|n| n.name == name)
+    }
+}
+
+struct Frame<'a> {
+    graph: &'a TaskGraph,
+    resources: &'a Resources,
+    remaining: Vec<AtomicUsize>,
+    started: Instant,
+    timings: Mutex<Vec<NodeTiming>>,
+}
+
+/// A validated graph, ready to run once per frame.
+pub struct TaskGraph {
+    nodes: Vec<Node>,
+    /// Number of dependencies per node.
+    deps: Vec<usize>,
+    dependents: Vec<Vec<usize>>,
+}
+impl TaskGraph {
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+    /// Runs every node once, each as soon as its dependencies have finished.
+    ///
+    /// # Panics
+    ///
+    /// If a node panics. Nodes that depend on it are skipped, and the panic is resumed once
+    /// everything else has finished.
+    pub fn run(&self, pool: &Pool, resources: &Resources) -> Result<FrameTimings, GraphError> {
+        if let Some(missing) = self
+            .nodes
+            .iter()
+            .flat_map(|n| n.reads.iter().chain(n.writes.iter()))
+            .find(|r| !resources.contains(r))
+        {
+            return Err(GraphError::MissingResource(missing.clone()));
+        }
+        let frame = Frame {
+            graph: self,
+            resources,
+            remaining: self.deps.iter().map(|&d| AtomicUsize::new(d)).collect(),
+            started: Instant::now(),
+            timings: Mutex::new(Vec::with_capacity(self.nodes.len())),
+        };
+        pool.scope(|s| {
+            for i in (0..self.nodes.len()).filter(|&i| self.deps[i] == 0) {
+                spawn_node(s, &frame, i);
+            }
+        });
+        Ok(FrameTimings {
+            total: frame.started.elapsed(),
+            nodes: frame
+                .timings
+                .into_inner()
+                .unwrap_or_else(|e| e.into_inner()),
+        })
+    }
+}
+impl std::fmt::Debug for TaskGraph {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list()
+            .entries(self.nodes.iter().map(|n| &n.name))
+            .finish()
+    }
+}
+
+fn spawn_node<'env>(s: &Scope<'env>, frame: &'env Frame<'env>, i: usize) {
+    s.spawn(move |s| {
+        let node = &frame.graph.nodes[i];
+        let start = Instant::now();
+        trace!("Running node {}.", node.name);
+        (node.f)(&Ctx {
+            node,
+            resources: frame.resources,
+        });
+        let timing = NodeTiming {
+            name: node.name.clone(),
+            start: start - frame.started,
+            duration: start.elapsed(),
+        };
+        frame
+            .timings
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(timing);
+        for &d in frame.graph.dependents[i].iter() {
+            if frame.remaining[d].fetch_sub(1, Ordering::AcqRel) == 1 {
+                spawn_node(s, frame, d);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn input_sim_render_gui_frame() {
+        let pool = Pool::new("test", 2).unwrap();
+        let mut b = GraphBuilder::new();
+        b.node("input").writes("events").run(|ctx| {
+            ctx.write::<Vec<u32>>("events").push(1);
+        });
+        b.node("sim").reads("events").writes("dynamic").run(|ctx| {
+            let events = ctx.read::<Vec<u32>>("events");
+            *ctx.write::<u32>("dynamic") += events.iter().sum::<u32>();
+        });
+        b.node("render-prep")
+            .reads("dynamic")
+            .writes("draws")
+            .run(|ctx| {
+                let dynamic = *ctx.read::<u32>("dynamic");
+                ctx.write::<Vec<u32>>("draws").push(dynamic);
+            });
+        b.node("gui")
+            .reads("events")
+            .writes("gui")
+            .after("render-prep")
+            .run(|ctx| {
+                *ctx.write::<usize>("gui") = ctx.read::<Vec<u32>>("events").len();
+            });
+        let graph = b.build().unwrap();
+
+        let mut res = Resources::new();
+        res.insert("events", Vec::<u32>::new());
+        res.insert("dynamic", 0u32);
+        res.insert("draws", Vec::<u32>::new());
+        res.insert("gui", 0usize);
+        for _ in 0..3 {
+            let timings = graph.run(&pool, &res).unwrap();
+            assert_eq!(timings.nodes.len(), 4);
+            let end = |n: &str| timings.get(n).map(|t| t.start + t.duration).unwrap();
+            let start = |n: &str| timings.get(n).map(|t| t.start).unwrap();
+            assert!(start("sim") >= end("input"));
+            assert!(start("render-prep") >= end("sim"));
+            assert!(start("gui") >= end("render-prep"));
+        }
+        assert_eq!(res.get_mut::<Vec<u32>>("draws"), Some(&mut vec![1, 3, 6]));
+        assert_eq!(res.get_mut::<usize>("gui"), Some(&mut 3));
+    }
+
+    #[test]
+    fn independent_nodes_run_in_parallel() {
+        let pool = Pool::new("test", 2).unwrap();
+        let active = std::sync::Arc::new(AtomicUsize::new(0));
+        let peak = std::sync::Arc::new(AtomicUsize::new(0));
+        let mut b = GraphBuilder::new();
+        for name in ["a", "b"] {
+            let (active, peak) = (active.clone(), peak.clone());
+            b.node(name).run(move |_| {
+                let now = active.fetch_add(1, Ordering::SeqCst) + 1;
+                peak.fetch_max(now, Ordering::SeqCst);
+                std::thread::sleep(Duration::from_millis(50));
+                active.fetch_sub(1, Ordering::SeqCst);
+            });
+        }
+        let timings = b.build().unwrap().run(&pool, &Resources::new()).unwrap();
+        assert_eq!(peak.load(Ordering::SeqCst), 2);
+        assert!(timings
+            .nodes
+            .iter()
+            .all(|n| n.duration >= Duration::from_millis(50)));
+    }
+
+    #[test]
+    fn cycles_are_reported() {
+        let mut b = GraphBuilder::new();
+        b.node("a").reads("z").writes("x").run(|_| {});
+        b.node("b").reads("x").writes("y").run(|_| {});
+        b.node("c").reads("y").writes("z").run(|_| {});
+        b.node("d").reads("x").run(|_| {});
+        match b.build() {
+            Err(GraphError::Cycle(cycle)) => {
+                assert_eq!(cycle.len(), 3);
+                for n in ["a", "b", "c"] {
+                    assert!(cycle.iter().any(|c| c == n));
+                }
+            }
+            other => panic!("Expected a cycle, got {:?}.", other),
+        }
+    }
+
+    #[test]
+    fn ambiguous_graphs_are_rejected() {
+        let mut b = GraphBuilder::new();
+        b.node("a").writes("x").run(|_| {});
+        b.node("b").writes("x").run(|_| {});
+        assert!(matches!(
+            b.build(),
+            Err(GraphError::ConflictingWriters { .. })
+        ));
+
+        let mut b = GraphBuilder::new();
+        b.node("a").after("nope").run(|_| {});
+        assert_eq!(
+            b.build().err(),
+            Some(GraphError::UnknownNode("nope".into()))
+        );
+
+        let mut b = GraphBuilder::new();
+        b.node("a").reads("x").run(|_| {});
+        let graph = b.build().unwrap();
+        let pool = Pool::new("test", 1).unwrap();
+        assert_eq!(
+            graph.run(&pool, &Resources::new()).err(),
+            Some(GraphError::MissingResource("x".into()))
+        );
+    }
+}
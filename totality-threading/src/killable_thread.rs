@@ -3,12 +3,16 @@
 //! A KillableThread is a thread with a built-in interruption mechanism/flag.
 
 use std::{
+    any::Any,
     option::Option,
     result::Result,
     sync::mpsc::{SendError, Sender},
     thread::JoinHandle,
 };
 
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
 /// A `KillableThread`. Effectively a `JoinHandle` to the thread started by when creating
 /// `KillableThread`.
 pub struct KillableThread<P: Send + 'static, T: Send + 'static> {
@@ -62,12 +66,33 @@ impl<P: Send + 'static, T: Send + 'static> KillableThread<P, T> {
         drop(self.kill_mechanism.take());
         self.handle.take().map(|h| h.join())
     }
+    /// Whether the thread has stopped running, either by returning or by panicking.
+    pub fn is_finished(&self) -> bool {
+        self.handle.as_ref().is_none_or(|h| h.is_finished())
+    }
+    pub fn name(&self) -> Option<&str> {
+        self.handle.as_ref().and_then(|h| h.thread().name())
+    }
 }
 /// Alias for the return of `finish` in `KillableThread`.
 pub type FinishResult<T> = Option<std::thread::Result<T>>;
 impl<P: Send + 'static, T: Send + 'static> Drop for KillableThread<P, T> {
     fn drop(&mut self) {
         drop(self.kill_mechanism.take());
-        drop(self.handle.take().map(|h| h.join()));
+        if let Some(h) = self.handle.take() {
+            let name = h.thread().name().unwrap_or("Unnamed").to_string();
+            if let Err(payload) = h.join() {
+                error!("{} thread panicked: {}", name, panic_message(&*payload));
+            }
+        }
     }
 }
+
+/// The message a thread panicked with, if it was a string.
+pub fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("<non-string payload>")
+}
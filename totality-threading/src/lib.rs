@@ -2,13 +2,35 @@
 //!
 //! Easily create heavy threads operating under various frequencies and conditions.
 //!
-//! Provides a variety of macros that help out with the thread creation. Also provides
-//! trace-level logging statements.
+//! Threads are put together from closures through `builder`, and a variety of macros help out
+//! with the thread creation when that gets in the way. Also provides trace-level logging
+//! statements. The threads they create can be paused, retargeted and sent commands, see
+//! `control`.
+//!
+//! Long-running threads can be handed to a `supervisor`, which restarts them when they panic,
+//! and report how their loops keep up to `telemetry`. To see what every thread was doing when,
+//! record a trace with `profile`.
+//!
+//! For short bursts of parallel work rather than long-running loops, see `pool`. To schedule a
+//! frame's worth of dependent work on one, see `graph`.
 
+pub mod builder;
+pub mod clock;
+pub mod control;
+pub mod graph;
 pub mod killable_thread;
+pub mod pool;
+pub mod profile;
+pub mod supervisor;
+pub mod telemetry;
+pub mod timestep;
 
 /// A macro to create a simple KillableThread.
 ///
+/// A thin wrapper around `builder::ThreadBuilder`, for when sharing state between closures is more
+/// trouble than sharing variables between blocks. The thread is controlled through
+/// `control::Control` messages, see `control` for what they do.
+///
 /// # Arguments
 ///
 /// * `type` The fork's return type.
@@ -16,6 +38,9 @@ pub mod killable_thread;
 /// * `head` The first part of the thread's execution. Could be used for setup. Variables declared
 /// here are accessible to `body` and `head`.
 /// * `body` The looped part of the body's execution.
+/// * `cmd` Optional. The identifier of a received command, followed by the block that handles it.
+/// Runs in between loops, and can access variables declared in `head`. Leaving this out ignores
+/// commands.
 /// * `tail` The last part of the thread's execution. Could be used for cleanup. Should return
 /// value.
 ///
@@ -25,43 +50,16 @@ pub mod killable_thread;
 /// interrupted-ness of the thread.
 #[macro_export]
 macro_rules! create_kt {
+    ( $name:literal, {$($head:tt)*}, {$($body:tt)*}, | $cmd:ident | {$($on_cmd:tt)*}, {$($tail:tt)*} ) => {
+        $crate::__controlled_kt!(
+            $crate::builder::ThreadBuilder::new($name).pacing($crate::builder::Pacing::Free), {$($head)*}, {$($body)*}, | $cmd | {$($on_cmd)*}, {$($tail)*}
+        )
+    };
     ( $name:literal, {$($head:tt)*}, {$($body:tt)*}, {$($tail:tt)*} ) => {
-        {
-            use log::*;
-            use ::std::{
-                time::*,
-                sync::mpsc::*,
-            };
-            let (tx, rx) = std::sync::mpsc::channel();
-            $crate::killable_thread::KillableThread::new(tx, $name.to_string(), move || {
-                info!("Starting {} thread.", $name);
-                $($head)*;
-                loop {
-                    let curr_start_time = Instant::now();
-                    $($body)*;
-                    trace!("Checking for {} thread's death.", $name);
-                    match rx.try_recv() {
-                        // Cannot handle messages
-                        Ok(c) => panic!("Unexpected input {:?} into thread control channel.", c),
-                        // No input means continue
-                        Err(TryRecvError::Empty) => (),
-                        // Outside was dropped, so stop this thread
-                        Err(TryRecvError::Disconnected) => {
-                            info!("{} thread completed", $name);
-                            break
-                        },
-                    };
-                    let busy_time = Instant::now() - curr_start_time;
-                    trace!("{} thread spent {:?} busy in loop.", $name, busy_time);
-                }
-                let ret = {
-                    $($tail)*
-                };
-                trace!("{} thread winding down.", $name);
-                ret
-            })
-        }
-    }
+        $crate::__controlled_kt!(
+            $crate::builder::ThreadBuilder::new($name).pacing($crate::builder::Pacing::Free), {$($head)*}, {$($body)*}, | cmd | { let () = cmd; }, {$($tail)*}
+        )
+    };
 }
 
 /// A macro to create a thread that aims to finish the loop with exactly a certain duration.
@@ -71,58 +69,26 @@ macro_rules! create_kt {
 /// # Arguments
 ///
 /// * `type` The fork's return type.
-/// * `dura` Get the target Duration per loop.
+/// * `dura` The target Duration per loop.
 /// * `name` A string literal of the name of the thread. Used for logging.
 /// * `head` The first part of the thread's execution. Could be used for setup. Variables declared
 /// here are accessible to `body` and `head`.
 /// * `body` The looped part of the body's execution.
+/// * `cmd` Optional. The identifier of a received command, followed by the block that handles it.
 /// * `tail` The last part of the thread's execution. Could be used for cleanup. Should return
 /// value.
 #[macro_export]
 macro_rules! create_duration_kt {
+    ( $dura:expr, $name:literal, {$($head:tt)*}, {$($body:tt)*}, | $cmd:ident | {$($on_cmd:tt)*}, {$($tail:tt)*} ) => {
+        $crate::__controlled_kt!(
+            $crate::builder::ThreadBuilder::new($name).pacing($crate::builder::Pacing::Duration($dura)), {$($head)*}, {$($body)*}, | $cmd | {$($on_cmd)*}, {$($tail)*}
+        )
+    };
     ( $dura:expr, $name:literal, {$($head:tt)*}, {$($body:tt)*}, {$($tail:tt)*} ) => {
-        {
-            use log::*;
-            use ::std::{
-                time::*,
-                sync::mpsc::*,
-            };
-            use $crate::killable_thread::KillableThread;
-            let (tx, rx) = std::sync::mpsc::channel();
-            $crate::killable_thread::KillableThread::new(tx, $name.to_string(), move || {
-                info!("Starting {} thread.", $name);
-                $($head)*;
-                let target = $dura;
-                loop {
-                    let curr_start_time = Instant::now();
-                    $($body)*;
-                    trace!("Checking for {} thread's death.", $name);
-                    match rx.try_recv() {
-                        // Cannot handle messages
-                        Ok(c) => panic!("Unexpected input {:?} into thread control channel.", c),
-                        // No input means continue
-                        Err(TryRecvError::Empty) => (),
-                        // Outside was dropped, so stop this thread
-                        Err(TryRecvError::Disconnected) => {
-                            info!("{} thread completed.", $name);
-                            break
-                        },
-                    };
-                    let busy_time = Instant::now() - curr_start_time;
-                    if target > busy_time {
-                        std::thread::sleep(target - busy_time);
-                    }
-                    let total_time = Instant::now() - curr_start_time;
-                    trace!("{} thread spent {:?} busy and {:?} total in loop.", $name, busy_time, total_time);
-                }
-                let ret = {
-                    $($tail)*
-                };
-                trace!("{} thread winding down.", $name);
-                ret
-            })
-        }
-    }
+        $crate::__controlled_kt!(
+            $crate::builder::ThreadBuilder::new($name).pacing($crate::builder::Pacing::Duration($dura)), {$($head)*}, {$($body)*}, | cmd | { let () = cmd; }, {$($tail)*}
+        )
+    };
 }
 
 /// A macro to create a thread that aims to run the loop at a target rate (aka FPS).
@@ -137,60 +103,30 @@ macro_rules! create_duration_kt {
 /// * `head` The first part of the thread's execution. Could be used for setup. Variables declared
 /// here are accessible to `body` and `head`.
 /// * `body` The looped part of the body's execution.
+/// * `cmd` Optional. The identifier of a received command, followed by the block that handles it.
 /// * `tail` The last part of the thread's execution. Could be used for cleanup. Should return
 /// value.
 #[macro_export]
 macro_rules! create_rated_kt {
+    ( $rate:expr, $name:literal, {$($head:tt)*}, {$($body:tt)*}, | $cmd:ident | {$($on_cmd:tt)*}, {$($tail:tt)*} ) => {
+        $crate::__controlled_kt!(
+            $crate::builder::ThreadBuilder::new($name).pacing($crate::builder::Pacing::Rate($rate)), {$($head)*}, {$($body)*}, | $cmd | {$($on_cmd)*}, {$($tail)*}
+        )
+    };
     ( $rate:expr, $name:literal, {$($head:tt)*}, {$($body:tt)*}, {$($tail:tt)*} ) => {
-        {
-            use log::*;
-            use ::std::{
-                time::*,
-                sync::mpsc::*,
-            };
-            use $crate::killable_thread::KillableThread;
-            let (tx, rx) = std::sync::mpsc::channel();
-            $crate::killable_thread::KillableThread::new(tx, $name.to_string(), move || {
-                info!("Starting {} thread.", $name);
-                $($head)*;
-                let target = Duration::from_secs(1).checked_div($rate).expect("A constant is taken to be equal to 0...");
-                loop {
-                    let curr_start_time = Instant::now();
-                    $($body)*;
-                    trace!("Checking for {} thread's death.", $name);
-                    match rx.try_recv() {
-                        // Cannot handle messages
-                        Ok(c) => panic!("Unexpected input {:?} into thread control channel.", c),
-                        // No input means continue
-                        Err(TryRecvError::Empty) => (),
-                        // Outside was dropped, so stop this thread
-                        Err(TryRecvError::Disconnected) => {
-                            info!("{} thread completed.", $name);
-                            break
-                        },
-                    };
-                    let busy_time = Instant::now() - curr_start_time;
-                    if target > busy_time {
-                        std::thread::sleep(target - busy_time);
-                    }
-                    let total_time = Instant::now() - curr_start_time;
-                    trace!("{} thread spent {:?} busy and {:?} total in loop.", $name, busy_time, total_time);
-                }
-                let ret = {
-                    $($tail)*
-                };
-                trace!("{} thread winding down.", $name);
-                ret
-            })
-        }
-    }
+        $crate::__controlled_kt!(
+            $crate::builder::ThreadBuilder::new($name).pacing($crate::builder::Pacing::Rate($rate)), {$($head)*}, {$($body)*}, | cmd | { let () = cmd; }, {$($tail)*}
+        )
+    };
 }
 
 /// A macro to create a thread that runs for every message sent.
 ///
+/// Messages are sent as commands, through `KillableThread::command`.
+///
 /// # Arguments
 ///
-/// * `send_type` The fork's Sender's type parameter.
+/// * `send_type` The fork's command type.
 /// * `type` The fork's return type.
 /// * `name` A string literal of the name of the thread. Used for logging.
 /// * `v` The identifier of the input value that will be used in the body. Leaving this out
@@ -203,75 +139,80 @@ macro_rules! create_rated_kt {
 #[macro_export]
 macro_rules! create_waiting_kt {
     ( $name:literal, {$($head:tt)*}, | $v:ident | {$($body:tt)*}, {$($tail:tt)*} ) => {
-        {
-            use log::*;
-            use ::std::{
-                time::*,
-                sync::mpsc::*,
-            };
-            use $crate::killable_thread::KillableThread;
-            let (tx, rx) = std::sync::mpsc::channel();
-            $crate::killable_thread::KillableThread::new(tx, $name.to_string(), move || {
-                info!("Starting {} thread.", $name);
-                $($head)*;
-                loop {
-                    let curr_start_time = Instant::now();
-                    trace!("Checking for {} thread's death.", $name);
-                    match rx.recv() {
-                        // Cannot handle messages
-                        Ok($v) => {
-                            $($body)*;
-                        },
-                        // Outside was dropped, so stop this thread
-                        Err(RecvError) => {
-                            info!("{} thread completed.", $name);
-                            break
-                        },
-                    };
-                    let total_time = Instant::now() - curr_start_time;
-                    trace!("{} thread spent {:?} total in loop.", $name, total_time);
-                }
-                let ret = { $($tail)* };
-                trace!("{} thread winding down.", $name);
-                ret
-            })
-        }
+        $crate::__controlled_kt!(
+            $crate::builder::ThreadBuilder::new($name).pacing($crate::builder::Pacing::Messages), {$($head)*}, {}, | $v | {$($body)*}, {$($tail)*}
+        )
     };
     ( $name:literal, {$($head:tt)*}, {$($body:tt)*}, {$($tail:tt)*} ) => {
+        $crate::__controlled_kt!(
+            $crate::builder::ThreadBuilder::new($name).pacing($crate::builder::Pacing::Messages), {$($head)*}, {}, | v | { let _ = v; $($body)* }, {$($tail)*}
+        )
+    };
+}
+
+/// A macro to create a thread from an already configured `builder::ThreadBuilder`.
+///
+/// Use this over the other `create_*_kt` macros to set anything they don't, such as the clock the
+/// thread keeps time with. Refer to `create_kt` for more details.
+///
+/// # Arguments
+///
+/// * `builder` The `ThreadBuilder` to spawn the thread with, which also names and paces it.
+/// * `head` The first part of the thread's execution. Variables declared here are in scope after.
+/// * `body` The looped part of the body's execution.
+/// * `cmd` Optional. The identifier of a received command, followed by the block that handles it.
+/// * `tail` The last part of the thread's execution. Should return value.
+#[macro_export]
+macro_rules! create_built_kt {
+    ( $builder:expr, {$($head:tt)*}, {$($body:tt)*}, | $cmd:ident | {$($on_cmd:tt)*}, {$($tail:tt)*} ) => {
+        $crate::__controlled_kt!(
+            $builder, {$($head)*}, {$($body)*}, | $cmd | {$($on_cmd)*}, {$($tail)*}
+        )
+    };
+    ( $builder:expr, {$($head:tt)*}, {$($body:tt)*}, {$($tail:tt)*} ) => {
+        $crate::__controlled_kt!(
+            $builder, {$($head)*}, {$($body)*}, | cmd | { let () = cmd; }, {$($tail)*}
+        )
+    };
+}
+
+/// The loop shared by the `create_*_kt` macros.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __controlled_kt {
+    ( $builder:expr, {$($head:tt)*}, {$($body:tt)*}, | $cmd:ident | {$($on_cmd:tt)*}, {$($tail:tt)*} ) => {
         {
+            #[allow(unused_imports)]
             use log::*;
+            #[allow(unused_imports)]
             use ::std::{
                 time::*,
                 sync::mpsc::*,
             };
-            use $crate::killable_thread::KillableThread;
-            let (tx, rx) = std::sync::mpsc::channel();
-            $crate::killable_thread::KillableThread::new(tx, $name.to_string(), move || {
-                info!("Starting {} thread.", $name);
-                $($head)*;
-                loop {
-                    let curr_start_time = Instant::now();
-                    trace!("Checking for {} thread's death.", $name);
-                    match rx.recv() {
-                        // Cannot handle messages
-                        Ok(_) => {
-                            $($body)*;
-                        },
-                        // Outside was dropped, so stop this thread
-                        Err(RecvError) => {
-                            info!("{} thread completed.", $name);
-                            break
-                        },
-                    };
-                    let total_time = Instant::now() - curr_start_time;
-                    trace!("{} thread spent {:?} total in loop.", $name, total_time);
-                }
-                let ret = { $($tail)* };
-                trace!("{} thread winding down.", $name);
+            use $crate::control::Event;
+            $crate::builder::ThreadBuilder::spawn_with($builder, move |ctl| {
+                // `head` and `body` may or may not end in a semicolon.
+                #[allow(redundant_semicolons)]
+                let ret = {
+                    $($head)*;
+                    for event in ctl {
+                        match event {
+                            Event::Tick => {
+                                $($body)*;
+                            },
+                            Event::Command($cmd) => {
+                                $($on_cmd)*
+                            },
+                        }
+                    }
+                    {
+                        $($tail)*
+                    }
+                };
                 ret
             })
         }
-    };
+    }
 }
 
 #[test]
@@ -318,15 +259,16 @@ pub fn run_test() {
                     Err(e) => panic!("Not like this!!!! We've failed due to {:?}!", e),
                 }
             }
-            match rx.try_recv() {
+            // The thread drops its end right after the last send, so give it a moment to get there.
+            match rx.recv_timeout(std::time::Duration::from_secs(1)) {
                 Ok(v) => panic!(
                     "We received a value ({:?}) after it should've been dropped...",
                     v
                 ),
-                Err(std::sync::mpsc::TryRecvError::Empty) => {
+                Err(std::sync::mpsc::RecvTimeoutError::Timeout) => {
                     panic!("The channel should've dropped by now!")
                 }
-                Err(std::sync::mpsc::TryRecvError::Disconnected) => {
+                Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => {
                     info!("Successfully disconnected from the thread.")
                 }
             }
@@ -0,0 +1,448 @@
+//! # Pool
+//!
+//! A work-stealing job system for short, parallel bursts of work, as opposed to the long-running
+//! looping threads created by the macros.
+//!
+//! Every worker is a `KillableThread` with its own deque. Jobs spawned from a worker go onto its own
+//! deque, jobs spawned from outside go onto a shared queue per priority, and idle workers steal from
+//! each other. Threads waiting on a scope or a `join` run other jobs in the meantime, so nesting
+//! never starves the pool.
+//!
+//! Dropping the pool stops it gracefully: jobs already queued still run, then the workers are
+//! joined like any other `KillableThread`.
+
+use std::{
+    any::Any,
+    cell::RefCell,
+    marker::PhantomData,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc::{self, TryRecvError},
+        Arc, Condvar, Mutex,
+    },
+    time::Duration,
+};
+
+use cb::deque::{Injector, Steal, Stealer, Worker};
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+use crate::killable_thread::KillableThread;
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// How soon a job should run relative to others.
+///
+/// High priority jobs are picked up before anything else, including a worker's own nested jobs.
+/// Low priority jobs are only picked up once there is nothing else left to run or steal.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    High,
+    #[default]
+    Normal,
+    Low,
+}
+
+/// How long an idle thread sleeps before checking again on its own. Wakeups normally come from new
+/// jobs and finished scopes; this only bounds how late a worker notices that it was killed.
+const IDLE_TIMEOUT: Duration = Duration::from_millis(50);
+
+struct Shared {
+    injectors: [Injector<Job>; 3],
+    stealers: Vec<Stealer<Job>>,
+    sleep: Mutex<()>,
+    wake: Condvar,
+    stopping: AtomicBool,
+    panics: AtomicUsize,
+}
+impl Shared {
+    fn id(self: &Arc<Self>) -> usize {
+        Arc::as_ptr(self) as usize
+    }
+    fn notify(&self) {
+        let _guard = self.sleep.lock().unwrap_or_else(|e| e.into_inner());
+        self.wake.notify_all();
+    }
+    fn push(self: &Arc<Self>, priority: Priority, job: Job) {
+        let job = match priority {
+            Priority::Normal => LOCAL.with(|local| match &*local.borrow() {
+                Some(local) if local.pool == self.id() => {
+                    local.worker.push(job);
+                    None
+                }
+                _ => Some(job),
+            }),
+            _ => Some(job),
+        };
+        if let Some(job) = job {
+            self.injectors[priority as usize].push(job);
+        }
+        self.notify();
+    }
+    fn has_work(&self) -> bool {
+        self.injectors.iter().any(|i| !i.is_empty()) || self.stealers.iter().any(|s| !s.is_empty())
+    }
+    /// Finds the next job to run, in order of priority.
+    fn find(&self, local: Option<&Worker<Job>>) -> Option<Job> {
+        let from_injector = |injector: &Injector<Job>| {
+            retry(|| match local {
+                Some(local) => injector.steal_batch_and_pop(local),
+                None => injector.steal(),
+            })
+        };
+        from_injector(&self.injectors[Priority::High as usize])
+            .or_else(|| local.and_then(|l| l.pop()))
+            .or_else(|| from_injector(&self.injectors[Priority::Normal as usize]))
+            .or_else(|| self.stealers.iter().find_map(|s| retry(|| s.steal())))
+            .or_else(|| from_injector(&self.injectors[Priority::Low as usize]))
+    }
+    /// Runs a single job if there is one, using the calling worker's deque if it belongs to us.
+    fn run_one(self: &Arc<Self>) -> bool {
+        let job = LOCAL.with(|local| match &*local.borrow() {
+            Some(local) if local.pool == self.id() => self.find(Some(&local.worker)),
+            _ => self.find(None),
+        });
+        match job {
+            Some(job) => {
+                job();
+                true
+            }
+            None => false,
+        }
+    }
+    /// Sleeps until there might be work or `done` might have changed.
+    fn idle(&self, done: impl Fn() -> bool) {
+        let guard = self.sleep.lock().unwrap_or_else(|e| e.into_inner());
+        if !done() && !self.has_work() {
+            drop(self.wake.wait_timeout(guard, IDLE_TIMEOUT));
+        }
+    }
+}
+
+fn retry(mut steal: impl FnMut() -> Steal<Job>) -> Option<Job> {
+    loop {
+        match steal() {
+            Steal::Success(job) => return Some(job),
+            Steal::Empty => return None,
+            Steal::Retry => {}
+        }
+    }
+}
+
+struct Local {
+    pool: usize,
+    worker: Worker<Job>,
+}
+thread_local! {
+    static LOCAL: RefCell<Option<Local>> = const { RefCell::new(None) };
+}
+
+/// A work-stealing thread pool.
+pub struct Pool {
+    shared: Arc<Shared>,
+    workers: Vec<KillableThread<(), ()>>,
+}
+impl Pool {
+    /// Starts a pool with `threads` workers, named after `name`.
+    pub fn new(name: &str, threads: usize) -> Result<Pool, std::io::Error> {
+        let threads = threads.max(1);
+        let locals = (0..threads).map(|_| Worker::new_lifo()).collect::<Vec<_>>();
+        let shared = Arc::new(Shared {
+            injectors: [Injector::new(), Injector::new(), Injector::new()],
+            stealers: locals.iter().map(|w| w.stealer()).collect(),
+            sleep: Mutex::new(()),
+            wake: Condvar::new(),
+            stopping: AtomicBool::new(false),
+            panics: AtomicUsize::new(0),
+        });
+        let mut workers = Vec::with_capacity(threads);
+        for (i, worker) in locals.into_iter().enumerate() {
+            let (tx, rx) = mpsc::channel::<()>();
+            let shared = shared.clone();
+            let name = format!("{} worker {}", name, i);
+            workers.push(KillableThread::new(tx, name.clone(), move || {
+                info!("Starting {} thread.", name);
+                LOCAL.with(|local| {
+                    *local.borrow_mut() = Some(Local {
+                        pool: shared.id(),
+                        worker,
+                    })
+                });
+                loop {
+                    if shared.run_one() {
+                        continue;
+                    }
+                    let killed = matches!(rx.try_recv(), Err(TryRecvError::Disconnected));
+                    if killed || shared.stopping.load(Ordering::Acquire) {
+                        // Leftovers may have been pushed between the last search and now.
+                        while shared.run_one() {}
+                        break;
+                    }
+                    shared.idle(|| shared.stopping.load(Ordering::Acquire));
+                }
+                LOCAL.with(|local| local.borrow_mut().take());
+                info!("{} thread completed.", name);
+            })?);
+        }
+        Ok(Pool { shared, workers })
+    }
+    pub fn threads(&self) -> usize {
+        self.workers.len()
+    }
+    /// Number of detached jobs that have panicked so far.
+    pub fn panics(&self) -> usize {
+        self.shared.panics.load(Ordering::Relaxed)
+    }
+    /// Runs `f` on the pool without waiting for it. A panic inside it is logged and counted, but
+    /// otherwise swallowed.
+    pub fn spawn<F: FnOnce() + Send + 'static>(&self, priority: Priority, f: F) {
+        let shared = Arc::downgrade(&self.shared);
+        self.shared.push(
+            priority,
+            Box::new(move || {
+                if panic::catch_unwind(AssertUnwindSafe(f)).is_err() {
+                    error!("A detached job panicked.");
+                    if let Some(shared) = shared.upgrade() {
+                        shared.panics.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+            }),
+        );
+    }
+    /// Creates a scope in which jobs may borrow from the caller's stack. Returns once `f` and every
+    /// job spawned in the scope have finished, running jobs on the calling thread in the meantime.
+    ///
+    /// If `f` or any of the jobs panic, the panic is resumed here once everything has finished.
+    pub fn scope<'env, R>(&self, f: impl FnOnce(&Scope<'env>) -> R) -> R {
+        let scope = Scope {
+            shared: self.shared.clone(),
+            state: Arc::new(ScopeState {
+                pending: AtomicUsize::new(0),
+                panic: Mutex::new(None),
+            }),
+            _env: PhantomData,
+        };
+        let res = panic::catch_unwind(AssertUnwindSafe(|| f(&scope)));
+        scope.wait();
+        if let Some(payload) = scope
+            .state
+            .panic
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .take()
+        {
+            panic::resume_unwind(payload);
+        }
+        res.unwrap_or_else(|payload| panic::resume_unwind(payload))
+    }
+    /// Runs `a` and `b` in parallel and returns both results.
+    pub fn join<A, B, RA, RB>(&self, a: A, b: B) -> (RA, RB)
+    where
+        A: FnOnce() -> RA + Send,
+        B: FnOnce() -> RB + Send,
+        RA: Send,
+        RB: Send,
+    {
+        let mut rb = None;
+        let ra = self.scope(|s| {
+            s.spawn(|_| rb = Some(b()));
+            a()
+        });
+        (
+            ra,
+            rb.expect("Scope only returns once every job has finished."),
+        )
+    }
+    fn grain(&self, len: usize) -> usize {
+        (len / (self.threads() * 4)).max(1)
+    }
+    /// Calls `f` on every item and its index, split into chunks across the pool.
+    pub fn parallel_for<T: Sync>(&self, items: &[T], f: impl Fn(usize, &T) + Sync) {
+        let grain = self.grain(items.len());
+        let f = &f;
+        self.scope(|s| {
+            for (i, chunk) in items.chunks(grain).enumerate() {
+                s.spawn(move |_| {
+                    for (j, item) in chunk.iter().enumerate() {
+                        f(i * grain + j, item);
+                    }
+                });
+            }
+        });
+    }
+    /// Like `parallel_for`, but with mutable access to the items.
+    pub fn parallel_for_mut<T: Send>(&self, items: &mut [T], f: impl Fn(usize, &mut T) + Sync) {
+        let grain = self.grain(items.len());
+        let f = &f;
+        self.scope(|s| {
+            for (i, chunk) in items.chunks_mut(grain).enumerate() {
+                s.spawn(move |_| {
+                    for (j, item) in chunk.iter_mut().enumerate() {
+                        f(i * grain + j, item);
+                    }
+                });
+            }
+        });
+    }
+}
+impl Drop for Pool {
+    fn drop(&mut self) {
+        self.shared.stopping.store(true, Ordering::Release);
+        self.shared.notify();
+        // Each worker's drop cuts its kill channel and joins it.
+        self.workers.clear();
+    }
+}
+impl std::fmt::Debug for Pool {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Pool")
+            .field("threads", &self.threads())
+            .finish()
+    }
+}
+
+struct ScopeState {
+    pending: AtomicUsize,
+    panic: Mutex<Option<Box<dyn Any + Send>>>,
+}
+
+/// Handle for spawning jobs that may borrow anything outliving `'env`.
+pub struct Scope<'env> {
+    shared: Arc<Shared>,
+    state: Arc<ScopeState>,
+    _env: PhantomData<&'env mut &'env ()>,
+}
+impl<'env> Scope<'env> {
+    pub fn spawn<F: FnOnce(&Scope<'env>) + Send + 'env>(&self, f: F) {
+        self.spawn_with(Priority::Normal, f);
+    }
+    pub fn spawn_with<F: FnOnce(&Scope<'env>) + Send + 'env>(&self, priority: Priority, f: F) {
+        self.state.pending.fetch_add(1, Ordering::Relaxed);
+        let scope = Scope {
+            shared: self.shared.clone(),
+            state: self.state.clone(),
+            _env: PhantomData,
+        };
+        let job: Box<dyn FnOnce() + Send + 'env> = Box::new(move || {
+            if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(|| f(&scope))) {
+                scope
+                    .state
+                    .panic
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .get_or_insert(payload);
+            }
+            scope.state.pending.fetch_sub(1, Ordering::AcqRel);
+            scope.shared.notify();
+        });
+        // The scope does not return before this job has run, so nothing it borrows can be gone by
+        // then.
+        let job = unsafe { std::mem::transmute::<Box<dyn FnOnce() + Send + 'env>, Job>(job) };
+        self.shared.push(priority, job);
+    }
+    fn wait(&self) {
+        let done = || self.state.pending.load(Ordering::Acquire) == 0;
+        while !done() {
+            if !self.shared.run_one() {
+                self.shared.idle(done);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn fib(pool: &Pool, n: u64) -> u64 {
+        if n < 2 {
+            return n;
+        }
+        let (a, b) = pool.join(|| fib(pool, n - 1), || fib(pool, n - 2));
+        a + b
+    }
+
+    #[test]
+    fn nested_joins() {
+        let pool = Pool::new("test", 2).unwrap();
+        assert_eq!(fib(&pool, 16), 987);
+    }
+
+    #[test]
+    fn nested_parallel_for() {
+        let pool = Pool::new("test", 3).unwrap();
+        let mut rows = vec![vec![0usize; 64]; 32];
+        pool.parallel_for_mut(&mut rows, |i, row| {
+            pool.parallel_for_mut(row, |j, v| *v = i * 64 + j);
+        });
+        let sum = AtomicUsize::new(0);
+        pool.parallel_for(&rows, |_, row| {
+            sum.fetch_add(row.iter().sum(), Ordering::Relaxed);
+        });
+        assert_eq!(sum.into_inner(), (0..32 * 64).sum());
+    }
+
+    #[test]
+    fn scoped_jobs_borrow_and_spawn_more() {
+        let pool = Pool::new("test", 2).unwrap();
+        let count = AtomicUsize::new(0);
+        pool.scope(|s| {
+            for _ in 0..8 {
+                s.spawn(|s| {
+                    count.fetch_add(1, Ordering::Relaxed);
+                    s.spawn_with(Priority::High, |_| {
+                        count.fetch_add(1, Ordering::Relaxed);
+                    });
+                });
+            }
+        });
+        assert_eq!(count.into_inner(), 16);
+    }
+
+    #[test]
+    fn panics_propagate_out_of_scopes() {
+        let pool = Pool::new("test", 2).unwrap();
+        let finished = AtomicUsize::new(0);
+        let res = panic::catch_unwind(AssertUnwindSafe(|| {
+            pool.scope(|s| {
+                s.spawn(|_| panic!("Job failed."));
+                for _ in 0..4 {
+                    s.spawn(|_| {
+                        finished.fetch_add(1, Ordering::Relaxed);
+                    });
+                }
+            })
+        }));
+        let payload = res.expect_err("The panic should have been resumed.");
+        assert_eq!(payload.downcast_ref::<&str>(), Some(&"Job failed."));
+        // Everything else still ran, and the pool still works.
+        assert_eq!(finished.into_inner(), 4);
+        assert_eq!(pool.join(|| 1, || 2), (1, 2));
+    }
+
+    #[test]
+    fn detached_panics_are_counted() {
+        let pool = Pool::new("test", 1).unwrap();
+        pool.spawn(Priority::Low, || panic!("Detached job failed."));
+        let (tx, rx) = mpsc::channel();
+        pool.spawn(Priority::Low, move || tx.send(()).unwrap());
+        rx.recv().unwrap();
+        assert_eq!(pool.panics(), 1);
+    }
+
+    #[test]
+    fn dropping_the_pool_drains_queued_jobs() {
+        let count = Arc::new(AtomicUsize::new(0));
+        {
+            let pool = Pool::new("test", 2).unwrap();
+            for _ in 0..100 {
+                let count = count.clone();
+                pool.spawn(Priority::Normal, move || {
+                    count.fetch_add(1, Ordering::Relaxed);
+                });
+            }
+        }
+        assert_eq!(count.load(Ordering::Relaxed), 100);
+    }
+}
@@ -0,0 +1,277 @@
+//! # Profile
+//!
+//! A span profiler for seeing every thread on one timeline.
+//!
+//! Spans are recorded by `Scope` guards, from creation until they are dropped, into a ring buffer
+//! owned by the thread they were recorded on. Nothing is recorded until the profiler is
+//! `enable`d, and a disabled scope costs a single atomic load. Threads started by
+//! `builder::ThreadBuilder` record a span for every loop and command on their own.
+//!
+//! `write_chrome_trace` exports everything in the Chrome trace event format, which can be opened in
+//! `chrome://tracing` or Perfetto.
+//!
+//! ```ignore
+//! profile::enable();
+//! {
+//!     let _span = profile::scope("Simulation::step");
+//!     sim.step();
+//! }
+//! profile::save("trace.json")?;
+//! ```
+
+use std::{
+    borrow::Cow,
+    collections::VecDeque,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex, MutexGuard, OnceLock,
+    },
+    time::{Duration, Instant},
+};
+
+/// The most spans kept per thread. Older ones are overwritten.
+pub const CAPACITY: usize = 1 << 16;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static NEXT_TID: AtomicU64 = AtomicU64::new(1);
+static BUFFERS: Mutex<Vec<Arc<Mutex<Buffer>>>> = Mutex::new(Vec::new());
+
+thread_local! {
+    static LOCAL: Arc<Mutex<Buffer>> = Buffer::register();
+}
+
+/// Starts recording spans.
+pub fn enable() {
+    epoch();
+    ENABLED.store(true, Ordering::Relaxed);
+}
+/// Stops recording spans. What was recorded is kept.
+pub fn disable() {
+    ENABLED.store(false, Ordering::Relaxed);
+}
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// When the trace starts.
+fn epoch() -> Instant {
+    static EPOCH: OnceLock<Instant> = OnceLock::new();
+    *EPOCH.get_or_init(Instant::now)
+}
+
+fn lock<T>(m: &Mutex<T>) -> MutexGuard<'_, T> {
+    m.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[derive(Debug)]
+struct Span {
+    name: Cow<'static, str>,
+    /// Since the epoch.
+    start: Duration,
+    duration: Duration,
+}
+
+#[derive(Debug)]
+struct Buffer {
+    tid: u64,
+    thread: String,
+    spans: VecDeque<Span>,
+    overwritten: u64,
+}
+impl Buffer {
+    fn register() -> Arc<Mutex<Self>> {
+        let tid = NEXT_TID.fetch_add(1, Ordering::Relaxed);
+        let thread = std::thread::current()
+            .name()
+            .map_or_else(|| format!("Thread {}", tid), str::to_string);
+        let buffer = Arc::new(Mutex::new(Self {
+            tid,
+            thread,
+            spans: VecDeque::new(),
+            overwritten: 0,
+        }));
+        lock(&BUFFERS).push(buffer.clone());
+        buffer
+    }
+    fn push(&mut self, span: Span) {
+        if self.spans.len() == CAPACITY {
+            self.spans.pop_front();
+            self.overwritten += 1;
+        }
+        self.spans.push_back(span);
+    }
+}
+
+/// Records a span from its creation until it is dropped.
+#[must_use = "A scope ends as soon as it is dropped."]
+#[derive(Debug)]
+pub struct Scope {
+    /// The span's name and start, or `None` when the profiler was disabled at creation.
+    started: Option<(Cow<'static, str>, Instant)>,
+}
+impl Drop for Scope {
+    fn drop(&mut self) {
+        if let Some((name, start)) = self.started.take() {
+            let end = Instant::now();
+            let span = Span {
+                name,
+                start: start.saturating_duration_since(epoch()),
+                duration: end - start,
+            };
+            // The thread may be tearing down its locals already.
+            let _ = LOCAL.try_with(|buffer| lock(buffer).push(span));
+        }
+    }
+}
+
+/// Starts a span on the current thread. The clock is only read if the profiler is enabled.
+pub fn scope(name: impl Into<Cow<'static, str>>) -> Scope {
+    Scope {
+        started: is_enabled().then(|| (name.into(), Instant::now())),
+    }
+}
+
+/// Throws away every recorded span, along with the buffers of threads that have finished.
+pub fn clear() {
+    lock(&BUFFERS).retain(|buffer| {
+        let mut b = lock(buffer);
+        b.spans.clear();
+        b.overwritten = 0;
+        drop(b);
+        Arc::strong_count(buffer) > 1
+    });
+}
+
+/// How many spans were overwritten because a thread recorded more than `CAPACITY`.
+pub fn overwritten() -> u64 {
+    lock(&BUFFERS).iter().map(|b| lock(b).overwritten).sum()
+}
+
+fn write_str(w: &mut impl Write, s: &str) -> io::Result<()> {
+    w.write_all(b"\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => w.write_all(b"\\\"")?,
+            '\\' => w.write_all(b"\\\\")?,
+            '\n' => w.write_all(b"\\n")?,
+            c if c.is_control() => write!(w, "\\u{:04x}", c as u32)?,
+            c => write!(w, "{}", c)?,
+        }
+    }
+    w.write_all(b"\"")
+}
+
+/// Microseconds, as trace events count time.
+fn micros(d: Duration) -> f64 {
+    d.as_nanos() as f64 / 1000.
+}
+
+/// Writes every recorded span in the Chrome trace event format.
+pub fn write_chrome_trace(mut w: impl Write) -> io::Result<()> {
+    let pid = std::process::id();
+    let buffers = lock(&BUFFERS).clone();
+    w.write_all(b"{\"traceEvents\":[")?;
+    let mut first = true;
+    for buffer in buffers.iter() {
+        let buffer = lock(buffer);
+        if !first {
+            w.write_all(b",")?;
+        }
+        first = false;
+        write!(
+            w,
+            "\n{{\"name\":\"thread_name\",\"ph\":\"M\",\"pid\":{},\"tid\":{},\"args\":{{\"name\":",
+            pid, buffer.tid
+        )?;
+        write_str(&mut w, &buffer.thread)?;
+        w.write_all(b"}}")?;
+        for span in buffer.spans.iter() {
+            w.write_all(b",\n{\"name\":")?;
+            write_str(&mut w, &span.name)?;
+            write!(
+                w,
+                ",\"ph\":\"X\",\"pid\":{},\"tid\":{},\"ts\":{:.3},\"dur\":{:.3}}}",
+                pid,
+                buffer.tid,
+                micros(span.start),
+                micros(span.duration)
+            )?;
+        }
+    }
+    w.write_all(b"\n],\"displayTimeUnit\":\"ms\"}\n")
+}
+
+/// Writes the trace to a file at `path`.
+pub fn save(path: impl AsRef<Path>) -> io::Result<()> {
+    let mut w = BufWriter::new(File::create(path)?);
+    write_chrome_trace(&mut w)?;
+    w.flush()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn trace() -> String {
+        let mut out = vec![];
+        write_chrome_trace(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn spans_from_every_thread_are_exported() {
+        enable();
+        {
+            let _outer = scope("profile outer");
+            let _inner = scope(format!("profile {}", "inner"));
+        }
+        std::thread::Builder::new()
+            .name("profile \"worker\"".into())
+            .spawn(|| drop(scope("profile worker")))
+            .unwrap()
+            .join()
+            .unwrap();
+        let trace = trace();
+        assert!(trace.starts_with("{\"traceEvents\":["));
+        assert!(trace.ends_with("],\"displayTimeUnit\":\"ms\"}\n"));
+        for name in ["profile outer", "profile inner", "profile worker"] {
+            assert!(
+                trace.contains(&format!("{{\"name\":\"{}\",\"ph\":\"X\"", name)),
+                "{} is missing from {}",
+                name,
+                trace
+            );
+        }
+        assert!(trace.contains("\"args\":{\"name\":\"profile \\\"worker\\\"\"}"));
+    }
+
+    #[test]
+    fn buffers_keep_the_latest_spans() {
+        let mut buffer = Buffer {
+            tid: 0,
+            thread: String::new(),
+            spans: VecDeque::new(),
+            overwritten: 0,
+        };
+        for i in 0..CAPACITY + 3 {
+            buffer.push(Span {
+                name: "span".into(),
+                start: Duration::from_nanos(i as u64),
+                duration: Duration::ZERO,
+            });
+        }
+        assert_eq!(buffer.spans.len(), CAPACITY);
+        assert_eq!(buffer.overwritten, 3);
+        assert_eq!(buffer.spans[0].start, Duration::from_nanos(3));
+    }
+
+    #[test]
+    fn strings_are_escaped() {
+        let mut out = vec![];
+        write_str(&mut out, "a\"b\\c\nd\u{1}").unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), r#""a\"b\\c\nd\u0001""#);
+    }
+}
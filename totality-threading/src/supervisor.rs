@@ -0,0 +1,517 @@
+//! # Supervisor
+//!
+//! Owns named `KillableThread`s, notices when they stop, and restarts the ones that panicked
+//! according to their `RestartPolicy`.
+//!
+//! Panics can only be caught with `panic = "unwind"`. Under `panic = "abort"` the first panic
+//! still takes the whole process down, and there is nothing left to supervise.
+//!
+//! The supervisor does not run on its own; `poll` it regularly, e.g. once per frame, and act on or
+//! log the reports it returns.
+
+use std::{
+    io,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+use crate::{
+    clock::{Clock, SystemClock},
+    killable_thread::{panic_message, KillableThread},
+};
+
+/// When to restart a thread that panicked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Restart {
+    Never,
+    /// Right away.
+    Always,
+    /// After waiting `initial`, doubling the wait after every restart up to `max`.
+    Backoff {
+        initial: Duration,
+        max: Duration,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RestartPolicy {
+    pub restart: Restart,
+    /// Gives up on the thread after restarting it this many times. Restarts that failed to spawn a
+    /// thread count too.
+    pub max_restarts: Option<u32>,
+    /// Forgets about earlier restarts, and starts backing off from the beginning again, once a
+    /// thread has been running for this long. Without it, every restart counts for good.
+    pub reset_after: Option<Duration>,
+}
+impl RestartPolicy {
+    pub fn never() -> Self {
+        Self {
+            restart: Restart::Never,
+            max_restarts: None,
+            reset_after: None,
+        }
+    }
+    pub fn always() -> Self {
+        Self {
+            restart: Restart::Always,
+            max_restarts: None,
+            reset_after: None,
+        }
+    }
+    pub fn backoff(initial: Duration, max: Duration) -> Self {
+        Self {
+            restart: Restart::Backoff { initial, max },
+            max_restarts: None,
+            reset_after: None,
+        }
+    }
+    pub fn max_restarts(mut self, max_restarts: u32) -> Self {
+        self.max_restarts = Some(max_restarts);
+        self
+    }
+    pub fn reset_after(mut self, healthy: Duration) -> Self {
+        self.reset_after = Some(healthy);
+        self
+    }
+    fn initial_backoff(&self) -> Duration {
+        match self.restart {
+            Restart::Backoff { initial, .. } => initial,
+            _ => Duration::ZERO,
+        }
+    }
+}
+
+/// Something that happened to a supervised thread.
+#[derive(Debug)]
+pub enum Report {
+    /// The thread returned on its own. It is not restarted.
+    Finished {
+        name: String,
+    },
+    Panicked {
+        name: String,
+        payload: String,
+    },
+    Restarted {
+        name: String,
+        restarts: u32,
+    },
+    /// Restarting failed to spawn a thread. It is retried like a panicked one would be.
+    RestartFailed {
+        name: String,
+        error: io::Error,
+    },
+    /// The policy ran out of restarts, or never allowed any.
+    GaveUp {
+        name: String,
+    },
+}
+
+/// A supervised thread with its type erased.
+trait Handle: Send {
+    fn is_finished(&self) -> bool;
+    fn join(self: Box<Self>) -> std::thread::Result<()>;
+}
+impl<P: Send + 'static, T: Send + 'static> Handle for KillableThread<P, T> {
+    fn is_finished(&self) -> bool {
+        KillableThread::is_finished(self)
+    }
+    fn join(self: Box<Self>) -> std::thread::Result<()> {
+        self.finish()
+            .expect("The handle is only taken when finishing.")
+            .map(drop)
+    }
+}
+
+type Spawner = Box<dyn FnMut() -> io::Result<Box<dyn Handle>> + Send>;
+
+struct Entry {
+    name: String,
+    policy: RestartPolicy,
+    spawn: Spawner,
+    handle: Option<Box<dyn Handle>>,
+    /// When the running thread was started.
+    started: Instant,
+    restarts: u32,
+    /// When to restart, if the thread is waiting to be.
+    restart_at: Option<Instant>,
+    backoff: Duration,
+}
+
+pub struct Supervisor {
+    entries: Vec<Entry>,
+    clock: Arc<dyn Clock>,
+}
+impl Supervisor {
+    pub fn new() -> Self {
+        Self::with_clock(SystemClock)
+    }
+    /// Creates a supervisor that times backoffs with `clock`.
+    pub fn with_clock(clock: impl Clock + 'static) -> Self {
+        Self {
+            entries: vec![],
+            clock: Arc::new(clock),
+        }
+    }
+    fn now(&self) -> Instant {
+        self.clock.now()
+    }
+    /// Starts a thread through `spawn`, which is called again for every restart.
+    ///
+    /// A thread of the same name that is already supervised is stopped and replaced.
+    pub fn supervise<P, T, F>(
+        &mut self,
+        name: &str,
+        policy: RestartPolicy,
+        mut spawn: F,
+    ) -> io::Result<()>
+    where
+        P: Send + 'static,
+        T: Send + 'static,
+        F: FnMut() -> io::Result<KillableThread<P, T>> + Send + 'static,
+    {
+        self.stop(name);
+        let handle: Box<dyn Handle> = Box::new(spawn()?);
+        self.entries.push(Entry {
+            name: name.to_string(),
+            policy,
+            spawn: Box::new(move || Ok(Box::new(spawn()?) as Box<dyn Handle>)),
+            handle: Some(handle),
+            started: self.now(),
+            restarts: 0,
+            restart_at: None,
+            backoff: policy.initial_backoff(),
+        });
+        Ok(())
+    }
+    /// Stops a thread and forgets about it. Returns whether it was supervised.
+    pub fn stop(&mut self, name: &str) -> bool {
+        match self.entries.iter().position(|e| e.name == name) {
+            Some(i) => {
+                let entry = self.entries.remove(i);
+                if let Some(Err(payload)) = entry.handle.map(|h| h.join()) {
+                    warn!(
+                        "{} thread panicked while stopping: {}",
+                        name,
+                        panic_message(&*payload)
+                    );
+                }
+                true
+            }
+            None => false,
+        }
+    }
+    pub fn is_running(&self, name: &str) -> bool {
+        self.entries
+            .iter()
+            .any(|e| e.name == name && e.handle.as_ref().is_some_and(|h| !h.is_finished()))
+    }
+    /// How many times a thread has been restarted, or tried to be, since its policy last reset.
+    pub fn restarts(&self, name: &str) -> Option<u32> {
+        self.entries
+            .iter()
+            .find(|e| e.name == name)
+            .map(|e| e.restarts)
+    }
+    /// Collects threads that stopped and restarts the ones that are due.
+    pub fn poll(&mut self) -> Vec<Report> {
+        let now = self.now();
+        let mut reports = vec![];
+        for entry in self.entries.iter_mut() {
+            if entry.handle.as_ref().is_some_and(|h| h.is_finished()) {
+                let handle = entry.handle.take().expect("Handle was just checked.");
+                match handle.join() {
+                    Ok(()) => {
+                        info!("{} thread finished.", entry.name);
+                        reports.push(Report::Finished {
+                            name: entry.name.clone(),
+                        });
+                    }
+                    Err(payload) => {
+                        let payload = panic_message(&*payload).to_string();
+                        error!("{} thread panicked: {}", entry.name, payload);
+                        reports.push(Report::Panicked {
+                            name: entry.name.clone(),
+                            payload,
+                        });
+                        if entry
+                            .policy
+                            .reset_after
+                            .is_some_and(|healthy| now - entry.started >= healthy)
+                        {
+                            entry.restarts = 0;
+                            entry.backoff = entry.policy.initial_backoff();
+                        }
+                        entry.schedule_restart(now, &mut reports);
+                    }
+                }
+            }
+            if entry.restart_at.is_some_and(|at| at <= now) {
+                entry.restart_at = None;
+                entry.restarts += 1;
+                match (entry.spawn)() {
+                    Ok(handle) => {
+                        entry.handle = Some(handle);
+                        entry.started = now;
+                        info!(
+                            "Restarted {} thread, {} times so far.",
+                            entry.name, entry.restarts
+                        );
+                        reports.push(Report::Restarted {
+                            name: entry.name.clone(),
+                            restarts: entry.restarts,
+                        });
+                    }
+                    Err(error) => {
+                        error!("Could not restart {} thread: {}", entry.name, error);
+                        reports.push(Report::RestartFailed {
+                            name: entry.name.clone(),
+                            error,
+                        });
+                        entry.schedule_restart(now, &mut reports);
+                    }
+                }
+            }
+        }
+        reports
+    }
+}
+impl Entry {
+    fn schedule_restart(&mut self, now: Instant, reports: &mut Vec<Report>) {
+        let out_of_restarts = self
+            .policy
+            .max_restarts
+            .is_some_and(|max| self.restarts >= max);
+        let delay = match self.policy.restart {
+            Restart::Never => None,
+            _ if out_of_restarts => None,
+            Restart::Always => Some(Duration::ZERO),
+            Restart::Backoff { max, .. } => {
+                let delay = self.backoff;
+                self.backoff = (self.backoff * 2).min(max);
+                Some(delay)
+            }
+        };
+        match delay {
+            Some(delay) => self.restart_at = Some(now + delay),
+            None => {
+                warn!("Giving up on {} thread.", self.name);
+                reports.push(Report::GaveUp {
+                    name: self.name.clone(),
+                });
+            }
+        }
+    }
+}
+impl Default for Supervisor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl std::fmt::Debug for Supervisor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list()
+            .entries(self.entries.iter().map(|e| &e.name))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc,
+    };
+
+    use super::*;
+    use crate::clock::ManualClock;
+
+    /// A thread that panics right away, counting how often it was started.
+    fn crashing(
+        starts: Arc<AtomicUsize>,
+    ) -> impl FnMut() -> io::Result<KillableThread<(), ()>> + Send {
+        move || {
+            let n = starts.fetch_add(1, Ordering::SeqCst);
+            KillableThread::new(mpsc::channel().0, "crashing".into(), move || {
+                panic!("Crash {}.", n)
+            })
+        }
+    }
+
+    /// Polls until no thread is running or due to be restarted.
+    fn settle(sup: &mut Supervisor) -> Vec<Report> {
+        let mut reports = vec![];
+        for _ in 0..400 {
+            reports.extend(sup.poll());
+            let now = sup.now();
+            if sup
+                .entries
+                .iter()
+                .all(|e| e.handle.is_none() && e.restart_at.is_none_or(|at| at > now))
+            {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        reports
+    }
+
+    #[test]
+    fn panics_are_reported_and_restarts_are_capped() {
+        let starts = Arc::new(AtomicUsize::new(0));
+        let mut sup = Supervisor::new();
+        sup.supervise(
+            "crashing",
+            RestartPolicy::always().max_restarts(2),
+            crashing(starts.clone()),
+        )
+        .unwrap();
+        let reports = settle(&mut sup);
+        let panics = reports
+            .iter()
+            .filter_map(|r| match r {
+                Report::Panicked { name, payload } => Some((name.as_str(), payload.as_str())),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            panics,
+            vec![
+                ("crashing", "Crash 0."),
+                ("crashing", "Crash 1."),
+                ("crashing", "Crash 2.")
+            ]
+        );
+        assert!(matches!(reports.last(), Some(Report::GaveUp { .. })));
+        assert_eq!(starts.load(Ordering::SeqCst), 3);
+        assert_eq!(sup.restarts("crashing"), Some(2));
+    }
+
+    #[test]
+    fn restarts_back_off() {
+        let clock = ManualClock::new();
+        let starts = Arc::new(AtomicUsize::new(0));
+        let mut sup = Supervisor::with_clock(clock.clone());
+        let policy = RestartPolicy::backoff(Duration::from_secs(1), Duration::from_secs(3));
+        sup.supervise("crashing", policy, crashing(starts.clone()))
+            .unwrap();
+        for (wait, started) in [(1, 2), (2, 3), (3, 4), (3, 5)] {
+            settle(&mut sup);
+            // Just short of the backoff, nothing happens.
+            clock.advance(Duration::from_secs(wait) - Duration::from_millis(1));
+            sup.poll();
+            assert_eq!(starts.load(Ordering::SeqCst), started - 1);
+            clock.advance(Duration::from_millis(1));
+            assert!(matches!(sup.poll().as_slice(), [Report::Restarted { .. }]));
+            assert_eq!(starts.load(Ordering::SeqCst), started);
+        }
+    }
+
+    #[test]
+    fn failed_restarts_back_off_and_count() {
+        let clock = ManualClock::new();
+        let starts = Arc::new(AtomicUsize::new(0));
+        let mut sup = Supervisor::with_clock(clock.clone());
+        let policy =
+            RestartPolicy::backoff(Duration::from_secs(1), Duration::from_secs(8)).max_restarts(3);
+        let mut crash = crashing(starts.clone());
+        sup.supervise("failing", policy, move || {
+            // Only the first start spawns a thread.
+            if starts.load(Ordering::SeqCst) == 0 {
+                crash()
+            } else {
+                Err(io::Error::other("No more threads."))
+            }
+        })
+        .unwrap();
+        settle(&mut sup);
+        for wait in [1, 2] {
+            clock.advance(Duration::from_secs(wait));
+            assert!(matches!(
+                sup.poll().as_slice(),
+                [Report::RestartFailed { .. }]
+            ));
+            // Nothing is retried until the next backoff is over.
+            assert!(sup.poll().is_empty());
+        }
+        clock.advance(Duration::from_secs(4));
+        assert!(matches!(
+            sup.poll().as_slice(),
+            [Report::RestartFailed { .. }, Report::GaveUp { .. }]
+        ));
+        assert_eq!(sup.restarts("failing"), Some(3));
+        clock.advance(Duration::from_secs(60));
+        assert!(sup.poll().is_empty());
+    }
+
+    #[test]
+    fn healthy_runs_reset_the_restarts() {
+        let clock = ManualClock::new();
+        let (tx, rx) = mpsc::channel::<()>();
+        let rx = Arc::new(std::sync::Mutex::new(rx));
+        let mut sup = Supervisor::with_clock(clock.clone());
+        let policy = RestartPolicy::always()
+            .max_restarts(1)
+            .reset_after(Duration::from_secs(10));
+        // Every thread panics once it is told to.
+        sup.supervise("flaky", policy, move || {
+            let rx = rx.clone();
+            KillableThread::new(mpsc::channel::<()>().0, "flaky".into(), move || {
+                rx.lock().unwrap().recv().unwrap();
+                panic!("Told to.")
+            })
+        })
+        .unwrap();
+        // Threads keep running after a restart, so poll for the reports instead of settling.
+        let mut poll_for = |n: usize| {
+            let mut reports = vec![];
+            for _ in 0..400 {
+                reports.extend(sup.poll());
+                if reports.len() >= n {
+                    break;
+                }
+                std::thread::sleep(Duration::from_millis(5));
+            }
+            reports
+        };
+        for _ in 0..3 {
+            clock.advance(Duration::from_secs(10));
+            tx.send(()).unwrap();
+            let reports = poll_for(2);
+            assert!(
+                matches!(
+                    reports.as_slice(),
+                    [
+                        Report::Panicked { .. },
+                        Report::Restarted { restarts: 1, .. }
+                    ]
+                ),
+                "{:?}",
+                reports
+            );
+        }
+        // Crashing again right away uses up the one restart.
+        tx.send(()).unwrap();
+        let reports = poll_for(2);
+        assert!(matches!(
+            reports.as_slice(),
+            [Report::Panicked { .. }, Report::GaveUp { .. }]
+        ));
+    }
+
+    #[test]
+    fn finished_threads_are_not_restarted() {
+        let mut sup = Supervisor::new();
+        sup.supervise("done", RestartPolicy::always(), || {
+            KillableThread::new(mpsc::channel::<()>().0, "done".into(), || ())
+        })
+        .unwrap();
+        let reports = settle(&mut sup);
+        assert!(matches!(reports.as_slice(), [Report::Finished { .. }]));
+        assert!(sup.stop("done"));
+        assert!(!sup.stop("done"));
+    }
+}
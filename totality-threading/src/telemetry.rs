@@ -0,0 +1,272 @@
+//! # Telemetry
+//!
+//! Per-thread loop statistics, gathered in a `Registry` that can be read while the threads run.
+//!
+//! Every thread started by `builder::ThreadBuilder` reports to `Registry::global()` unless given
+//! another registry. For each thread name it counts loops and deadlines missed, keeps busy and idle
+//! times of the most recent loops for percentiles, and measures how far sleeps overshoot what they
+//! asked for. Threads sharing a name, e.g. one restarted by a `supervisor`, share their
+//! statistics.
+//!
+//! `Registry::snapshot` copies everything out, ready to be printed or shown on screen:
+//!
+//! ```ignore
+//! for stats in Registry::global().snapshot() {
+//!     info!("{}", stats);
+//! }
+//! ```
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fmt,
+    sync::{Arc, Mutex, MutexGuard, OnceLock},
+    time::Duration,
+};
+
+/// How many of the most recent loops percentiles are taken over.
+pub const WINDOW: usize = 512;
+
+/// A recent stretch of loop times.
+#[derive(Debug, Default)]
+struct Window(VecDeque<Duration>);
+impl Window {
+    fn push(&mut self, d: Duration) {
+        if self.0.len() == WINDOW {
+            self.0.pop_front();
+        }
+        self.0.push_back(d);
+    }
+    fn percentiles(&self) -> Percentiles {
+        let mut sorted = self.0.iter().copied().collect::<Vec<_>>();
+        sorted.sort_unstable();
+        // Nearest rank.
+        let rank = |p: usize| match sorted.len() {
+            0 => Duration::ZERO,
+            n => sorted[(n * p).div_ceil(100).max(1) - 1],
+        };
+        Percentiles {
+            p50: rank(50),
+            p90: rank(90),
+            p99: rank(99),
+            max: rank(100),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Stats {
+    target: Option<Duration>,
+    loops: u64,
+    missed: u64,
+    busy: Window,
+    idle: Window,
+    sleeps: u64,
+    overshoot: Duration,
+    max_overshoot: Duration,
+}
+
+/// Where threads report their loops to. Clones share the same statistics.
+#[derive(Debug, Clone, Default)]
+pub struct Registry(Arc<Mutex<BTreeMap<String, Probe>>>);
+impl Registry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// The registry threads report to by default.
+    pub fn global() -> &'static Registry {
+        static GLOBAL: OnceLock<Registry> = OnceLock::new();
+        GLOBAL.get_or_init(Registry::new)
+    }
+    fn lock(&self) -> MutexGuard<'_, BTreeMap<String, Probe>> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+    /// The probe a thread named `name` reports through, created if there is none yet.
+    pub fn probe(&self, name: &str) -> Probe {
+        self.lock().entry(name.to_string()).or_default().clone()
+    }
+    /// Forgets a thread's statistics. A thread still reporting to them is not added back.
+    pub fn remove(&self, name: &str) -> bool {
+        self.lock().remove(name).is_some()
+    }
+    pub fn get(&self, name: &str) -> Option<Snapshot> {
+        self.lock().get(name).map(|probe| probe.snapshot(name))
+    }
+    /// Copies out the statistics of every thread, ordered by name.
+    pub fn snapshot(&self) -> Vec<Snapshot> {
+        self.lock()
+            .iter()
+            .map(|(name, probe)| probe.snapshot(name))
+            .collect()
+    }
+}
+
+/// A thread's end of the registry.
+#[derive(Debug, Clone, Default)]
+pub struct Probe(Arc<Mutex<Stats>>);
+impl Probe {
+    fn lock(&self) -> MutexGuard<'_, Stats> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+    /// Records a loop's body taking `busy`, against the loop's `target` if it is paced.
+    pub(crate) fn busy(&self, busy: Duration, target: Option<Duration>) {
+        let mut stats = self.lock();
+        stats.target = target;
+        stats.loops += 1;
+        if target.is_some_and(|target| busy > target) {
+            stats.missed += 1;
+        }
+        stats.busy.push(busy);
+    }
+    /// Records the time in between two loops' bodies.
+    pub(crate) fn idle(&self, idle: Duration) {
+        self.lock().idle.push(idle);
+    }
+    /// Records a sleep of `asked` that actually took `slept`.
+    pub(crate) fn slept(&self, asked: Duration, slept: Duration) {
+        let overshoot = slept.saturating_sub(asked);
+        let mut stats = self.lock();
+        stats.sleeps += 1;
+        stats.overshoot += overshoot;
+        stats.max_overshoot = stats.max_overshoot.max(overshoot);
+    }
+    fn snapshot(&self, name: &str) -> Snapshot {
+        let stats = self.lock();
+        Snapshot {
+            name: name.to_string(),
+            target: stats.target,
+            loops: stats.loops,
+            missed: stats.missed,
+            busy: stats.busy.percentiles(),
+            idle: stats.idle.percentiles(),
+            mean_overshoot: stats
+                .overshoot
+                .checked_div(stats.sleeps as u32)
+                .unwrap_or_default(),
+            max_overshoot: stats.max_overshoot,
+        }
+    }
+}
+
+/// Loop times over the last `WINDOW` loops.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Percentiles {
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+impl fmt::Display for Percentiles {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "p50 {:?} p90 {:?} p99 {:?} max {:?}",
+            self.p50, self.p90, self.p99, self.max
+        )
+    }
+}
+
+/// A thread's statistics at one point in time.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    pub name: String,
+    /// How long a loop should take, if the thread is paced.
+    pub target: Option<Duration>,
+    pub loops: u64,
+    /// Loops whose body alone took longer than the target.
+    pub missed: u64,
+    pub busy: Percentiles,
+    pub idle: Percentiles,
+    /// How much longer than asked for sleeps took, on average.
+    pub mean_overshoot: Duration,
+    pub max_overshoot: Duration,
+}
+impl fmt::Display for Snapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {} loops", self.name, self.loops)?;
+        if let Some(target) = self.target {
+            write!(f, ", {} missed {:?}", self.missed, target)?;
+        }
+        write!(
+            f,
+            ", busy {}, idle {}, overshoot mean {:?} max {:?}",
+            self.busy, self.idle, self.mean_overshoot, self.max_overshoot
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::mpsc;
+
+    use super::*;
+    use crate::{
+        builder::{Flow, Pacing, ThreadBuilder},
+        clock::ManualClock,
+    };
+
+    const MS: Duration = Duration::from_millis(1);
+
+    #[test]
+    fn percentiles_cover_the_window() {
+        let probe = Probe::default();
+        for ms in 1..=100 {
+            probe.busy(MS * ms, None);
+        }
+        let stats = probe.snapshot("probe");
+        assert_eq!(stats.loops, 100);
+        assert_eq!(
+            stats.busy,
+            Percentiles {
+                p50: MS * 50,
+                p90: MS * 90,
+                p99: MS * 99,
+                max: MS * 100
+            }
+        );
+        // Old loops fall out of the window, but still count.
+        for _ in 0..WINDOW {
+            probe.busy(MS, None);
+        }
+        let stats = probe.snapshot("probe");
+        assert_eq!(stats.loops, 100 + WINDOW as u64);
+        assert_eq!(stats.busy.max, MS);
+        assert_eq!(stats.missed, 0);
+    }
+
+    #[test]
+    fn paced_threads_report_missed_deadlines() {
+        let registry = Registry::new();
+        let clock = ManualClock::new();
+        let (tx, rx) = mpsc::channel();
+        let body_clock = clock.clone();
+        let kt = ThreadBuilder::new("telemetry")
+            .pacing(Pacing::Rate(100))
+            .clock(clock.clone())
+            .telemetry(&registry)
+            .setup(move || Ok::<_, ()>((0, tx)))
+            .body(move |(loops, tx)| {
+                *loops += 1;
+                // Every fourth loop takes twice as long as it should.
+                body_clock.advance(if *loops % 4 == 0 { MS * 20 } else { MS * 2 });
+                if *loops == 20 {
+                    tx.send(()).unwrap();
+                    return Ok(Flow::Stop);
+                }
+                Ok(Flow::Continue)
+            })
+            .spawn()
+            .unwrap();
+        rx.recv_timeout(Duration::from_millis(500)).unwrap();
+        kt.try_finish().unwrap();
+        let stats = registry.get("telemetry").unwrap();
+        assert_eq!(stats.loops, 20);
+        assert_eq!(stats.missed, 5);
+        assert_eq!(stats.target, Some(MS * 10));
+        assert_eq!(stats.busy.p50, MS * 2);
+        assert_eq!(stats.busy.max, MS * 20);
+        // Loops that ran short sleep up to the target, and a manual clock sleeps exactly.
+        assert_eq!(stats.idle.p50, MS * 8);
+        assert_eq!(stats.max_overshoot, Duration::ZERO);
+        assert_eq!(registry.snapshot(), vec![stats]);
+    }
+}
@@ -0,0 +1,202 @@
+//! # Timestep
+//!
+//! Fixed-timestep pacing. Elapsed wall time goes into an accumulator and comes back out in whole
+//! steps, so a loop that overran catches up with extra steps instead of letting simulation time
+//! drift. What is left over is published as the interpolation alpha, for consumers that render in
+//! between steps.
+
+use std::{
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+use crate::clock::Clock;
+
+/// A shared view of how far into the next step the accumulator is, from 0 up to but excluding 1.
+#[derive(Debug, Clone, Default)]
+pub struct Alpha(Arc<AtomicU32>);
+impl Alpha {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn get(&self) -> f32 {
+        f32::from_bits(self.0.load(Ordering::Relaxed))
+    }
+    fn set(&self, alpha: f32) {
+        self.0.store(alpha.to_bits(), Ordering::Relaxed);
+    }
+}
+
+/// An accumulator handing out fixed steps.
+#[derive(Debug)]
+pub struct FixedStep {
+    step: Duration,
+    /// The most steps handed out at once. Anything beyond is dropped, so that a loop slower than
+    /// its step does not fall further behind every time it catches up.
+    max_steps: u32,
+    accumulated: Duration,
+    last: Option<Instant>,
+    dropped: Duration,
+    alpha: Alpha,
+}
+impl FixedStep {
+    /// # Panics
+    ///
+    /// If `step` is zero.
+    pub fn new(step: Duration, max_steps: u32) -> Self {
+        Self::with_alpha(step, max_steps, Alpha::new())
+    }
+    /// Publishes the interpolation alpha to an existing handle.
+    pub fn with_alpha(step: Duration, max_steps: u32, alpha: Alpha) -> Self {
+        assert!(!step.is_zero(), "Step must be nonzero.");
+        alpha.set(0.);
+        Self {
+            step,
+            max_steps,
+            accumulated: Duration::ZERO,
+            last: None,
+            dropped: Duration::ZERO,
+            alpha,
+        }
+    }
+    pub fn step(&self) -> Duration {
+        self.step
+    }
+    /// Changes the step from now on. The accumulated time is kept.
+    pub fn set_step(&mut self, step: Duration) {
+        assert!(!step.is_zero(), "Step must be nonzero.");
+        self.step = step;
+    }
+    pub fn alpha(&self) -> Alpha {
+        self.alpha.clone()
+    }
+    /// Total time thrown away because more than `max_steps` were due at once.
+    pub fn dropped(&self) -> Duration {
+        self.dropped
+    }
+    /// Forgets time that passed since the last advance, e.g. after being paused.
+    pub fn reset(&mut self, now: Instant) {
+        self.last = Some(now);
+    }
+    /// How long until the next step is due.
+    pub fn until_next(&self, now: Instant) -> Duration {
+        match self.last {
+            Some(last) => self
+                .step
+                .saturating_sub(self.accumulated + now.saturating_duration_since(last)),
+            None => Duration::ZERO,
+        }
+    }
+    /// Adds the time elapsed since the last advance and returns how many steps to run. The very
+    /// first advance always runs one.
+    pub fn advance(&mut self, now: Instant) -> u32 {
+        self.accumulated += match self.last.replace(now) {
+            Some(last) => now.saturating_duration_since(last),
+            None => self.step,
+        };
+        let due = self.accumulated.as_nanos() / self.step.as_nanos();
+        let steps = if due > self.max_steps as u128 {
+            let kept = self.step * self.max_steps;
+            // Keep the fraction of a step, so that the alpha stays continuous.
+            let excess = self.accumulated - kept - self.accumulated_fraction();
+            self.dropped += excess;
+            self.accumulated -= excess;
+            warn!(
+                "Fell {:?} behind with a step of {:?}, dropping it.",
+                excess, self.step
+            );
+            self.max_steps
+        } else {
+            due as u32
+        };
+        self.accumulated -= self.step * steps;
+        self.alpha
+            .set((self.accumulated.as_secs_f64() / self.step.as_secs_f64()) as f32);
+        steps
+    }
+    fn accumulated_fraction(&self) -> Duration {
+        Duration::from_nanos((self.accumulated.as_nanos() % self.step.as_nanos()) as u64)
+    }
+    /// Sleeps on `clock` until a step is due, then advances.
+    pub fn wait(&mut self, clock: &(impl Clock + ?Sized)) -> u32 {
+        let wait = self.until_next(clock.now());
+        if !wait.is_zero() {
+            clock.sleep(wait);
+        }
+        self.advance(clock.now())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::clock::ManualClock;
+
+    const STEP: Duration = Duration::from_millis(10);
+
+    #[test]
+    fn catches_up_and_interpolates() {
+        let clock = ManualClock::new();
+        let mut fixed = FixedStep::new(STEP, 8);
+        let alpha = fixed.alpha();
+        assert_eq!(fixed.wait(&clock), 1);
+        // Waiting for the next step sleeps exactly up to it.
+        let before = clock.now();
+        assert_eq!(fixed.wait(&clock), 1);
+        assert_eq!(clock.now() - before, STEP);
+        // An overrun of two and a half steps gets caught up on, with the half left over.
+        clock.advance(STEP * 5 / 2);
+        assert_eq!(fixed.wait(&clock), 2);
+        assert!((alpha.get() - 0.5).abs() < 1e-6);
+        // The half counts towards the next step.
+        let before = clock.now();
+        assert_eq!(fixed.wait(&clock), 1);
+        assert_eq!(clock.now() - before, STEP / 2);
+        assert_eq!(alpha.get(), 0.);
+    }
+
+    #[test]
+    fn simulation_time_tracks_wall_time() {
+        let clock = ManualClock::new();
+        let start = clock.now();
+        let mut fixed = FixedStep::new(STEP, 8);
+        let mut steps = 0;
+        for i in 0..100u32 {
+            // Every few loops the body overruns by a varying amount.
+            clock.advance(STEP * (i % 4) / 3);
+            steps += fixed.wait(&clock);
+        }
+        // The first step runs right away, so simulation is ahead by whatever is left of the step.
+        let simulated = STEP * steps;
+        let wall = clock.now() - start;
+        assert!(
+            simulated >= wall && simulated - wall <= STEP,
+            "{:?} simulated in {:?}.",
+            simulated,
+            wall
+        );
+        assert_eq!(fixed.dropped(), Duration::ZERO);
+    }
+
+    #[test]
+    fn spiral_of_death_is_capped() {
+        let clock = ManualClock::new();
+        let mut fixed = FixedStep::new(STEP, 4);
+        fixed.wait(&clock);
+        clock.advance(STEP * 10 + STEP / 4);
+        assert_eq!(fixed.wait(&clock), 4);
+        assert_eq!(fixed.dropped(), STEP * 6);
+        assert!((fixed.alpha().get() - 0.25).abs() < 1e-6);
+        // Pausing forgets the time in between instead of dropping it.
+        clock.advance(STEP * 100);
+        fixed.reset(clock.now());
+        assert_eq!(fixed.wait(&clock), 1);
+        assert_eq!(fixed.dropped(), STEP * 6);
+    }
+}
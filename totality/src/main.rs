@@ -15,7 +15,8 @@ mod link;
 use link::*;
 
 use e::{a, b, p, C, V};
-use events::cb::ValueStore;
+use events::action::{ActionMap, ActionValue};
+use events::cb::{CBFn, ValueStore};
 use events::hal as e;
 use geom::{
     scene::{Scene},
@@ -78,12 +79,14 @@ struct State {
     sim: sim::Manager,
     gui: gui::Manager,
     c: Config,
+    // bindings
+    actions: Arc<Mutex<ActionMap>>,
+    actions_cb: Arc<Mutex<dyn CBFn<e::State, V, C>>>,
     // shutdown flow
     shutdown: Arc<Mutex<io::CBFn>>,
     current_action: Arc<Mutex<Action>>,
     // graphics settings
     should_use_depth: Arc<Mutex<bool>>,
-    settings_cb: Arc<Mutex<io::CBFn>>,
     should_restart_renderer: Arc<Mutex<bool>>,
     window_change_cb: Arc<Mutex<io::CBFn>>,
     // color flow
@@ -91,10 +94,10 @@ struct State {
     color_changer: Arc<Mutex<io::CBFn>>,
     // fish selection
     fish: Arc<Mutex<i32>>,
-    change_fish: Arc<Mutex<io::CBFn>>,
     // camera stuffs
     camera: Arc<Mutex<geom::camera::Camera>>,
-    camera_mover: Arc<Mutex<io::CBFn>>,
+    moving: Arc<Mutex<na::Vector3<f32>>>,
+    last_step: Instant,
     camera_roter: Arc<Mutex<io::CBFn>>,
 }
 impl State {
@@ -175,7 +178,21 @@ impl State {
             })
         };
         sm.reg_imm(b::C::F(b::Flag::Close).into(), cb_shutdown.clone());
-        sm.reg_imm(b::C::S(b::Key::Esc).into(), cb_shutdown.clone());
+        // set up bindings, which the flows below subscribe to
+        let actions = Arc::new(Mutex::new(
+            ActionMap::load("resources/bindings.cfg").expect("Could not load bindings!"),
+        ));
+        let mut action_map = actions.lock().unwrap();
+        action_map.push("gameplay");
+        {
+            let c_act = c_act.clone();
+            action_map.subscribe("exit", move |v| {
+                if let ActionValue::Digital(true) = v {
+                    debug!("What? You wanted to exit?");
+                    (*c_act.lock().unwrap()) = Action::Exit;
+                }
+            });
+        }
         let c_restart_render = Arc::new(Mutex::new(false));
         let cb_win_chg = {
             let c_restart_render = c_restart_render.clone();
@@ -195,32 +212,30 @@ impl State {
         sm.reg_imm(p::C::ScreenSz.into(), cb_win_chg.clone());
         // set up settings flow
         let c_should_use_depth = Arc::new(Mutex::new(false));
-        let cb_settings = {
+        {
             let c_should_use_depth = c_should_use_depth.clone();
-            cb_arc!("Depth Usage Toggle", v, s, {
-                if let V::B(b::V(_, b::State::UP)) = v {
+            action_map.subscribe("toggle_depth", move |v| {
+                if let ActionValue::Digital(false) = v {
                     if let Ok(mut f) = c_should_use_depth.lock() {
                         (*f) = !*f
                     }
                 }
-            })
-        };
-        sm.reg_imm(b::C::A('u').into(), cb_settings.clone());
+            });
+        }
         // set up render flow
         let c_fish = Arc::new(Mutex::new(1));
-        let cb_change_fish = {
+        {
             let c_fish = c_fish.clone();
-            cb_arc!("Fish Toggle", v, s, {
-                if let V::B(b::V(_, b::State::UP)) = v {
+            action_map.subscribe("change_fish", move |v| {
+                if let ActionValue::Digital(false) = v {
                     if let Ok(mut f) = c_fish.lock() {
                         (*f) += 1;
                         (*f) %= 2;
                         info!("Fish {:?} needs to be rendered.", f);
                     }
                 }
-            })
-        };
-        sm.reg_imm(b::C::A('c').into(), cb_change_fish.clone());
+            });
+        }
         // set up color flow
         let c_color = Arc::new(Mutex::new(na::Vector4::new(1f32, 1f32, 1f32, 1f32)));
         let cb_color = {
@@ -257,46 +272,25 @@ impl State {
         let cam = Arc::new(Mutex::new(geom::camera::Camera::Perspective(
             geom::camera::PerspectiveCamera::default(),
         )));
-        let cb_mover = {
-            let cam = cam.clone();
-            const MOVE_SPEED: f32 = 1.;
-            cb_arc!("Mover", v, s, l_t, c_t, {
-                let duration_held = *c_t - *l_t;
-                trace!("Mover run on value: {:?}", v);
-                let time_held = (duration_held.as_secs() as f64
-                    + (duration_held.subsec_nanos() as f64 / 1_000_000_000f64))
-                    as f32;
-                if let Ok(mut cam) = cam.lock() {
-                    if let V::B(b::V(b::C::A(c), b::State::DOWN)) = v {
-                        match c {
-                            'w' | 'a' | 's' | 'd' | 'q' | 'e' => {
-                                (*cam).trans_cam_space(
-                                    MOVE_SPEED
-                                        * time_held
-                                        * match c {
-                                            'w' => na::Vector3::new(0., 0., -1.),
-                                            'a' => na::Vector3::new(-1., 0., 0.),
-                                            's' => na::Vector3::new(0., 0., 1.),
-                                            'd' => na::Vector3::new(1., 0., 0.),
-                                            'q' => na::Vector3::new(0., 1., 0.),
-                                            'e' => na::Vector3::new(0., -1., 0.),
-                                            _ => na::Vector3::new(0., 0., 0.),
-                                        },
-                                );
-                                trace!("Camera at location: {:?}", cam.pos());
-                            }
-                            _ => (),
-                        }
-                    }
+        // The camera moves every step, in whichever direction is held.
+        let c_moving = Arc::new(Mutex::new(na::Vector3::zeros()));
+        {
+            let c_moving = c_moving.clone();
+            action_map.subscribe("move", move |v| {
+                if let (ActionValue::Axis2D(xy), Ok(mut moving)) = (v, c_moving.lock()) {
+                    moving.x = xy.x;
+                    moving.z = -xy.y;
                 }
-            })
-        };
-        sm.reg_per(b::C::A('w').into(), cb_mover.clone());
-        sm.reg_per(b::C::A('a').into(), cb_mover.clone());
-        sm.reg_per(b::C::A('s').into(), cb_mover.clone());
-        sm.reg_per(b::C::A('d').into(), cb_mover.clone());
-        sm.reg_per(b::C::A('q').into(), cb_mover.clone());
-        sm.reg_per(b::C::A('e').into(), cb_mover.clone());
+            });
+        }
+        {
+            let c_moving = c_moving.clone();
+            action_map.subscribe("rise", move |v| {
+                if let (ActionValue::Axis1D(y), Ok(mut moving)) = (v, c_moving.lock()) {
+                    moving.y = *y;
+                }
+            });
+        }
         let cb_rotor = {
             let cam = cam.clone();
             const ROT_SPEED: f32 = -1.0;
@@ -326,6 +320,11 @@ impl State {
             (*cam).trans_cam_space(na::Vector3::new(0., 0., 1.));
         }
         // sm.reg_per(b::C::A('n').into(), cb_rotor.clone());
+        let cb_actions = ActionMap::callback(&actions);
+        for c in action_map.categories() {
+            sm.reg_imm_cb(io::CB::new(c, Arc::downgrade(&cb_actions)));
+        }
+        drop(action_map);
         info!("Finished initial setup.");
         let sim_step = Duration::from_secs(1)
             .checked_div(120)
@@ -356,12 +355,14 @@ impl State {
                 gui::Manager::new()
             },
             c: cfg,
+            // bindings
+            actions,
+            actions_cb: cb_actions,
             // shutdown flow
             shutdown: cb_shutdown,
             current_action: c_act,
             // graphics settings
             should_use_depth: c_should_use_depth,
-            settings_cb: cb_settings,
             should_restart_renderer: c_restart_render,
             window_change_cb: cb_win_chg,
             // color flow
@@ -369,15 +370,25 @@ impl State {
             color_changer: cb_color,
             // fish selection
             fish: c_fish,
-            change_fish: cb_change_fish,
             // camera
             camera: cam,
-            camera_mover: cb_mover,
+            moving: c_moving,
+            last_step: Instant::now(),
             camera_roter: cb_rotor,
         }
     }
     fn step(&mut self) -> Action {
         // Every invocation
+        const MOVE_SPEED: f32 = 1.;
+        let now = Instant::now();
+        let time_step = (now - self.last_step).as_secs_f32();
+        self.last_step = now;
+        if let (Ok(moving), Ok(mut cam)) = (self.moving.lock(), self.camera.lock()) {
+            if *moving != na::Vector3::zeros() {
+                (*cam).trans_cam_space(MOVE_SPEED * time_step * *moving);
+                trace!("Camera at location: {:?}", cam.pos());
+            }
+        }
         // TODO update state (hot loops)
         // Every frame -- Vsync, and all the other fancy stuffs prohibit this from completely
         // working